@@ -0,0 +1,4 @@
+ALTER TABLE scopes
+    DROP COLUMN description,
+    DROP COLUMN created,
+    DROP COLUMN retired;
@@ -0,0 +1,7 @@
+ALTER TABLE scopes
+    ADD COLUMN description  text NOT NULL DEFAULT '',
+    ADD COLUMN created      timestamp NOT NULL DEFAULT now(),
+    ADD COLUMN retired      bool NOT NULL DEFAULT false;
+
+UPDATE scopes SET description = 'administer users, scopes and tokens' WHERE name = 'authadmin';
+
@@ -0,0 +1,3 @@
+DELETE FROM scopes WHERE name = 'authintrospect';
+ALTER TABLE tokens
+    DROP COLUMN issued;
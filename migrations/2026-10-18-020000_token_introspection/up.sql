@@ -0,0 +1,7 @@
+ALTER TABLE tokens
+    ADD COLUMN issued       timestamp NOT NULL DEFAULT now();
+
+INSERT INTO scopes(name, description) VALUES
+    ('authintrospect', 'introspect tokens issued to other users')
+    ;
+
@@ -0,0 +1,14 @@
+-- Refresh tokens are rotated on use. Every refresh token descended from
+-- the same login shares a family, so a replayed token can kill them all.
+CREATE TABLE refresh_tokens (
+    token       varchar(40) PRIMARY KEY,
+    family      varchar(40) NOT NULL,
+    access      varchar(40) NOT NULL, -- access token issued alongside this one
+    username    varchar(16) NOT NULL,
+    expiration  timestamp NOT NULL,
+    scopes      text[] NOT NULL,
+    used        bool NOT NULL
+);
+
+CREATE INDEX refresh_tokens_family ON refresh_tokens (family);
+
@@ -0,0 +1,7 @@
+-- JWT access tokens are checked without looking them up in the tokens
+-- table, so revoking one has to be recorded by its jti until it expires.
+CREATE TABLE revoked_tokens (
+    jti         varchar(40) PRIMARY KEY,
+    expiration  timestamp NOT NULL
+);
+
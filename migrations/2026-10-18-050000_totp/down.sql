@@ -0,0 +1,6 @@
+ALTER TABLE scopes
+    DROP COLUMN require_mfa;
+ALTER TABLE users
+    DROP COLUMN require_mfa;
+DROP TABLE recovery_codes;
+DROP TABLE totp;
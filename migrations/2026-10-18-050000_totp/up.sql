@@ -0,0 +1,21 @@
+-- TOTP second factor. The secret has to be kept as is to check codes against it.
+CREATE TABLE totp (
+    username    varchar(16) PRIMARY KEY,
+    secret      varchar(64) NOT NULL, -- base32
+    confirmed   bool NOT NULL,
+    last_step   bigint NOT NULL -- last time step used, so a code can't be replayed
+);
+
+-- One-time recovery codes, stored as sha256 hex digests.
+CREATE TABLE recovery_codes (
+    username    varchar(16) NOT NULL,
+    hash        varchar(64) NOT NULL,
+    PRIMARY KEY (username, hash)
+);
+
+ALTER TABLE users
+    ADD COLUMN require_mfa  bool NOT NULL DEFAULT false;
+
+ALTER TABLE scopes
+    ADD COLUMN require_mfa  bool NOT NULL DEFAULT false;
+
@@ -0,0 +1,15 @@
+-- Authentication and admin events, kept for the security team.
+CREATE TABLE audit_events (
+    id          bigserial PRIMARY KEY,
+    time        timestamp NOT NULL DEFAULT now(),
+    event       varchar(32) NOT NULL,
+    actor       varchar(16),  -- user that did it, if known
+    actor_token varchar(64),  -- fingerprint of the token it used
+    subject     varchar(64),  -- user, scope or token it was done to
+    client_ip   varchar(45),
+    detail      text NOT NULL
+);
+
+CREATE INDEX audit_events_time ON audit_events (time);
+CREATE INDEX audit_events_actor ON audit_events (actor, time);
+CREATE INDEX audit_events_subject ON audit_events (subject, time);
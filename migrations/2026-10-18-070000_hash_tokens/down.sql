@@ -0,0 +1,12 @@
+-- A digest can't be turned back into the secret, so every outstanding token is dropped.
+DELETE FROM tokens;
+DELETE FROM refresh_tokens;
+DELETE FROM revoked_tokens WHERE length(jti) > 40;
+
+ALTER TABLE tokens
+    ALTER COLUMN token TYPE varchar(40);
+ALTER TABLE refresh_tokens
+    ALTER COLUMN token TYPE varchar(40),
+    ALTER COLUMN access TYPE varchar(40);
+ALTER TABLE revoked_tokens
+    ALTER COLUMN jti TYPE varchar(40);
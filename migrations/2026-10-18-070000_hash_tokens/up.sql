@@ -0,0 +1,22 @@
+-- Tokens are stored under the sha256 hex digest of the secret instead of the
+-- secret itself. Existing opaque tokens keep working, since clients present
+-- the same secret and it hashes to the new name.
+--
+-- A JWT's jti is the secret too, so the revocation list is kept by digest as
+-- well, and JWTs issued before this migration stay revoked or revocable.
+-- Cached entries under the old names are never read again and go away after
+-- cache_lifetime.
+ALTER TABLE tokens
+    ALTER COLUMN token TYPE varchar(64);
+UPDATE tokens SET token = encode(sha256(token::bytea), 'hex');
+
+ALTER TABLE refresh_tokens
+    ALTER COLUMN token TYPE varchar(64),
+    ALTER COLUMN access TYPE varchar(64);
+UPDATE refresh_tokens SET
+    token = encode(sha256(token::bytea), 'hex'),
+    access = encode(sha256(access::bytea), 'hex');
+
+ALTER TABLE revoked_tokens
+    ALTER COLUMN jti TYPE varchar(64);
+UPDATE revoked_tokens SET jti = encode(sha256(jti::bytea), 'hex');
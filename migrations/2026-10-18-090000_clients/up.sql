@@ -0,0 +1,19 @@
+-- Applications that get tokens, for themselves with client_credentials or
+-- for their users with password. Secrets are argon2 hashes like user
+-- passwords. A client has more than one while its secret is being rotated.
+CREATE TABLE clients (
+    client_id     varchar(16) PRIMARY KEY,
+    secrets       text[] NOT NULL,
+    scopes        text[] NOT NULL,
+    grant_types   text[] NOT NULL,
+    redirect_uris text[] NOT NULL,
+    owner         varchar(16) NOT NULL, -- the user responsible for it
+    enabled       bool NOT NULL,
+    created       timestamp NOT NULL DEFAULT now()
+);
+
+-- The client that asked for a token, NULL when it was asked for directly.
+-- Refresh tokens keep it so the tokens they issue do too.
+ALTER TABLE tokens ADD COLUMN client_id varchar(16);
+CREATE INDEX tokens_client_id ON tokens (client_id);
+ALTER TABLE refresh_tokens ADD COLUMN client_id varchar(16);
@@ -0,0 +1,3 @@
+UPDATE scopes SET require_mfa = false WHERE name = 'authadmin';
+ALTER TABLE users
+    DROP COLUMN mfa_enroll;
@@ -0,0 +1,10 @@
+-- Set by an admin to let a user enroll a second factor with just its password.
+-- Cleared once the enrollment is confirmed.
+ALTER TABLE users
+    ADD COLUMN mfa_enroll  bool NOT NULL DEFAULT false;
+
+-- Admin tokens need a second factor. Admins that don't have one yet may enroll.
+UPDATE scopes SET require_mfa = true WHERE name = 'authadmin';
+UPDATE users SET mfa_enroll = true
+    WHERE 'authadmin' = ANY(scopes)
+    AND name NOT IN (SELECT username FROM totp WHERE confirmed);
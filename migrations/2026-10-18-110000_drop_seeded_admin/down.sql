@@ -0,0 +1,2 @@
+-- The seeded admin isn't put back.
+SELECT 1;
@@ -0,0 +1,8 @@
+-- The first migration made admin with the published password adminadmin.
+-- Drop it, and anything it is logged in with, if that is still its password.
+-- The server makes a new admin when it next starts.
+DELETE FROM tokens WHERE username = 'admin'
+    AND EXISTS (SELECT 1 FROM users WHERE name = 'admin' AND hash = '$argon2i$v=19$m=4096,t=3,p=1$cmFuZG9tc2FsdA$HXrbCSqkWTwH9W4z4JTyyJuuhEX/DNDs5tgTDfo+dHI');
+DELETE FROM refresh_tokens WHERE username = 'admin'
+    AND EXISTS (SELECT 1 FROM users WHERE name = 'admin' AND hash = '$argon2i$v=19$m=4096,t=3,p=1$cmFuZG9tc2FsdA$HXrbCSqkWTwH9W4z4JTyyJuuhEX/DNDs5tgTDfo+dHI');
+DELETE FROM users WHERE name = 'admin' AND hash = '$argon2i$v=19$m=4096,t=3,p=1$cmFuZG9tc2FsdA$HXrbCSqkWTwH9W4z4JTyyJuuhEX/DNDs5tgTDfo+dHI';
@@ -0,0 +1,8 @@
+DROP TABLE audit_events;
+DROP TABLE recovery_codes;
+DROP TABLE totp;
+DROP TABLE revoked_tokens;
+DROP TABLE refresh_tokens;
+DROP TABLE tokens;
+DROP TABLE scopes;
+DROP TABLE users;
@@ -0,0 +1,78 @@
+-- The same tables as the postgres migrations, for single node deployments.
+-- Scopes are json lists of names, and times are unix seconds. There is no
+-- seeded admin; the server makes the first one when it starts.
+
+CREATE TABLE users (
+    name        varchar(16) PRIMARY KEY,
+    hash        varchar(128) NOT NULL,
+    expiration  bigint NOT NULL,
+    enabled     bool NOT NULL,
+    scopes      text NOT NULL,
+    require_mfa bool NOT NULL DEFAULT false
+);
+
+CREATE TABLE scopes (
+    name        varchar(16) PRIMARY KEY,
+    description text NOT NULL DEFAULT '',
+    created     bigint NOT NULL DEFAULT (strftime('%s', 'now')),
+    retired     bool NOT NULL DEFAULT false,
+    require_mfa bool NOT NULL DEFAULT false
+);
+
+INSERT INTO scopes(name, description) VALUES
+    ('authadmin', 'administer users, scopes and tokens'),
+    ('authintrospect', 'introspect tokens issued to other users')
+    ;
+
+CREATE TABLE tokens (
+    token       varchar(64) PRIMARY KEY,
+    username    varchar(16) NOT NULL,
+    expiration  bigint NOT NULL,
+    scopes      text NOT NULL,
+    issued      bigint NOT NULL
+);
+
+CREATE TABLE refresh_tokens (
+    token       varchar(64) PRIMARY KEY,
+    family      varchar(40) NOT NULL,
+    access      varchar(64) NOT NULL,
+    username    varchar(16) NOT NULL,
+    expiration  bigint NOT NULL,
+    scopes      text NOT NULL,
+    used        bool NOT NULL
+);
+
+CREATE INDEX refresh_tokens_family ON refresh_tokens (family);
+
+CREATE TABLE revoked_tokens (
+    jti         varchar(64) PRIMARY KEY,
+    expiration  bigint NOT NULL
+);
+
+CREATE TABLE totp (
+    username    varchar(16) PRIMARY KEY,
+    secret      varchar(64) NOT NULL,
+    confirmed   bool NOT NULL,
+    last_step   bigint NOT NULL
+);
+
+CREATE TABLE recovery_codes (
+    username    varchar(16) NOT NULL,
+    hash        varchar(64) NOT NULL,
+    PRIMARY KEY (username, hash)
+);
+
+CREATE TABLE audit_events (
+    id          integer PRIMARY KEY, -- an alias for the rowid, so it counts up
+    time        bigint NOT NULL,
+    event       varchar(32) NOT NULL,
+    actor       varchar(16),
+    actor_token varchar(64),
+    subject     varchar(64),
+    client_ip   varchar(45),
+    detail      text NOT NULL
+);
+
+CREATE INDEX audit_events_time ON audit_events (time);
+CREATE INDEX audit_events_actor ON audit_events (actor, time);
+CREATE INDEX audit_events_subject ON audit_events (subject, time);
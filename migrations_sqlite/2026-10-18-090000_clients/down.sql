@@ -0,0 +1,4 @@
+ALTER TABLE refresh_tokens DROP COLUMN client_id;
+DROP INDEX tokens_client_id;
+ALTER TABLE tokens DROP COLUMN client_id;
+DROP TABLE clients;
@@ -0,0 +1,15 @@
+-- The same as the postgres clients migration. Lists are json.
+CREATE TABLE clients (
+    client_id     varchar(16) PRIMARY KEY,
+    secrets       text NOT NULL,
+    scopes        text NOT NULL,
+    grant_types   text NOT NULL,
+    redirect_uris text NOT NULL,
+    owner         varchar(16) NOT NULL,
+    enabled       bool NOT NULL,
+    created       bigint NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+ALTER TABLE tokens ADD COLUMN client_id varchar(16);
+CREATE INDEX tokens_client_id ON tokens (client_id);
+ALTER TABLE refresh_tokens ADD COLUMN client_id varchar(16);
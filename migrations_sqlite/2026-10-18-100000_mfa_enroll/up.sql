@@ -0,0 +1,8 @@
+-- The same as the postgres mfa_enroll migration.
+ALTER TABLE users
+    ADD COLUMN mfa_enroll  bool NOT NULL DEFAULT false;
+
+UPDATE scopes SET require_mfa = true WHERE name = 'authadmin';
+UPDATE users SET mfa_enroll = true
+    WHERE scopes LIKE '%"authadmin"%'
+    AND name NOT IN (SELECT username FROM totp WHERE confirmed);
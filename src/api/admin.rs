@@ -1,14 +1,17 @@
 
-use rand::{Rng, rngs::StdRng};
 use std::collections::HashSet;
-use std::sync::Mutex;
-use std::time::{SystemTime, Duration};
-use rocket::serde::{Deserialize, json::Json};
+use std::time::{SystemTime, Duration, UNIX_EPOCH};
+use rand::Rng;
+use rocket::http::uri::Absolute;
+use rocket::serde::{Serialize, Deserialize, json::Json};
+use tracing::instrument;
 
-use crate::cache;
+use crate::{cache, manage, throttle, Error};
+use crate::cache::CacheStats;
+use crate::password::HashStats;
 use crate::rocktypes::{BearerToken, CachedDb};
-use crate::model::{user, scopes, token};
-use crate::json::{StrRes, JsonRes, json_res, ERR_FAILED, ERR_BADSCOPES};
+use crate::model::{user, client, scopes, token, refresh, revoked, totp, audit, name_valid};
+use crate::json::{StrRes, JsonRes, json_res, ERR_BADSCOPES, ERR_NOTFOUND, ERR_INVALID};
 
 #[derive(Deserialize)]
 #[serde(crate = "rocket::serde")]
@@ -18,13 +21,10 @@ pub struct CreateReq<'r> {
     pub life: u64,
     //pub enable: '&r str,
     scopes: HashSet<&'r str>,
-}
-
-fn hash_password(rng: &Mutex<StdRng>, secret: &str) -> String {
-    let hash_config = argon2::Config::default(); // XXX config?
-    let salt: [u8; 20] = rng.lock().unwrap().gen(); // safe
-    let hash = argon2::hash_encoded(secret.as_bytes(), &salt, &hash_config).unwrap();
-    hash
+    #[serde(default)]
+    pub require_mfa: bool,
+    #[serde(default)]
+    pub mfa_enroll: bool,
 }
 
 fn scopes_valid(req_scopes: &HashSet<&str>, active_scopes: &Vec<String>) -> bool {
@@ -37,30 +37,24 @@ fn scopes_valid(req_scopes: &HashSet<&str>, active_scopes: &Vec<String>) -> bool
     return true;
 }
 
+fn scope_list(scopes: &HashSet<&str>) -> Vec<String> {
+    scopes.iter().copied().map(|s| s.to_owned()).collect()
+}
+
 // XXX make some of the fields optional?
 
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
 async fn create_user_sr(cdb: CachedDb<'_>, bearer: BearerToken, req: Json<CreateReq<'_>>) -> StrRes<&'static str> {
-    bearer.require_scope(&cdb, "authadmin").await?;
-    let active_scopes: Vec<String> = scopes::get_scopes(&cdb).await.or(Err(ERR_FAILED))?;
-    if !scopes_valid(&req.scopes, &active_scopes) {
-        return Err(ERR_BADSCOPES);
-    }
-
-    let expire = SystemTime::now() + Duration::from_secs(req.life); // XXX cant this fail?
-    let hash = hash_password(&cdb.serv.rng, req.secret);
-    let granted_scopes = req.scopes.iter().copied().map(|s| s.to_owned()).collect();
-    let u = user::User {
-        name: req.name.to_owned(),
-        hash: hash,
-        expiration: expire,
-        enabled: true,
-        scopes: granted_scopes,
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    let nu = manage::NewUser {
+        name: req.name,
+        secret: req.secret,
+        life: req.life,
+        scopes: scope_list(&req.scopes),
+        require_mfa: req.require_mfa,
+        mfa_enroll: req.mfa_enroll,
     };
-
-    // XXX do we have to check if the user already exists?
-    // I expect db insert will fail if it already exists
-    // XXX translate errors better.. need to know why it failed...
-    user::put_user(&cdb, u).await.or(Err(ERR_FAILED))?;
+    manage::create_user(&cdb, Some(&actor), nu).await?;
     Ok("created")
 }
 
@@ -69,9 +63,10 @@ pub async fn create_user(cdb: CachedDb<'_>, bearer: BearerToken, req: Json<Creat
     json_res(create_user_sr(cdb, bearer, req).await)
 }
 
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
 async fn create_scope_sr(cdb: CachedDb<'_>, bearer: BearerToken, req: Json<String>) -> StrRes<&'static str> {
-    bearer.require_scope(&cdb, "authadmin").await?;
-    scopes::put_scope(&cdb, &req.to_owned()).await.or(Err(ERR_FAILED))?;
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    manage::create_scope(&cdb, Some(&actor), &req).await?;
     Ok("created")
 }
 
@@ -80,10 +75,15 @@ pub async fn create_scope(cdb: CachedDb<'_>, bearer: BearerToken, req: Json<Stri
     json_res(create_scope_sr(cdb, bearer, req).await)
 }
 
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
 async fn clean_sr(cdb: CachedDb<'_>, bearer: BearerToken) -> StrRes<&'static str> {
-    bearer.require_scope(&cdb, "authadmin").await?;
-    let _ = cache::clean(&cdb).await;
-    let _ = token::clean(&cdb).await;
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    let cached = cache::clean(&cdb).await.unwrap_or(0);
+    let toks = token::clean(&cdb).await.unwrap_or(0);
+    let refreshes = refresh::clean(&cdb).await.unwrap_or(0);
+    let revokes = revoked::clean(&cdb).await.unwrap_or(0);
+    let detail = format!("tokens={} refresh={} revoked={} cache={}", toks, refreshes, revokes, cached);
+    audit::record(&cdb, "clean", Some(&actor), None, detail).await;
     Ok("cleaned")
 }
 
@@ -92,3 +92,636 @@ pub async fn clean(cdb: CachedDb<'_>, bearer: BearerToken) -> JsonRes<&'static s
     json_res(clean_sr(cdb, bearer).await)
 }
 
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn revoke_token_sr(cdb: CachedDb<'_>, bearer: BearerToken, req: Json<String>) -> StrRes<&'static str> {
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    manage::revoke_token(&cdb, Some(&actor), req.into_inner()).await?;
+    Ok("revoked")
+}
+
+#[post("/token/revoke", format="json", data="<req>")]
+pub async fn revoke_token(cdb: CachedDb<'_>, bearer: BearerToken, req: Json<String>) -> JsonRes<&'static str> {
+    json_res(revoke_token_sr(cdb, bearer, req).await)
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UserResp {
+    name: String,
+    life: u64,
+    enabled: bool,
+    scopes: Vec<String>,
+    require_mfa: bool,
+    mfa_enroll: bool,
+}
+
+impl From<user::User> for UserResp {
+    fn from(u: user::User) -> Self {
+        UserResp {
+            life: u.seconds_left(),
+            name: u.name,
+            enabled: u.enabled,
+            scopes: u.scopes,
+            require_mfa: u.require_mfa,
+            mfa_enroll: u.mfa_enroll,
+        }
+    }
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn get_user_sr(cdb: CachedDb<'_>, bearer: BearerToken, name: &str) -> StrRes<UserResp> {
+    bearer.require_user_or_scope(&cdb, name, "authadmin").await?;
+    let u = user::get_user(&cdb, name.to_owned()).await?;
+    Ok(UserResp::from(u))
+}
+
+#[get("/user/<name>", format="json")]
+pub async fn get_user(cdb: CachedDb<'_>, bearer: BearerToken, name: &str) -> JsonRes<UserResp> {
+    json_res(get_user_sr(cdb, bearer, name).await)
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn list_users_sr(cdb: CachedDb<'_>, bearer: BearerToken, offset: Option<i64>, limit: Option<i64>) -> StrRes<Vec<UserResp>> {
+    bearer.require_scope(&cdb, "authadmin").await?;
+    let offset = offset.unwrap_or(0).max(0);
+    let limit = limit.unwrap_or(100).clamp(1, 1000);
+    let us = user::list_users(&cdb, offset, limit).await?;
+    Ok(us.into_iter().map(UserResp::from).collect())
+}
+
+#[get("/users?<offset>&<limit>", format="json")]
+pub async fn list_users(cdb: CachedDb<'_>, bearer: BearerToken, offset: Option<i64>, limit: Option<i64>) -> JsonRes<Vec<UserResp>> {
+    json_res(list_users_sr(cdb, bearer, offset, limit).await)
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UpdateReq<'r> {
+    #[serde(borrow)]
+    pub secret: Option<&'r str>,
+    pub life: Option<u64>,
+    pub enabled: Option<bool>,
+    #[serde(borrow)]
+    scopes: Option<HashSet<&'r str>>,
+    pub require_mfa: Option<bool>,
+    pub mfa_enroll: Option<bool>,
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn update_user_sr(cdb: CachedDb<'_>, bearer: BearerToken, name: &str, req: Json<UpdateReq<'_>>) -> StrRes<&'static str> {
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    let ch = manage::UserChanges {
+        secret: req.secret,
+        life: req.life,
+        enabled: req.enabled,
+        scopes: req.scopes.as_ref().map(scope_list),
+        require_mfa: req.require_mfa,
+        mfa_enroll: req.mfa_enroll,
+    };
+    manage::update_user(&cdb, Some(&actor), name, ch).await?;
+    Ok("updated")
+}
+
+#[patch("/user/<name>", format="json", data="<req>")]
+pub async fn update_user(cdb: CachedDb<'_>, bearer: BearerToken, name: &str, req: Json<UpdateReq<'_>>) -> JsonRes<&'static str> {
+    json_res(update_user_sr(cdb, bearer, name, req).await)
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn delete_user_sr(cdb: CachedDb<'_>, bearer: BearerToken, name: &str) -> StrRes<&'static str> {
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    manage::delete_user(&cdb, Some(&actor), name).await?;
+    Ok("deleted")
+}
+
+#[delete("/user/<name>", format="json")]
+pub async fn delete_user(cdb: CachedDb<'_>, bearer: BearerToken, name: &str) -> JsonRes<&'static str> {
+    json_res(delete_user_sr(cdb, bearer, name).await)
+}
+
+/*
+ * Remove a user's second factor, e.g. when it lost its authenticator and its recovery codes.
+ * It may enroll a new one with just its password, and until then gets no scopes.
+ */
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn reset_totp_sr(cdb: CachedDb<'_>, bearer: BearerToken, name: &str) -> StrRes<&'static str> {
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    let cnt = totp::del_totp(&cdb, name.to_owned()).await?;
+    if cnt == 0 {
+        return Err(ERR_NOTFOUND);
+    }
+    let mut u = user::get_user(&cdb, name.to_owned()).await?;
+    u.mfa_enroll = true;
+    user::update_user(&cdb, u).await?;
+    audit::record(&cdb, "totp_reset", Some(&actor), Some(name), String::new()).await;
+    Ok("deleted")
+}
+
+#[delete("/user/<name>/totp", format="json")]
+pub async fn reset_totp(cdb: CachedDb<'_>, bearer: BearerToken, name: &str) -> JsonRes<&'static str> {
+    json_res(reset_totp_sr(cdb, bearer, name).await)
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ScopeResp {
+    name: String,
+    description: String,
+    created: u64,
+    retired: bool,
+    require_mfa: bool,
+}
+
+impl From<scopes::Scope> for ScopeResp {
+    fn from(sc: scopes::Scope) -> Self {
+        ScopeResp {
+            created: sc.created.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            name: sc.name,
+            description: sc.description,
+            retired: sc.retired,
+            require_mfa: sc.require_mfa,
+        }
+    }
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn list_scopes_sr(cdb: CachedDb<'_>, bearer: BearerToken) -> StrRes<Vec<ScopeResp>> {
+    bearer.require_scope(&cdb, "authadmin").await?;
+    let scs = scopes::list_scopes(&cdb).await?;
+    Ok(scs.into_iter().map(ScopeResp::from).collect())
+}
+
+#[get("/scopes", format="json")]
+pub async fn list_scopes(cdb: CachedDb<'_>, bearer: BearerToken) -> JsonRes<Vec<ScopeResp>> {
+    json_res(list_scopes_sr(cdb, bearer).await)
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UpdateScopeReq {
+    pub description: Option<String>,
+    pub retired: Option<bool>,
+    pub require_mfa: Option<bool>,
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn update_scope_sr(cdb: CachedDb<'_>, bearer: BearerToken, name: &str, req: Json<UpdateScopeReq>) -> StrRes<&'static str> {
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    let req = req.into_inner();
+    let changes = scopes::ScopeChanges {
+        description: req.description,
+        retired: req.retired,
+        require_mfa: req.require_mfa,
+    };
+    manage::update_scope(&cdb, Some(&actor), name, changes).await?;
+    Ok("updated")
+}
+
+#[patch("/scope/<name>", format="json", data="<req>")]
+pub async fn update_scope(cdb: CachedDb<'_>, bearer: BearerToken, name: &str, req: Json<UpdateScopeReq>) -> JsonRes<&'static str> {
+    json_res(update_scope_sr(cdb, bearer, name, req).await)
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn delete_scope_sr(cdb: CachedDb<'_>, bearer: BearerToken, name: &str) -> StrRes<&'static str> {
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    manage::delete_scope(&cdb, Some(&actor), name).await?;
+    Ok("deleted")
+}
+
+#[delete("/scope/<name>", format="json")]
+pub async fn delete_scope(cdb: CachedDb<'_>, bearer: BearerToken, name: &str) -> JsonRes<&'static str> {
+    json_res(delete_scope_sr(cdb, bearer, name).await)
+}
+
+// A client keeps at most this many secrets, so an old one can't linger forever.
+const MAX_SECRETS: usize = 2;
+
+fn grants_valid(grants: &HashSet<&str>) -> bool {
+    grants.iter().all(|g| client::GRANT_TYPES.contains(g))
+}
+
+fn redirects_valid(uris: &[String]) -> bool {
+    uris.iter().all(|u| match Absolute::parse(u) {
+        Ok(abs) => abs.scheme() == "https" || abs.scheme() == "http",
+        Err(_) => false,
+    })
+}
+
+// Client secrets are always made up here, and only shown once.
+fn gen_secret(cdb: &CachedDb<'_>) -> String {
+    let bytes: [u8; 32] = cdb.serv.rng.lock().unwrap().gen(); // safe
+    hex::encode(bytes)
+}
+
+// Revoke everything a client asked for, e.g. when it is disabled or its secret leaked.
+async fn revoke_client(cdb: &CachedDb<'_>, client_id: &str) -> StrRes<()> {
+    token::del_client_tokens(cdb, client_id.to_owned()).await?;
+    refresh::del_client_refresh(cdb, client_id.to_owned()).await?;
+    Ok(())
+}
+
+// The owner must be a user, so someone answers for the client.
+async fn owner_valid(cdb: &CachedDb<'_>, owner: &str) -> StrRes<()> {
+    match user::get_user(cdb, owner.to_owned()).await {
+        Ok(_) => Ok(()),
+        Err(Error::NotFound) => Err(ERR_INVALID),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CreateClientReq<'r> {
+    pub client_id: &'r str,
+    #[serde(borrow)]
+    scopes: HashSet<&'r str>,
+    #[serde(borrow)]
+    grant_types: HashSet<&'r str>,
+    #[serde(default)]
+    redirect_uris: Vec<String>,
+    pub owner: Option<&'r str>, // the admin creating it if not given
+}
+
+// The only time a client secret is ever sent.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SecretResp {
+    client_id: String,
+    client_secret: String,
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn create_client_sr(cdb: CachedDb<'_>, bearer: BearerToken, req: Json<CreateClientReq<'_>>) -> StrRes<SecretResp> {
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    if !name_valid(req.client_id) || !grants_valid(&req.grant_types) || !redirects_valid(&req.redirect_uris) {
+        return Err(ERR_INVALID);
+    }
+    let active_scopes: Vec<String> = scopes::get_scopes(&cdb).await?;
+    if !scopes_valid(&req.scopes, &active_scopes) {
+        return Err(ERR_BADSCOPES);
+    }
+    let owner = req.owner.unwrap_or(&actor.username);
+    owner_valid(&cdb, owner).await?;
+
+    let secret = gen_secret(&cdb);
+    let hash = cdb.serv.password.hash(&cdb.serv.rng, &secret).await?;
+    let c = client::Client {
+        client_id: req.client_id.to_owned(),
+        secrets: vec![hash],
+        scopes: req.scopes.iter().copied().map(|s| s.to_owned()).collect(),
+        grant_types: req.grant_types.iter().copied().map(|s| s.to_owned()).collect(),
+        redirect_uris: req.redirect_uris.clone(),
+        owner: owner.to_owned(),
+        enabled: true,
+        created: SystemTime::now(),
+    };
+    let detail = format!("scopes={} grant_types={} owner={}", c.scopes.join(","), c.grant_types.join(","), c.owner);
+
+    // the db insert fails with a conflict if the client already exists
+    client::put_client(&cdb, c).await?;
+    audit::record(&cdb, "client_created", Some(&actor), Some(req.client_id), detail).await;
+    Ok(SecretResp{ client_id: req.client_id.to_owned(), client_secret: secret })
+}
+
+#[post("/client", format="json", data="<req>")]
+pub async fn create_client(cdb: CachedDb<'_>, bearer: BearerToken, req: Json<CreateClientReq<'_>>) -> JsonRes<SecretResp> {
+    json_res(create_client_sr(cdb, bearer, req).await)
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ClientResp {
+    client_id: String,
+    secrets: usize,
+    scopes: Vec<String>,
+    grant_types: Vec<String>,
+    redirect_uris: Vec<String>,
+    owner: String,
+    enabled: bool,
+    created: u64,
+}
+
+impl From<client::Client> for ClientResp {
+    fn from(c: client::Client) -> Self {
+        ClientResp {
+            created: c.created.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            secrets: c.secrets.len(),
+            client_id: c.client_id,
+            scopes: c.scopes,
+            grant_types: c.grant_types,
+            redirect_uris: c.redirect_uris,
+            owner: c.owner,
+            enabled: c.enabled,
+        }
+    }
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn get_client_sr(cdb: CachedDb<'_>, bearer: BearerToken, id: &str) -> StrRes<ClientResp> {
+    bearer.require_scope(&cdb, "authadmin").await?;
+    let c = client::get_client(&cdb, id.to_owned()).await?;
+    Ok(ClientResp::from(c))
+}
+
+#[get("/client/<id>", format="json")]
+pub async fn get_client(cdb: CachedDb<'_>, bearer: BearerToken, id: &str) -> JsonRes<ClientResp> {
+    json_res(get_client_sr(cdb, bearer, id).await)
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn list_clients_sr(cdb: CachedDb<'_>, bearer: BearerToken, offset: Option<i64>, limit: Option<i64>) -> StrRes<Vec<ClientResp>> {
+    bearer.require_scope(&cdb, "authadmin").await?;
+    let offset = offset.unwrap_or(0).max(0);
+    let limit = limit.unwrap_or(100).clamp(1, 1000);
+    let cs = client::list_clients(&cdb, offset, limit).await?;
+    Ok(cs.into_iter().map(ClientResp::from).collect())
+}
+
+#[get("/clients?<offset>&<limit>", format="json")]
+pub async fn list_clients(cdb: CachedDb<'_>, bearer: BearerToken, offset: Option<i64>, limit: Option<i64>) -> JsonRes<Vec<ClientResp>> {
+    json_res(list_clients_sr(cdb, bearer, offset, limit).await)
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UpdateClientReq<'r> {
+    #[serde(borrow)]
+    scopes: Option<HashSet<&'r str>>,
+    #[serde(borrow)]
+    grant_types: Option<HashSet<&'r str>>,
+    redirect_uris: Option<Vec<String>>,
+    pub owner: Option<&'r str>,
+    pub enabled: Option<bool>,
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn update_client_sr(cdb: CachedDb<'_>, bearer: BearerToken, id: &str, req: Json<UpdateClientReq<'_>>) -> StrRes<&'static str> {
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    let mut c = client::get_client(&cdb, id.to_owned()).await?;
+    let mut changes: Vec<String> = Vec::new();
+
+    if let Some(want) = &req.scopes {
+        let active_scopes: Vec<String> = scopes::get_scopes(&cdb).await?;
+        if !scopes_valid(want, &active_scopes) {
+            return Err(ERR_BADSCOPES);
+        }
+        c.scopes = want.iter().copied().map(|s| s.to_owned()).collect();
+        changes.push(format!("scopes={}", c.scopes.join(",")));
+    }
+    if let Some(grants) = &req.grant_types {
+        if !grants_valid(grants) {
+            return Err(ERR_INVALID);
+        }
+        c.grant_types = grants.iter().copied().map(|s| s.to_owned()).collect();
+        changes.push(format!("grant_types={}", c.grant_types.join(",")));
+    }
+    if let Some(uris) = &req.redirect_uris {
+        if !redirects_valid(uris) {
+            return Err(ERR_INVALID);
+        }
+        c.redirect_uris = uris.clone();
+        changes.push("redirect_uris".to_owned());
+    }
+    if let Some(owner) = req.owner {
+        owner_valid(&cdb, owner).await?;
+        c.owner = owner.to_owned();
+        changes.push(format!("owner={}", owner));
+    }
+    if let Some(enabled) = req.enabled {
+        c.enabled = enabled;
+        changes.push(format!("enabled={}", enabled));
+    }
+
+    // like a disabled user, a disabled client shouldn't keep using tokens it already has
+    let revoke = !c.enabled;
+    client::update_client(&cdb, c).await?;
+    if revoke {
+        revoke_client(&cdb, id).await?;
+    }
+    audit::record(&cdb, "client_updated", Some(&actor), Some(id), changes.join(" ")).await;
+    Ok("updated")
+}
+
+#[patch("/client/<id>", format="json", data="<req>")]
+pub async fn update_client(cdb: CachedDb<'_>, bearer: BearerToken, id: &str, req: Json<UpdateClientReq<'_>>) -> JsonRes<&'static str> {
+    json_res(update_client_sr(cdb, bearer, id, req).await)
+}
+
+// Without replace the old secret keeps working until the next rotation,
+// so the client can be moved over to the new one first.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RotateReq {
+    #[serde(default)]
+    replace: bool,
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn rotate_secret_sr(cdb: CachedDb<'_>, bearer: BearerToken, id: &str, req: Json<RotateReq>) -> StrRes<SecretResp> {
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    let mut c = client::get_client(&cdb, id.to_owned()).await?;
+
+    let secret = gen_secret(&cdb);
+    let hash = cdb.serv.password.hash(&cdb.serv.rng, &secret).await?;
+    if req.replace {
+        c.secrets.clear();
+    }
+    c.secrets.push(hash);
+    let old = c.secrets.len().saturating_sub(MAX_SECRETS);
+    c.secrets.drain(..old);
+
+    client::update_client(&cdb, c).await?;
+    // a replaced secret may have leaked, so nothing it got should still work
+    if req.replace {
+        revoke_client(&cdb, id).await?;
+    }
+    audit::record(&cdb, "client_secret_rotated", Some(&actor), Some(id), format!("replace={}", req.replace)).await;
+    Ok(SecretResp{ client_id: id.to_owned(), client_secret: secret })
+}
+
+#[post("/client/<id>/secret", format="json", data="<req>")]
+pub async fn rotate_secret(cdb: CachedDb<'_>, bearer: BearerToken, id: &str, req: Json<RotateReq>) -> JsonRes<SecretResp> {
+    json_res(rotate_secret_sr(cdb, bearer, id, req).await)
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn delete_client_sr(cdb: CachedDb<'_>, bearer: BearerToken, id: &str) -> StrRes<&'static str> {
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    let cnt = client::del_client(&cdb, id.to_owned()).await?;
+    revoke_client(&cdb, id).await?;
+    if cnt == 0 {
+        return Err(ERR_NOTFOUND);
+    }
+    audit::record(&cdb, "client_deleted", Some(&actor), Some(id), String::new()).await;
+    Ok("deleted")
+}
+
+#[delete("/client/<id>", format="json")]
+pub async fn delete_client(cdb: CachedDb<'_>, bearer: BearerToken, id: &str) -> JsonRes<&'static str> {
+    json_res(delete_client_sr(cdb, bearer, id).await)
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn list_lockouts_sr(cdb: CachedDb<'_>, bearer: BearerToken) -> StrRes<Vec<throttle::Lockout>> {
+    bearer.require_scope(&cdb, "authadmin").await?;
+    let x = throttle::list_lockouts(&cdb).await?;
+    Ok(x)
+}
+
+#[get("/lockouts", format="json")]
+pub async fn list_lockouts(cdb: CachedDb<'_>, bearer: BearerToken) -> JsonRes<Vec<throttle::Lockout>> {
+    json_res(list_lockouts_sr(cdb, bearer).await)
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn get_lockout_sr(cdb: CachedDb<'_>, bearer: BearerToken, kind: &str, name: &str) -> StrRes<throttle::Lockout> {
+    bearer.require_scope(&cdb, "authadmin").await?;
+    let subject = throttle::Subject::parse(kind, name).ok_or(ERR_INVALID)?;
+    let x = throttle::get_lockout(&cdb, &subject).await?;
+    Ok(x)
+}
+
+// kind is "user", "client" or "ip"
+#[get("/lockout/<kind>/<name>", format="json")]
+pub async fn get_lockout(cdb: CachedDb<'_>, bearer: BearerToken, kind: &str, name: &str) -> JsonRes<throttle::Lockout> {
+    json_res(get_lockout_sr(cdb, bearer, kind, name).await)
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn clear_lockout_sr(cdb: CachedDb<'_>, bearer: BearerToken, kind: &str, name: &str) -> StrRes<&'static str> {
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    let subject = throttle::Subject::parse(kind, name).ok_or(ERR_INVALID)?;
+    let cnt = throttle::clear(&cdb, &subject).await?;
+    if cnt == 0 {
+        return Err(ERR_NOTFOUND);
+    }
+    audit::record(&cdb, "lockout_cleared", Some(&actor), Some(name), kind.to_owned()).await;
+    Ok("cleared")
+}
+
+#[delete("/lockout/<kind>/<name>", format="json")]
+pub async fn clear_lockout(cdb: CachedDb<'_>, bearer: BearerToken, kind: &str, name: &str) -> JsonRes<&'static str> {
+    json_res(clear_lockout_sr(cdb, bearer, kind, name).await)
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct EventResp {
+    id: i64,
+    time: u64,
+    event: String,
+    actor: Option<String>,
+    actor_token: Option<String>,
+    subject: Option<String>,
+    client_ip: Option<String>,
+    detail: String,
+}
+
+impl From<audit::Event> for EventResp {
+    fn from(ev: audit::Event) -> Self {
+        EventResp {
+            time: ev.time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            id: ev.id,
+            event: ev.event,
+            actor: ev.actor,
+            actor_token: ev.actor_token,
+            subject: ev.subject,
+            client_ip: ev.client_ip,
+            detail: ev.detail,
+        }
+    }
+}
+
+// since and until are unix times
+#[derive(FromForm)]
+pub struct AuditQuery {
+    user: Option<String>,
+    event: Option<String>,
+    since: Option<u64>,
+    until: Option<u64>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+}
+
+// Newest events first.
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn list_audit_sr(cdb: CachedDb<'_>, bearer: BearerToken, q: AuditQuery) -> StrRes<Vec<EventResp>> {
+    bearer.require_scope(&cdb, "authadmin").await?;
+    let filter = audit::Filter {
+        user: q.user,
+        event: q.event,
+        since: q.since.map(|t| UNIX_EPOCH + Duration::from_secs(t)),
+        until: q.until.map(|t| UNIX_EPOCH + Duration::from_secs(t)),
+    };
+    let offset = q.offset.unwrap_or(0).max(0);
+    let limit = q.limit.unwrap_or(100).clamp(1, 1000);
+    let evs = audit::list_events(&cdb, filter, offset, limit).await?;
+    Ok(evs.into_iter().map(EventResp::from).collect())
+}
+
+#[get("/audit?<q..>", format="json")]
+pub async fn list_audit(cdb: CachedDb<'_>, bearer: BearerToken, q: AuditQuery) -> JsonRes<Vec<EventResp>> {
+    json_res(list_audit_sr(cdb, bearer, q).await)
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn hash_stats_sr(cdb: CachedDb<'_>, bearer: BearerToken) -> StrRes<HashStats> {
+    bearer.require_scope(&cdb, "authadmin").await?;
+    Ok(cdb.serv.password.stats())
+}
+
+#[get("/hashstats", format="json")]
+pub async fn hash_stats(cdb: CachedDb<'_>, bearer: BearerToken) -> JsonRes<HashStats> {
+    json_res(hash_stats_sr(cdb, bearer).await)
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn cache_stats_sr(cdb: CachedDb<'_>, bearer: BearerToken) -> StrRes<CacheStats> {
+    bearer.require_scope(&cdb, "authadmin").await?;
+    let x = cache::stats(&cdb).await?;
+    Ok(x)
+}
+
+#[get("/cache", format="json")]
+pub async fn cache_stats(cdb: CachedDb<'_>, bearer: BearerToken) -> JsonRes<CacheStats> {
+    json_res(cache_stats_sr(cdb, bearer).await)
+}
+
+// Exactly one of the fields should be given. token is the token as presented.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FlushReq {
+    user: Option<String>,
+    token: Option<String>,
+    #[serde(default)]
+    scopes: bool,
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn flush_cache_sr(cdb: CachedDb<'_>, bearer: BearerToken, req: Json<FlushReq>) -> StrRes<&'static str> {
+    let actor = bearer.require_scope(&cdb, "authadmin").await?;
+    let req = req.into_inner();
+    let detail = match (req.user, req.token, req.scopes) {
+        (Some(name), None, false) => {
+            user::evict(&cdb, &name).await?;
+            format!("user={}", name)
+        }
+        (None, Some(tok), false) => {
+            token::evict(&cdb, &token::token_id(&cdb, tok)).await?;
+            "token".to_owned()
+        }
+        (None, None, true) => {
+            scopes::evict(&cdb).await?;
+            "scopes".to_owned()
+        }
+        _ => return Err(ERR_INVALID),
+    };
+    audit::record(&cdb, "cache_flushed", Some(&actor), None, detail).await;
+    Ok("flushed")
+}
+
+#[post("/cache/flush", format="json", data="<req>")]
+pub async fn flush_cache(cdb: CachedDb<'_>, bearer: BearerToken, req: Json<FlushReq>) -> JsonRes<&'static str> {
+    json_res(flush_cache_sr(cdb, bearer, req).await)
+}
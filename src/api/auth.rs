@@ -1,14 +1,22 @@
 
 use std::collections::HashSet;
+use std::fmt;
 use std::time::{Duration, SystemTime};
 use std::sync::Mutex;
 use rocket::serde::{Serialize, Deserialize, json::Json};
 use rand::{Rng, rngs::StdRng};
 use hex::ToHex;
+use ring::digest;
+use tracing::instrument;
 
+use crate::{otp, throttle};
+use crate::password::Check;
+use crate::logging::Redacted;
+use crate::Error;
 use crate::rocktypes::{BearerToken, CachedDb};
-use crate::model::{user, scopes, token};
-use crate::json::{StatusErr, StrRes, JsonRes, json_res, ERR_FAILED, ERR_BADAUTH, ERR_BADSCOPES};
+use crate::model::{user, client, scopes, token, refresh, totp, audit, hash_secret};
+use crate::json::{StrRes, JsonRes, json_res, notfound_badauth,
+    ERR_BADAUTH, ERR_BADSCOPES, ERR_BADGRANT, ERR_EXPIRED, ERR_MFA_REQUIRED, ERR_CONFLICT, ERR_FAILED, ERR_LOCKED, ERR_DISABLED, ERR_FORBIDDEN};
 
 #[derive(Deserialize)]
 #[serde(crate = "rocket::serde")]
@@ -16,26 +24,42 @@ pub struct AuthReq<'r> {
     name: &'r str,
     secret: &'r str,
     scopes: HashSet<&'r str>,
+    otp: Option<&'r str>,
+}
+
+impl fmt::Debug for AuthReq<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthReq")
+            .field("name", &self.name)
+            .field("secret", &Redacted)
+            .field("scopes", &self.scopes)
+            .field("otp", &self.otp.map(|_| Redacted))
+            .finish()
+    }
 }
 
 #[derive(Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct AuthResp {
     token: String,
+    refresh: String,
     scopes: Vec<String>,
     life: u64,
 }
 
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RefreshReq<'r> {
+    refresh: &'r str,
+    scopes: Option<HashSet<&'r str>>,
+}
+
 fn gen_token(rng: &Mutex<StdRng>) -> String {
     // unwrap() here can only panic if another thread already paniced while holding the mutex
     let bytes: [u8; 20] = rng.lock().unwrap().gen(); // safe
     bytes.encode_hex()
 }
 
-fn password_valid(hash: &str, pw: &str) -> bool {
-    argon2::verify_encoded(hash, pw.as_bytes()).unwrap_or(false)
-}
-
 fn scopes_valid(req_scopes: &HashSet<&'_ str>, have_scopes: &Vec<String>, active_scopes: &Vec<String>) -> bool {
     // fail if any requested scope is no longer active or doesnt belong to the user
     for want in req_scopes.iter() {
@@ -47,48 +71,324 @@ fn scopes_valid(req_scopes: &HashSet<&'_ str>, have_scopes: &Vec<String>, active
     return true;
 }
 
-fn catch_notfound(msg: String) -> StatusErr {
-    if msg == "NotFound" {
-        ERR_BADAUTH
-    } else {
-        println!("error {}", msg);
-        ERR_FAILED
-    }
+// Recovery codes are stored hashed, salted with the user's name.
+fn hash_code(name: &str, code: &str) -> String {
+    let d = digest::digest(&digest::SHA256, format!("{}:{}", name, code.trim()).as_bytes());
+    d.as_ref().encode_hex()
 }
 
-pub async fn auth_sr(cdb: CachedDb<'_>, req: Json<AuthReq<'_>>) -> StrRes<AuthResp> {
-    // XXX to owned
-    let u = user::get_user(&cdb, req.name.to_owned()).await.map_err(catch_notfound)?;
-    let active_scopes: Vec<String> = scopes::get_scopes(&cdb).await.or(Err(ERR_FAILED))?;
+fn gen_recovery_code(rng: &Mutex<StdRng>) -> String {
+    let bytes: [u8; 6] = rng.lock().unwrap().gen(); // safe
+    bytes.encode_hex()
+}
 
-    // fail if disabled, expired, or if provided credentials are bad
-    if !u.is_enabled()
-    || !password_valid(&u.hash, &req.secret) {
+/*
+ * Check the second factor if the user, or any scope it asked for, requires one.
+ * The code may be a TOTP code or one of the user's recovery codes.
+ * A user that requires one only gets a token without it when an admin has
+ * let it enroll, and then without any scopes.
+ */
+async fn check_mfa(cdb: &CachedDb<'_>, u: &user::User, req_scopes: &HashSet<&str>, code: Option<&str>) -> StrRes<()> {
+    if req_scopes.is_empty() && (!u.require_mfa || u.mfa_enroll) {
+        return Ok(());
+    }
+    let mfa_scopes: Vec<String> = scopes::get_mfa_scopes(cdb).await?;
+    if !u.require_mfa && !req_scopes.iter().any(|want| mfa_scopes.iter().any(|s| want == s)) {
+        return Ok(());
+    }
+
+    let code = code.ok_or(ERR_MFA_REQUIRED)?;
+    let t = match totp::get_totp(cdb, u.name.clone()).await {
+        Ok(t) if t.confirmed => t,
+        Ok(_) | Err(Error::NotFound) => return Err(ERR_MFA_REQUIRED),
+        Err(e) => return Err(e.into()),
+    };
+    let secret = otp::decode_secret(&t.secret).ok_or(ERR_FAILED)?;
+    if let Some(step) = otp::verify(&secret, code, otp::current_step()) {
+        // each code can only be used once
+        if totp::use_step(cdb, u.name.clone(), step as i64).await? {
+            return Ok(());
+        }
         return Err(ERR_BADAUTH);
     }
-    if !scopes_valid(&req.scopes, &u.scopes, &active_scopes) {
-        return Err(ERR_BADSCOPES);
+    if totp::use_recovery_code(cdb, u.name.clone(), hash_code(&u.name, code)).await? {
+        return Ok(());
     }
+    Err(ERR_BADAUTH)
+}
 
-    let tokstr = gen_token(&cdb.serv.rng);
+// A newly issued token and the bearer string its owner presents to use it.
+pub struct Issued {
+    pub bearer: String,
+    pub tok: token::Token,
+}
+
+// Create and store a new token for a user, or a client, that has already been checked.
+async fn new_token(cdb: &CachedDb<'_>, username: &str, req_scopes: &HashSet<&str>, client_id: Option<&str>) -> StrRes<Issued> {
+    let secret = gen_token(&cdb.serv.rng);
     let life = Duration::new(cdb.serv.token_lifetime, 0);
-    let exp = SystemTime::now() + life;
-    let granted_scopes: Vec<String> = req.scopes.iter().copied().map(|s| s.to_owned()).collect();
+    let now = SystemTime::now();
+    let exp = now + life;
+    let granted_scopes: Vec<String> = req_scopes.iter().copied().map(|s| s.to_owned()).collect();
 
     // add session to our store
     let tok = token::Token {
-        token: tokstr.clone(),
-        username: u.name.clone(),
+        token: hash_secret(&secret),
+        username: username.to_owned(),
         expiration: exp,
-        scopes: granted_scopes.clone(),
+        scopes: granted_scopes,
+        issued: now,
+        client_id: client_id.map(|s| s.to_owned()),
+    };
+    token::put_token(cdb, &tok).await?;
+    audit::record(cdb, "token_issued", Some(&tok), Some(username), format!("scopes={}", tok.scopes.join(","))).await;
+
+    // a jwt carries everything needed to check it, and the secret as its jti
+    let bearer = match &cdb.serv.jwt {
+        Some(keys) => keys.sign(&token::claims(&tok, secret))?,
+        None => secret,
+    };
+    Ok(Issued{ bearer: bearer, tok: tok })
+}
+
+/*
+ * Check a user's credentials and the scopes it asked for, and issue it a new token.
+ * The password is hashed whatever the state of the account, even one that
+ * doesn't exist, so how long a login takes doesn't give away which it was.
+ */
+async fn login(cdb: &CachedDb<'_>, name: &str, secret: &str, req_scopes: &HashSet<&str>, code: Option<&str>, client_id: Option<&str>) -> StrRes<Issued> {
+    let u = match user::get_user(cdb, name.to_owned()).await {
+        Ok(u) => u,
+        Err(Error::NotFound) => {
+            cdb.serv.password.verify_nobody(secret).await?;
+            return Err(ERR_BADAUTH);
+        },
+        Err(e) => return Err(e.into()),
+    };
+    let check = cdb.serv.password.verify(&u.hash, secret).await?;
+    let active_scopes: Vec<String> = scopes::get_scopes(cdb).await?;
+
+    // fail if disabled, expired, or if provided credentials are bad
+    if u.is_expired() {
+        return Err(ERR_EXPIRED);
+    }
+    if !u.is_enabled() {
+        return Err(ERR_DISABLED);
+    }
+    if check == Check::Bad {
+        return Err(ERR_BADAUTH);
+    }
+    if check == Check::Rehash {
+        // we only see the password when it is right, so this is the time to upgrade its hash
+        // ignore any errors, it will be tried again next time
+        if let Ok(hash) = cdb.serv.password.hash(&cdb.serv.rng, secret).await {
+            let _ = user::set_hash(cdb, u.name.clone(), u.hash.clone(), hash).await;
+        }
+    }
+    if !scopes_valid(req_scopes, &u.scopes, &active_scopes) {
+        return Err(ERR_BADSCOPES);
+    }
+    check_mfa(cdb, &u, req_scopes, code).await?;
+
+    new_token(cdb, &u.name, req_scopes, client_id).await
+}
+
+/*
+ * Login, unless the user name or the client address has been locked out.
+ * Bad credentials count towards a lockout. The lockout is checked before
+ * the password, so a locked out client can't keep guessing.
+ * client_id is the registered client asking on the user's behalf, already checked.
+ */
+pub async fn issue_token(cdb: &CachedDb<'_>, name: &str, secret: &str, req_scopes: &HashSet<&str>, code: Option<&str>, client_id: Option<&str>) -> StrRes<Issued> {
+    let subjects = throttle::subjects(name, cdb.ip);
+    if let Some(secs) = throttle::locked(cdb, &subjects).await {
+        cdb.serv.metrics.logins.with_label_values(&["locked"]).inc();
+        audit::record(cdb, "login_failed", None, Some(name), ERR_LOCKED.code().to_owned()).await;
+        return Err(ERR_LOCKED.with_retry_after(secs));
+    }
+
+    let res = login(cdb, name, secret, req_scopes, code, client_id).await;
+    let outcome = match &res {
+        Ok(_) => "ok",
+        Err(e) => e.code(),
+    };
+    cdb.serv.metrics.logins.with_label_values(&[outcome]).inc();
+    match &res {
+        Ok(iss) => {
+            let _ = throttle::succeeded(cdb, &subjects[0]).await; // ignore any errors
+            audit::record(cdb, "login", Some(&iss.tok), Some(name), String::new()).await;
+        },
+        Err(e) => {
+            if let "badauth" | "disabled" | "expired" = e.code() {
+                let _ = throttle::failed(cdb, &subjects).await;
+            }
+            audit::record(cdb, "login_failed", None, Some(name), e.code().to_owned()).await;
+        },
+    }
+    // an expired or disabled account looks like bad credentials, so as not to reveal it exists
+    res.map_err(|e| match e.code() {
+        "disabled" | "expired" => ERR_BADAUTH,
+        _ => e,
+    })
+}
+
+// Check a registered client's secret, that it may use the grant and ask for the scopes.
+async fn verify_client(cdb: &CachedDb<'_>, id: &str, secret: &str, grant: &str, req_scopes: &HashSet<&str>) -> StrRes<client::Client> {
+    // as with users, the secret is hashed even for a client that doesn't exist or is disabled
+    let c = match client::get_client(cdb, id.to_owned()).await {
+        Ok(c) => c,
+        Err(Error::NotFound) => {
+            cdb.serv.password.verify_nobody(secret).await?;
+            return Err(ERR_BADAUTH);
+        },
+        Err(e) => return Err(e.into()),
+    };
+    // any of its secrets will do, while it is being rotated
+    let mut good = false;
+    for hash in c.secrets.iter() {
+        if cdb.serv.password.verify(hash, secret).await? != Check::Bad {
+            good = true;
+            break;
+        }
+    }
+    if c.secrets.is_empty() {
+        cdb.serv.password.verify_nobody(secret).await?;
+    }
+    if !c.enabled {
+        return Err(ERR_DISABLED);
+    }
+    if !good {
+        return Err(ERR_BADAUTH);
+    }
+    if !c.allows(grant) {
+        return Err(ERR_BADGRANT);
+    }
+    let active_scopes: Vec<String> = scopes::get_scopes(cdb).await?;
+    if !scopes_valid(req_scopes, &c.scopes, &active_scopes) {
+        return Err(ERR_BADSCOPES);
+    }
+    Ok(c)
+}
+
+/*
+ * Authenticate a registered client for a grant. Bad secrets count towards
+ * a lockout of the client_id, the same as bad passwords do for users,
+ * but kept apart from them so a client can't lock out a user.
+ */
+pub async fn check_client(cdb: &CachedDb<'_>, id: &str, secret: &str, grant: &str, req_scopes: &HashSet<&str>) -> StrRes<client::Client> {
+    let subjects = throttle::client_subjects(id, cdb.ip);
+    if let Some(secs) = throttle::locked(cdb, &subjects).await {
+        audit::record(cdb, "client_auth_failed", None, Some(id), format!("grant={} {}", grant, ERR_LOCKED.code())).await;
+        return Err(ERR_LOCKED.with_retry_after(secs));
+    }
+
+    let res = verify_client(cdb, id, secret, grant, req_scopes).await;
+    match &res {
+        Ok(_) => {
+            let _ = throttle::succeeded(cdb, &subjects[0]).await; // ignore any errors
+        },
+        Err(e) => {
+            if let "badauth" | "disabled" = e.code() {
+                let _ = throttle::failed(cdb, &subjects).await;
+            }
+            audit::record(cdb, "client_auth_failed", None, Some(id), format!("grant={} {}", grant, e.code())).await;
+        },
+    }
+    // as with users, a disabled client looks like bad credentials
+    res.map_err(|e| match e.code() {
+        "disabled" => ERR_BADAUTH,
+        _ => e,
+    })
+}
+
+// Issue a client a token of its own, named for its client_id. The client has already been checked.
+pub async fn issue_client_token(cdb: &CachedDb<'_>, c: &client::Client, req_scopes: &HashSet<&str>) -> StrRes<Issued> {
+    new_token(cdb, &c.client_id, req_scopes, Some(&c.client_id)).await
+}
+
+// Issue a refresh token alongside an access token. A new login starts a new family.
+pub async fn issue_refresh(cdb: &CachedDb<'_>, tok: &token::Token, scopes: Vec<String>, family: Option<String>) -> StrRes<String> {
+    let tokstr = gen_token(&cdb.serv.rng);
+    let life = Duration::new(cdb.serv.refresh_lifetime, 0);
+    let rt = refresh::RefreshToken {
+        token: hash_secret(&tokstr),
+        family: family.unwrap_or_else(|| gen_token(&cdb.serv.rng)),
+        access: tok.token.clone(),
+        username: tok.username.clone(),
+        expiration: SystemTime::now() + life,
+        scopes: scopes,
+        used: false,
+        client_id: tok.client_id.clone(),
     };
-    token::put_token(&cdb, &tok).await.or(Err(ERR_FAILED))?;
+    refresh::put_refresh(cdb, &rt).await?;
+    Ok(tokstr)
+}
+
+
+/*
+ * Swap a refresh token for a new access token and a new refresh token.
+ * The access token may ask for fewer scopes than the original login, but never more.
+ * Presenting a refresh token that was already swapped revokes its whole family.
+ * client_id is the client that has authenticated to present it, already checked.
+ */
+pub async fn refresh_token(cdb: &CachedDb<'_>, name: &str, req_scopes: Option<&HashSet<&str>>, client_id: Option<&str>) -> StrRes<(Issued, String)> {
+    let old = refresh::get_refresh(cdb, hash_secret(name)).await.map_err(notfound_badauth)?;
+    if old.is_expired() {
+        return Err(ERR_EXPIRED);
+    }
+    // a refresh token issued to a client is only any use to that client (RFC 6749 6),
+    // checked before it is used up so nobody else can spend it
+    if old.client_id.as_deref() != client_id {
+        return Err(ERR_BADAUTH);
+    }
+    if old.used || !refresh::mark_used(cdb, old.token.clone()).await? {
+        audit::record(cdb, "refresh_reused", None, Some(&old.username), String::new()).await;
+        refresh::revoke_family(cdb, old.family).await?;
+        return Err(ERR_BADAUTH);
+    }
+
+    // the user may have been disabled or lost scopes since it logged in
+    let u = user::get_user(cdb, old.username.clone()).await.map_err(notfound_badauth)?;
+    if !u.is_enabled() {
+        return Err(ERR_BADAUTH);
+    }
+    let active_scopes: Vec<String> = scopes::get_scopes(cdb).await?;
+    let want: HashSet<&str> = match req_scopes {
+        Some(want) => want.clone(),
+        None => old.scopes.iter().map(|s| s.as_str()).collect(),
+    };
+    if !scopes_valid(&want, &old.scopes, &active_scopes)
+    || !scopes_valid(&want, &u.scopes, &active_scopes) {
+        return Err(ERR_BADSCOPES);
+    }
+    // and so may the client that asked for the login
+    if let Some(id) = &old.client_id {
+        let c = client::get_client(cdb, id.clone()).await.map_err(notfound_badauth)?;
+        if !c.enabled || !c.allows("refresh_token") {
+            return Err(ERR_BADAUTH);
+        }
+        if !scopes_valid(&want, &c.scopes, &active_scopes) {
+            return Err(ERR_BADSCOPES);
+        }
+    }
+
+    // the new refresh token keeps the scopes of the original login
+    let iss = new_token(cdb, &u.name, &want, old.client_id.as_deref()).await?;
+    let newrefresh = issue_refresh(cdb, &iss.tok, old.scopes, Some(old.family)).await?;
+    Ok((iss, newrefresh))
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id, req = ?req.0))]
+pub async fn auth_sr(cdb: CachedDb<'_>, req: Json<AuthReq<'_>>) -> StrRes<AuthResp> {
+    let iss = issue_token(&cdb, req.name, req.secret, &req.scopes, req.otp, None).await?;
+    let refresh = issue_refresh(&cdb, &iss.tok, iss.tok.scopes.clone(), None).await?;
 
     // and send it back to the user
     let astate = AuthResp {
-        token: tokstr,
-        scopes: granted_scopes,
-        life: tok.seconds_left(),
+        life: iss.tok.seconds_left(),
+        token: iss.bearer,
+        refresh: refresh,
+        scopes: iss.tok.scopes,
     };
     Ok(astate)
 }
@@ -98,6 +398,23 @@ pub async fn auth(cdb: CachedDb<'_>, req: Json<AuthReq<'_>>) -> JsonRes<AuthResp
     json_res(auth_sr(cdb, req).await)
 }
 
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+pub async fn refresh_sr(cdb: CachedDb<'_>, req: Json<RefreshReq<'_>>) -> StrRes<AuthResp> {
+    let (iss, refresh) = refresh_token(&cdb, req.refresh, req.scopes.as_ref(), None).await?;
+    let astate = AuthResp {
+        life: iss.tok.seconds_left(),
+        token: iss.bearer,
+        refresh: refresh,
+        scopes: iss.tok.scopes,
+    };
+    Ok(astate)
+}
+
+#[post("/refresh", format="json", data="<req>")]
+pub async fn auth_refresh(cdb: CachedDb<'_>, req: Json<RefreshReq<'_>>) -> JsonRes<AuthResp> {
+    json_res(refresh_sr(cdb, req).await)
+}
+
 #[derive(Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct TokenResp {
@@ -106,6 +423,7 @@ pub struct TokenResp {
     scopes: Vec<String>,
 }
 
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
 pub async fn check_auth_sr(cdb: CachedDb<'_>, bearer: BearerToken) -> StrRes<TokenResp> {
     let tok = bearer.lookup(&cdb).await?;
     let scopes: Vec<String> = tok.scopes.iter().map(|s| s.clone()).collect();
@@ -122,3 +440,108 @@ pub async fn check_auth(cdb: CachedDb<'_>, bearer: BearerToken) -> JsonRes<Token
     json_res(check_auth_sr(cdb, bearer).await)
 }
 
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+pub async fn logout_sr(cdb: CachedDb<'_>, bearer: BearerToken) -> StrRes<&'static str> {
+    let tok = bearer.lookup(&cdb).await?;
+    token::revoke(&cdb, tok.token.clone()).await?;
+    audit::record(&cdb, "token_revoked", Some(&tok), Some(&tok.username), "logout".to_owned()).await;
+    Ok("revoked")
+}
+
+#[delete("/", format="json")]
+pub async fn logout(cdb: CachedDb<'_>, bearer: BearerToken) -> JsonRes<&'static str> {
+    json_res(logout_sr(cdb, bearer).await)
+}
+
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct EnrollResp {
+    uri: String,
+    secret: String,
+}
+
+// Only users an admin has let enroll may, so a stolen password can't be used to add a second factor.
+async fn may_enroll(cdb: &CachedDb<'_>, tok: &token::Token) -> StrRes<user::User> {
+    let u = user::get_user(cdb, tok.username.clone()).await.map_err(notfound_badauth)?;
+    u.mfa_enroll.then(|| u).ok_or(ERR_FORBIDDEN)
+}
+
+// Start enrolling a TOTP second factor. It isn't used until it is confirmed.
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+pub async fn totp_enroll_sr(cdb: CachedDb<'_>, bearer: BearerToken) -> StrRes<EnrollResp> {
+    let tok = bearer.require_user(&cdb).await?;
+    may_enroll(&cdb, &tok).await?;
+    match totp::get_totp(&cdb, tok.username.clone()).await {
+        Ok(t) if t.confirmed => return Err(ERR_CONFLICT),
+        Ok(_) | Err(Error::NotFound) => {},
+        Err(e) => return Err(e.into()),
+    }
+
+    let bytes: [u8; 20] = cdb.serv.rng.lock().unwrap().gen(); // safe
+    let secret = otp::encode_secret(&bytes);
+    let t = totp::Totp {
+        username: tok.username.clone(),
+        secret: secret.clone(),
+        confirmed: false,
+        last_step: 0,
+    };
+    totp::put_totp(&cdb, t).await?;
+
+    let resp = EnrollResp {
+        uri: otp::uri("authsrv", &tok.username, &secret),
+        secret: secret,
+    };
+    Ok(resp)
+}
+
+#[post("/totp/enroll", format="json")]
+pub async fn totp_enroll(cdb: CachedDb<'_>, bearer: BearerToken) -> JsonRes<EnrollResp> {
+    json_res(totp_enroll_sr(cdb, bearer).await)
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ConfirmReq<'r> {
+    otp: &'r str,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ConfirmResp {
+    recovery_codes: Vec<String>,
+}
+
+/*
+ * Confirm an enrollment with a code from the authenticator. From then on the
+ * user needs a second factor to get any scopes. The recovery codes are only
+ * ever shown here.
+ */
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+pub async fn totp_confirm_sr(cdb: CachedDb<'_>, bearer: BearerToken, req: Json<ConfirmReq<'_>>) -> StrRes<ConfirmResp> {
+    let tok = bearer.require_user(&cdb).await?;
+    let mut u = may_enroll(&cdb, &tok).await?;
+    let t = totp::get_totp(&cdb, tok.username.clone()).await?;
+    if t.confirmed {
+        return Err(ERR_CONFLICT);
+    }
+    let secret = otp::decode_secret(&t.secret).ok_or(ERR_FAILED)?;
+    let step = otp::verify(&secret, req.otp, otp::current_step()).ok_or(ERR_BADAUTH)?;
+
+    let codes: Vec<String> = (0..10).map(|_| gen_recovery_code(&cdb.serv.rng)).collect();
+    let hashes = codes.iter().map(|c| hash_code(&tok.username, c)).collect();
+    totp::put_recovery_codes(&cdb, tok.username.clone(), hashes).await?;
+    totp::confirm_totp(&cdb, tok.username.clone(), step as i64).await?;
+
+    u.require_mfa = true;
+    u.mfa_enroll = false;
+    user::update_user(&cdb, u).await?;
+    audit::record(&cdb, "totp_enrolled", Some(&tok), Some(&tok.username), String::new()).await;
+
+    Ok(ConfirmResp{ recovery_codes: codes })
+}
+
+#[post("/totp/confirm", format="json", data="<req>")]
+pub async fn totp_confirm(cdb: CachedDb<'_>, bearer: BearerToken, req: Json<ConfirmReq<'_>>) -> JsonRes<ConfirmResp> {
+    json_res(totp_confirm_sr(cdb, bearer, req).await)
+}
@@ -0,0 +1,105 @@
+
+/*
+ * Liveness and readiness, for whatever routes traffic to us.
+ * /healthz only says the process is up. /readyz probes what requests
+ * depend on and answers 503 when we shouldn't be sent any.
+ */
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+use rocket::http::Status;
+use rocket::serde::Serialize;
+use rocket::serde::json::Json;
+
+use crate::{Result, Error};
+use crate::json::{JsonRes, json_res};
+use crate::rocktypes::CachedDb;
+use crate::redis_support;
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Check {
+    ok: bool,
+    // a failure here doesn't make us unready
+    critical: bool,
+    ms: u128,
+    detail: String,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Readiness {
+    status: &'static str,
+    checks: BTreeMap<&'static str, Check>,
+}
+
+fn check(critical: bool, start: Instant, res: Result<String>) -> Check {
+    let (ok, detail) = match res {
+        Ok(detail) => (true, detail),
+        Err(e) => (false, e.to_string()),
+    };
+    Check{ ok: ok, critical: critical, ms: start.elapsed().as_millis(), detail: detail }
+}
+
+async fn check_database(cdb: &CachedDb<'_>) -> Result<String> {
+    cdb.serv.storage.ping(cdb).await?;
+    Ok(cdb.serv.storage.name().to_owned())
+}
+
+// Newer is fine, another node may have migrated ahead of us.
+async fn check_migrations(cdb: &CachedDb<'_>) -> Result<String> {
+    let want = cdb.serv.storage.latest_migration();
+    match cdb.serv.storage.migration_version(cdb).await? {
+        Some(have) if have.as_str() >= want => Ok(have),
+        have => Err(Error::Unavailable(format!("at {}, want {}",
+            have.as_deref().unwrap_or("none"), want))),
+    }
+}
+
+// When every connection is checked out, requests queue for the pool timeout.
+fn check_pool(in_use: usize, size: u32) -> Result<String> {
+    let detail = format!("{}/{} connections in use", in_use, size);
+    if in_use < size as usize {
+        Ok(detail)
+    } else {
+        Err(Error::Unavailable(detail))
+    }
+}
+
+// Redis is only a cache, so without it we carry on, slower and without throttling.
+// The other backends live in this process, so there is nothing to probe.
+async fn check_cache(cdb: &CachedDb<'_>) -> Result<String> {
+    let backend = cdb.serv.cache_backend.name();
+    if backend == "redis" {
+        cdb.cache().await?.run(|c| redis_support::ping(&mut c.0)).await?;
+    }
+    Ok(backend.to_owned())
+}
+
+#[get("/healthz")]
+pub fn healthz() -> JsonRes<&'static str> {
+    json_res(Ok("alive"))
+}
+
+#[get("/readyz")]
+pub async fn readyz(cdb: CachedDb<'_>) -> (Status, Json<Readiness>) {
+    let mut checks = BTreeMap::new();
+
+    // counted before we take a connection of our own
+    let start = Instant::now();
+    let in_use = cdb.serv.db_in_use.load(Ordering::Relaxed);
+    checks.insert("pool", check(true, start, check_pool(in_use, cdb.serv.db_pool_size)));
+
+    let start = Instant::now();
+    checks.insert("database", check(true, start, check_database(&cdb).await));
+
+    let start = Instant::now();
+    checks.insert("migrations", check(true, start, check_migrations(&cdb).await));
+
+    let start = Instant::now();
+    checks.insert("cache", check(false, start, check_cache(&cdb).await));
+
+    let ready = checks.values().all(|c| c.ok || !c.critical);
+    let (status, code) = if ready { ("ok", Status::Ok) } else { ("unavailable", Status::ServiceUnavailable) };
+    (code, Json(Readiness{ status: status, checks: checks }))
+}
@@ -0,0 +1,9 @@
+use rocket::http::ContentType;
+
+use crate::Server;
+
+// Everything in the Prometheus text format, for scraping.
+#[get("/metrics")]
+pub fn metrics(serv: &Server) -> (ContentType, String) {
+    (ContentType::Plain, serv.metrics.render(serv))
+}
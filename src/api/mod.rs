@@ -1,5 +1,8 @@
 
 pub mod admin;
 pub mod auth;
+pub mod health;
+pub mod metrics;
+pub mod oauth;
 pub mod test;
 
@@ -0,0 +1,316 @@
+
+use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::{Serialize, json::Json};
+use tracing::instrument;
+
+use crate::api::auth::{Issued, issue_token, issue_refresh, refresh_token, check_client, issue_client_token};
+use crate::model::client;
+use crate::model::token::lookup_token;
+use crate::rocktypes::{BasicAuth, BearerToken, CachedDb, Server};
+use crate::jwt::Jwks;
+use crate::json::StatusErr;
+use crate::Error;
+
+/*
+ * OAuth 2.0 (RFC 6749) flavored versions of the /auth endpoints.
+ */
+
+#[derive(FromForm)]
+pub struct TokenReq<'r> {
+    grant_type: Option<&'r str>,
+    username: Option<&'r str>,
+    password: Option<&'r str>,
+    scope: Option<&'r str>,
+    refresh_token: Option<&'r str>,
+    client_id: Option<&'r str>,
+    client_secret: Option<&'r str>,
+    otp: Option<&'r str>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TokenResp {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: u64,
+    scope: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+}
+
+// An error response as described in RFC 6749 section 5.2
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct OAuthErr {
+    error: &'static str,
+    error_description: &'static str,
+    #[serde(skip)]
+    status: Status,
+    #[serde(skip)]
+    retry_after: Option<u64>,
+}
+
+const ERR_INVALID_REQUEST: OAuthErr = OAuthErr {
+    error: "invalid_request",
+    error_description: "missing or malformed parameter",
+    status: Status::BadRequest,
+    retry_after: None,
+};
+const ERR_INVALID_CLIENT: OAuthErr = OAuthErr {
+    error: "invalid_client",
+    error_description: "client authentication failed",
+    status: Status::Unauthorized,
+    retry_after: None,
+};
+const ERR_INVALID_GRANT: OAuthErr = OAuthErr {
+    error: "invalid_grant",
+    error_description: "invalid credentials",
+    status: Status::BadRequest,
+    retry_after: None,
+};
+const ERR_INVALID_SCOPE: OAuthErr = OAuthErr {
+    error: "invalid_scope",
+    error_description: "requested scope is invalid or not granted",
+    status: Status::BadRequest,
+    retry_after: None,
+};
+const ERR_UNAUTHORIZED_CLIENT: OAuthErr = OAuthErr {
+    error: "unauthorized_client",
+    error_description: "client is not allowed to use this grant_type",
+    status: Status::BadRequest,
+    retry_after: None,
+};
+const ERR_UNSUPPORTED_GRANT: OAuthErr = OAuthErr {
+    error: "unsupported_grant_type",
+    error_description: "unsupported grant_type",
+    status: Status::BadRequest,
+    retry_after: None,
+};
+const ERR_INVALID_TOKEN: OAuthErr = OAuthErr {
+    error: "invalid_token",
+    error_description: "bearer token is invalid",
+    status: Status::Unauthorized,
+    retry_after: None,
+};
+const ERR_INSUFFICIENT_SCOPE: OAuthErr = OAuthErr {
+    error: "insufficient_scope",
+    error_description: "bearer token lacks the required scope",
+    status: Status::Forbidden,
+    retry_after: None,
+};
+const ERR_LOCKED: OAuthErr = OAuthErr {
+    error: "invalid_grant",
+    error_description: "too many failed attempts",
+    status: Status::TooManyRequests,
+    retry_after: None,
+};
+const ERR_SERVER: OAuthErr = OAuthErr {
+    error: "server_error",
+    error_description: "internal error",
+    status: Status::InternalServerError,
+    retry_after: None,
+};
+const ERR_UNAVAILABLE: OAuthErr = OAuthErr {
+    error: "temporarily_unavailable",
+    error_description: "service unavailable",
+    status: Status::ServiceUnavailable,
+    retry_after: None,
+};
+
+// Translate our errors into OAuth errors, reporting credential failures as badauth.
+fn oauth_err(e: StatusErr, badauth: OAuthErr) -> OAuthErr {
+    match e.code() {
+        "badauth" | "expired" | "mfarequired" => badauth,
+        "badscopes" => ERR_INVALID_SCOPE,
+        "badgrant" => ERR_UNAUTHORIZED_CLIENT,
+        "invalid" => ERR_INVALID_REQUEST,
+        "unavailable" => ERR_UNAVAILABLE,
+        "locked" => OAuthErr{ retry_after: e.retry_after(), ..ERR_LOCKED },
+        _ => ERR_SERVER,
+    }
+}
+
+pub type OAuthResult<T> = Result<T, OAuthErr>;
+
+// A Json result or OAuth error, sent with the headers RFC 6749 requires for token responses.
+pub struct OAuthRes<T>(OAuthResult<T>);
+
+impl<'r, T: Serialize> Responder<'r, 'static> for OAuthRes<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let (status, is_bearer, retry_after, mut resp) = match self.0 {
+            Ok(v) => (Status::Ok, false, None, Json(v).respond_to(req)?),
+            Err(e) => (e.status, e.error == "invalid_token", e.retry_after, Json(e).respond_to(req)?),
+        };
+        resp.set_status(status);
+        resp.set_raw_header("Cache-Control", "no-store");
+        resp.set_raw_header("Pragma", "no-cache");
+        if status == Status::Unauthorized {
+            let challenge = if is_bearer { "Bearer realm=\"authsrv\"" } else { "Basic realm=\"authsrv\"" };
+            resp.set_raw_header("WWW-Authenticate", challenge);
+        }
+        if let Some(secs) = retry_after {
+            resp.set_raw_header("Retry-After", secs.to_string());
+        }
+        Ok(resp)
+    }
+}
+
+fn scope_set(scope: Option<&str>) -> HashSet<&str> {
+    scope.map(|s| s.split_whitespace().collect()).unwrap_or_default()
+}
+
+// Client credentials, from basic auth or from the request body.
+fn client_creds<'a>(basic: &'a Option<BasicAuth>, req: &'a TokenReq<'_>) -> Option<(&'a str, &'a str)> {
+    match basic {
+        Some(ba) => Some((ba.name.as_str(), ba.secret.as_str())),
+        None => Some((req.client_id?, req.client_secret?)),
+    }
+}
+
+// A registered client may log its users in. Without client credentials the login is the user's own.
+async fn password_grant(cdb: &CachedDb<'_>, basic: Option<BasicAuth>, req: &TokenReq<'_>) -> OAuthResult<TokenResp> {
+    let name = req.username.ok_or(ERR_INVALID_REQUEST)?;
+    let secret = req.password.ok_or(ERR_INVALID_REQUEST)?;
+    let scopes = scope_set(req.scope);
+    let c = match client_creds(&basic, req) {
+        Some((id, csecret)) => Some(check_client(cdb, id, csecret, "password", &scopes).await
+                                    .map_err(|e| oauth_err(e, ERR_INVALID_CLIENT))?),
+        None => None,
+    };
+    let iss = issue_token(cdb, name, secret, &scopes, req.otp, c.as_ref().map(|c| c.client_id.as_str())).await
+                .map_err(|e| oauth_err(e, ERR_INVALID_GRANT))?;
+    if let Some(c) = &c {
+        if !c.allows("refresh_token") {
+            return Ok(token_resp(iss, None));
+        }
+    }
+    let refresh = issue_refresh(cdb, &iss.tok, iss.tok.scopes.clone(), None).await
+                .map_err(|e| oauth_err(e, ERR_SERVER))?;
+    Ok(token_resp(iss, Some(refresh)))
+}
+
+async fn client_credentials_grant(cdb: &CachedDb<'_>, basic: Option<BasicAuth>, req: &TokenReq<'_>) -> OAuthResult<TokenResp> {
+    let (name, secret) = client_creds(&basic, req).ok_or(ERR_INVALID_CLIENT)?;
+    let scopes = scope_set(req.scope);
+    let iss = match client::get_client(cdb, name.to_owned()).await {
+        // services set up as users before there were clients still log in as users
+        Err(Error::NotFound) => issue_token(cdb, name, secret, &scopes, None, None).await,
+        _ => match check_client(cdb, name, secret, "client_credentials", &scopes).await {
+            Ok(c) => issue_client_token(cdb, &c, &scopes).await,
+            Err(e) => Err(e),
+        },
+    };
+    let iss = iss.map_err(|e| oauth_err(e, ERR_INVALID_CLIENT))?;
+    Ok(token_resp(iss, None))
+}
+
+// A refresh token issued through a client can only be swapped by that client, with its credentials.
+async fn refresh_token_grant(cdb: &CachedDb<'_>, basic: Option<BasicAuth>, req: &TokenReq<'_>) -> OAuthResult<TokenResp> {
+    let name = req.refresh_token.ok_or(ERR_INVALID_REQUEST)?;
+    let scopes = req.scope.map(|s| scope_set(Some(s)));
+    let c = match client_creds(&basic, req) {
+        Some((id, csecret)) => Some(check_client(cdb, id, csecret, "refresh_token", &HashSet::new()).await
+                                    .map_err(|e| oauth_err(e, ERR_INVALID_CLIENT))?),
+        None => None,
+    };
+    let (iss, refresh) = refresh_token(cdb, name, scopes.as_ref(), c.as_ref().map(|c| c.client_id.as_str())).await
+                .map_err(|e| oauth_err(e, ERR_INVALID_GRANT))?;
+    Ok(token_resp(iss, Some(refresh)))
+}
+
+fn token_resp(iss: Issued, refresh: Option<String>) -> TokenResp {
+    TokenResp {
+        expires_in: iss.tok.seconds_left(),
+        access_token: iss.bearer,
+        token_type: "Bearer",
+        scope: iss.tok.scopes.join(" "),
+        refresh_token: refresh,
+    }
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn token_sr(cdb: CachedDb<'_>, basic: Option<BasicAuth>, req: Form<TokenReq<'_>>) -> OAuthResult<TokenResp> {
+    match req.grant_type {
+        Some("password") => password_grant(&cdb, basic, &req).await,
+        Some("client_credentials") => client_credentials_grant(&cdb, basic, &req).await,
+        Some("refresh_token") => refresh_token_grant(&cdb, basic, &req).await,
+        Some(_) => Err(ERR_UNSUPPORTED_GRANT),
+        None => Err(ERR_INVALID_REQUEST),
+    }
+}
+
+#[post("/token", format="form", data="<req>")]
+pub async fn token(cdb: CachedDb<'_>, basic: Option<BasicAuth>, req: Form<TokenReq<'_>>) -> OAuthRes<TokenResp> {
+    OAuthRes(token_sr(cdb, basic, req).await)
+}
+
+#[derive(FromForm)]
+pub struct IntrospectReq<'r> {
+    token: Option<&'r str>,
+}
+
+// A token introspection response as described in RFC 7662 section 2.2
+#[derive(Serialize, Default)]
+#[serde(crate = "rocket::serde")]
+pub struct IntrospectResp {
+    active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_type: Option<&'static str>,
+}
+
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[instrument(skip_all, fields(request_id = %cdb.request_id))]
+async fn introspect_sr(cdb: CachedDb<'_>, bearer: BearerToken, req: Form<IntrospectReq<'_>>) -> OAuthResult<IntrospectResp> {
+    // as RFC 6750 3.1 has it, a good token without the scope is forbidden
+    bearer.require_scope(&cdb, "authintrospect").await.map_err(|e| match e.code() {
+        "forbidden" => ERR_INSUFFICIENT_SCOPE,
+        _ => ERR_INVALID_TOKEN,
+    })?;
+    let name = req.token.ok_or(ERR_INVALID_REQUEST)?;
+
+    // unknown and expired tokens are reported as inactive, not as errors
+    let tok = match lookup_token(&cdb, name.to_owned()).await {
+        Ok(tok) if !tok.is_expired() => tok,
+        Ok(_) | Err(Error::NotFound) => return Ok(IntrospectResp::default()),
+        Err(e) => return Err(oauth_err(e.into(), ERR_SERVER)),
+    };
+    let resp = IntrospectResp {
+        active: true,
+        scope: Some(tok.scopes.join(" ")),
+        username: Some(tok.username),
+        exp: Some(unix_secs(tok.expiration)),
+        iat: Some(unix_secs(tok.issued)),
+        client_id: tok.client_id,
+        token_type: Some("Bearer"),
+    };
+    Ok(resp)
+}
+
+#[post("/introspect", format="form", data="<req>")]
+pub async fn introspect(cdb: CachedDb<'_>, bearer: BearerToken, req: Form<IntrospectReq<'_>>) -> OAuthRes<IntrospectResp> {
+    OAuthRes(introspect_sr(cdb, bearer, req).await)
+}
+
+// Publish the public half of our jwt signing keys
+#[get("/jwks.json")]
+pub fn jwks(serv: &Server) -> Option<Json<Jwks>> {
+    serv.jwt.as_ref().map(|keys| Json(keys.jwks()))
+}
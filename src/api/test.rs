@@ -3,8 +3,8 @@ use crate::Server;
 
 #[get("/")]
 pub fn health(serv: &Server) -> String {
-    format!("alive. caching is {}. cache lifetime {}\n",
-        if serv.use_cache { "enabled" } else { "disabled" },
+    format!("alive. caching with {}. cache lifetime {}\n",
+        serv.cache_backend.name(),
         serv.cache_lifetime)
 }
 
@@ -0,0 +1,226 @@
+
+/*
+ * Administers authsrv straight through its db, without the server running.
+ * It reads the same Rocket.toml and ROCKET_ environment as the server, and
+ * runs any migrations the db is missing before doing anything else.
+ *
+ * Passwords are made up and printed once, unless --prompt is given.
+ */
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use authsrv::{Error, Result};
+use authsrv::bootstrap;
+use authsrv::manage;
+use authsrv::rocktypes::CachedDb;
+use authsrv::model::{user, scopes, token, refresh, revoked, audit, name_valid};
+
+const USAGE: &str = "usage: authsrv-admin <command> [--prompt]
+
+    migrate                         run migrations and show the db's version
+    bootstrap [name]                create the admin, or reset its password
+    user create <name> <scope,...> [--life secs]
+    user list
+    user disable <name>             and revoke all of its tokens
+    user delete <name>
+    scope create <name>
+    scope list
+    scope retire <name>
+    scope delete <name>
+    token revoke <token>
+    token revoke-user <name>
+    purge                           delete expired tokens";
+
+// A year, for users made without --life.
+const DEFAULT_LIFE: u64 = 365 * 24 * 3600;
+
+fn invalid(msg: &str) -> Error {
+    Error::Invalid(msg.to_owned())
+}
+
+// The password to set, typed twice or made up and shown once.
+fn new_password(cdb: &CachedDb<'_>, prompt: bool) -> Result<String> {
+    if !prompt {
+        let pw = bootstrap::random_password(cdb.serv);
+        println!("password: {}", pw);
+        return Ok(pw);
+    }
+    let read = |msg: &str| rpassword::read_password_from_tty(Some(msg)).map_err(|e| invalid(&e.to_string()));
+    let pw = read("password: ")?;
+    if pw.is_empty() || pw != read("again: ")? {
+        return Err(invalid("passwords are empty or don't match"));
+    }
+    Ok(pw)
+}
+
+fn secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+async fn migrate(cdb: &CachedDb<'_>) -> Result<()> {
+    let storage = &cdb.serv.storage;
+    let have = storage.migration_version(cdb).await?;
+    println!("{} at migration {}, latest is {}",
+        storage.name(), have.as_deref().unwrap_or("none"), storage.latest_migration());
+    Ok(())
+}
+
+async fn bootstrap_admin(cdb: &CachedDb<'_>, name: &str, prompt: bool) -> Result<()> {
+    if !name_valid(name) {
+        return Err(invalid("bad user name"));
+    }
+    let pw = new_password(cdb, prompt)?;
+    if bootstrap::admin(cdb, name, &pw).await? {
+        println!("created {}", name);
+    } else {
+        println!("reset {}, its tokens are revoked", name);
+    }
+    Ok(())
+}
+
+async fn create_user(cdb: &CachedDb<'_>, name: &str, want: &str, life: u64, prompt: bool) -> Result<()> {
+    // checked again by manage, but before asking for a password for nothing
+    if !name_valid(name) {
+        return Err(invalid("bad user name"));
+    }
+    let pw = new_password(cdb, prompt)?;
+    let nu = manage::NewUser {
+        name: name,
+        secret: &pw,
+        life: life,
+        scopes: want.split(',').filter(|s| !s.is_empty()).map(|s| s.to_owned()).collect(),
+        require_mfa: false,
+        mfa_enroll: false,
+    };
+    manage::create_user(cdb, None, nu).await?;
+    println!("created {}", name);
+    Ok(())
+}
+
+async fn list_users(cdb: &CachedDb<'_>) -> Result<()> {
+    let mut offset = 0;
+    loop {
+        let us = user::list_users(cdb, offset, 1000).await?;
+        for u in us.iter() {
+            println!("{:16} {:8} expires {} mfa={} scopes={}",
+                u.name, if u.enabled { "enabled" } else { "disabled" },
+                secs(u.expiration), u.require_mfa, u.scopes.join(","));
+        }
+        if us.len() < 1000 {
+            return Ok(());
+        }
+        offset += 1000;
+    }
+}
+
+// Like the admin api, a disabled user shouldn't keep using tokens it already has.
+async fn disable_user(cdb: &CachedDb<'_>, name: &str) -> Result<()> {
+    let ch = manage::UserChanges { enabled: Some(false), ..Default::default() };
+    let cnt = manage::update_user(cdb, None, name, ch).await?;
+    println!("disabled {}, revoked {} tokens", name, cnt);
+    Ok(())
+}
+
+async fn delete_user(cdb: &CachedDb<'_>, name: &str) -> Result<()> {
+    manage::delete_user(cdb, None, name).await?;
+    println!("deleted {}", name);
+    Ok(())
+}
+
+async fn create_scope(cdb: &CachedDb<'_>, name: &str) -> Result<()> {
+    manage::create_scope(cdb, None, name).await?;
+    println!("created {}", name);
+    Ok(())
+}
+
+async fn list_scopes(cdb: &CachedDb<'_>) -> Result<()> {
+    for sc in scopes::list_scopes(cdb).await? {
+        println!("{:16} {:8} mfa={} {}",
+            sc.name, if sc.retired { "retired" } else { "active" }, sc.require_mfa, sc.description);
+    }
+    Ok(())
+}
+
+async fn retire_scope(cdb: &CachedDb<'_>, name: &str) -> Result<()> {
+    let changes = scopes::ScopeChanges { description: None, retired: Some(true), require_mfa: None };
+    manage::update_scope(cdb, None, name, changes).await?;
+    println!("retired {}", name);
+    Ok(())
+}
+
+async fn delete_scope(cdb: &CachedDb<'_>, name: &str) -> Result<()> {
+    manage::delete_scope(cdb, None, name).await?;
+    println!("deleted {}", name);
+    Ok(())
+}
+
+async fn revoke_token(cdb: &CachedDb<'_>, presented: &str) -> Result<()> {
+    manage::revoke_token(cdb, None, presented.to_owned()).await?;
+    println!("revoked");
+    Ok(())
+}
+
+async fn revoke_user(cdb: &CachedDb<'_>, name: &str) -> Result<()> {
+    let cnt = manage::revoke_user_tokens(cdb, None, name).await?;
+    println!("revoked {} tokens", cnt);
+    Ok(())
+}
+
+async fn purge(cdb: &CachedDb<'_>) -> Result<()> {
+    let toks = token::clean(cdb).await?;
+    let refreshes = refresh::clean(cdb).await?;
+    let revokes = revoked::clean(cdb).await?;
+    let detail = format!("tokens={} refresh={} revoked={}", toks, refreshes, revokes);
+    audit::record(cdb, "clean", None, None, detail.clone()).await;
+    println!("deleted {}", detail);
+    Ok(())
+}
+
+async fn run(cdb: &CachedDb<'_>, args: &[&str], prompt: bool) -> Result<()> {
+    match args {
+        ["migrate"] => migrate(cdb).await,
+        ["bootstrap"] => bootstrap_admin(cdb, "admin", prompt).await,
+        ["bootstrap", name] => bootstrap_admin(cdb, name, prompt).await,
+        ["user", "create", name, want] => create_user(cdb, name, want, DEFAULT_LIFE, prompt).await,
+        ["user", "create", name, want, "--life", life] => {
+            let life = life.parse().map_err(|_| invalid("bad life"))?;
+            create_user(cdb, name, want, life, prompt).await
+        },
+        ["user", "list"] => list_users(cdb).await,
+        ["user", "disable", name] => disable_user(cdb, name).await,
+        ["user", "delete", name] => delete_user(cdb, name).await,
+        ["scope", "create", name] => create_scope(cdb, name).await,
+        ["scope", "list"] => list_scopes(cdb).await,
+        ["scope", "retire", name] => retire_scope(cdb, name).await,
+        ["scope", "delete", name] => delete_scope(cdb, name).await,
+        ["token", "revoke", tok] => revoke_token(cdb, tok).await,
+        ["token", "revoke-user", name] => revoke_user(cdb, name).await,
+        ["purge"] => purge(cdb).await,
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        },
+    }
+}
+
+#[rocket::main]
+async fn main() {
+    authsrv::logging::init_cli();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let prompt = args.iter().any(|a| a == "--prompt");
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).filter(|a| *a != "--prompt").collect();
+
+    // igniting runs the migrations and sets up the pools, without serving anything
+    let rocket = match authsrv::build().ignite().await {
+        Ok(rocket) => rocket,
+        Err(e) => {
+            eprintln!("authsrv-admin: {}", e);
+            process::exit(1);
+        },
+    };
+    let cdb = CachedDb::offline(&rocket);
+    if let Err(e) = run(&cdb, &args, prompt).await {
+        eprintln!("authsrv-admin: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,207 @@
+
+/*
+ * Making the first admin, or taking an admin back after its password is
+ * lost. This works straight on the db, so it works when nobody can log in.
+ *
+ * The server makes the first admin itself when it starts and finds nobody
+ * holding authadmin. Its password comes from the config or a file, or is
+ * made up and printed once.
+ */
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use rand::Rng;
+use rocket::{Rocket, Build};
+use rocket::fairing::{self, AdHoc};
+use rocket::serde::Deserialize;
+use tracing::{info, warn, error};
+
+use crate::{Result, Error, ServerState};
+use crate::logging::Redacted;
+use crate::password::Check;
+use crate::rocktypes::CachedDb;
+use crate::model::{user, scopes, token, refresh, audit, name_valid};
+
+pub const ADMIN_SCOPE: &str = "authadmin";
+
+// The password of the admin the first migrations inserted. Anyone who has
+// read those migrations can log in with it.
+const DEFAULT_PASSWORD: &str = "adminadmin";
+
+#[derive(Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct Config {
+    pub admin: String,                 // name of the first admin
+    pub password: Option<String>,      // its password, better set with ROCKET_BOOTSTRAP
+    pub password_file: Option<String>, // or a file holding it, eg. a mounted secret
+    pub default_admin: String,         // "refuse" or "warn" when the old seeded admin is still there
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("admin", &self.admin)
+            .field("password", &self.password.as_ref().map(|_| Redacted))
+            .field("password_file", &self.password_file)
+            .field("default_admin", &self.default_admin)
+            .finish()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            admin: "admin".to_owned(),
+            password: None,
+            password_file: None,
+            default_admin: "refuse".to_owned(),
+        }
+    }
+}
+
+// Admins made here are good for ten years.
+const ADMIN_LIFE: u64 = 10 * 365 * 24 * 3600;
+
+// A password for an admin that nobody chose, to be shown once.
+pub fn random_password(serv: &ServerState) -> String {
+    let bytes: [u8; 16] = serv.rng.lock().unwrap().gen(); // safe
+    hex::encode(bytes)
+}
+
+/*
+ * Make name an enabled admin with the given password, creating it if it
+ * doesn't exist. An existing user keeps its other scopes but loses every
+ * token it had, in case whoever had the old password is still logged in.
+ * Returns true if the user was created.
+ */
+pub async fn admin(cdb: &CachedDb<'_>, name: &str, password: &str) -> Result<bool> {
+    // the scope may have been retired or deleted straight from the db
+    match scopes::put_scope(cdb, &ADMIN_SCOPE.to_owned()).await {
+        Ok(()) => {
+            let changes = scopes::ScopeChanges { description: None, retired: None, require_mfa: Some(true) };
+            scopes::update_scope(cdb, ADMIN_SCOPE.to_owned(), changes).await?;
+        },
+        Err(Error::Conflict(_)) => {
+            let changes = scopes::ScopeChanges { description: None, retired: Some(false), require_mfa: None };
+            scopes::update_scope(cdb, ADMIN_SCOPE.to_owned(), changes).await?;
+        },
+        Err(e) => return Err(e),
+    }
+
+    let hash = cdb.serv.password.hash(&cdb.serv.rng, password).await?;
+    let expiration = SystemTime::now() + Duration::from_secs(ADMIN_LIFE);
+    match user::get_user(cdb, name.to_owned()).await {
+        Ok(mut u) => {
+            u.hash = hash;
+            u.enabled = true;
+            u.mfa_enroll = true; // authadmin needs a second factor, it may not have one yet
+            u.expiration = u.expiration.max(expiration);
+            if !u.scopes.iter().any(|s| s == ADMIN_SCOPE) {
+                u.scopes.push(ADMIN_SCOPE.to_owned());
+            }
+            user::update_user(cdb, u).await?;
+            token::del_user_tokens(cdb, name.to_owned()).await?;
+            refresh::del_user_refresh(cdb, name.to_owned()).await?;
+            audit::record(cdb, "admin_rotated", None, Some(name), String::new()).await;
+            Ok(false)
+        },
+        Err(Error::NotFound) => {
+            let u = user::User {
+                name: name.to_owned(),
+                hash: hash,
+                expiration: expiration,
+                enabled: true,
+                scopes: vec![ADMIN_SCOPE.to_owned()],
+                require_mfa: false,
+                mfa_enroll: true,
+            };
+            user::put_user(cdb, u).await?;
+            audit::record(cdb, "admin_created", None, Some(name), String::new()).await;
+            Ok(true)
+        },
+        Err(e) => Err(e),
+    }
+}
+
+// Whether the old seeded admin can still log in with the published password.
+// A migration drops it, but not once its hash has been upgraded at a login.
+async fn has_default_admin(cdb: &CachedDb<'_>) -> Result<bool> {
+    match user::get_user(cdb, "admin".to_owned()).await {
+        Ok(u) if u.is_enabled() => Ok(cdb.serv.password.verify(&u.hash, DEFAULT_PASSWORD).await? != Check::Bad),
+        Ok(_) => Ok(false),
+        Err(Error::NotFound) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// The first admin's password, and whether it was made up here.
+fn first_password(cdb: &CachedDb<'_>, cfg: &Config) -> Result<(String, bool)> {
+    if let Some(pw) = &cfg.password {
+        return Ok((pw.clone(), false));
+    }
+    if let Some(path) = &cfg.password_file {
+        let pw = std::fs::read_to_string(path)
+            .map_err(|e| Error::Invalid(format!("bootstrap password_file {}: {}", path, e)))?;
+        let pw = pw.trim_end_matches(&['\n', '\r'][..]);
+        if pw.is_empty() {
+            return Err(Error::Invalid(format!("bootstrap password_file {} is empty", path)));
+        }
+        return Ok((pw.to_owned(), false));
+    }
+    Ok((random_password(cdb.serv), true))
+}
+
+// Returns false if the server shouldn't start.
+async fn first_run(rocket: &Rocket<Build>, cfg: &Config) -> Result<bool> {
+    let cdb = CachedDb::igniting(rocket);
+
+    if has_default_admin(&cdb).await? {
+        if cfg.default_admin == "warn" {
+            warn!("user admin still has the default password, reset it with authsrv-admin bootstrap");
+        } else {
+            error!("user admin still has the default password, reset it with authsrv-admin bootstrap \
+                    or set bootstrap.default_admin = \"warn\" to start anyway");
+            return Ok(false);
+        }
+    }
+
+    if !user::scope_holders(&cdb, ADMIN_SCOPE.to_owned()).await?.is_empty() {
+        return Ok(true);
+    }
+    if !name_valid(&cfg.admin) {
+        return Err(Error::Invalid(format!("bootstrap admin {} isn't a valid user name", cfg.admin)));
+    }
+    let (pw, generated) = first_password(&cdb, cfg)?;
+    admin(&cdb, &cfg.admin, &pw).await?;
+    if generated {
+        // straight to the terminal, never into the logs
+        eprintln!("authsrv: created admin {} with password {}", cfg.admin, pw);
+        warn!(user = %cfg.admin, "nobody held authadmin, created an admin with a generated password, printed once to stderr");
+    } else {
+        info!(user = %cfg.admin, "nobody held authadmin, created an admin with the configured password");
+    }
+    Ok(true)
+}
+
+async fn run(rocket: Rocket<Build>) -> fairing::Result {
+    let cfg: Config = match rocket.figment().focus("bootstrap").extract() {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            error!(error = %e, "bad bootstrap config");
+            return Err(rocket);
+        },
+    };
+    match first_run(&rocket, &cfg).await {
+        Ok(true) => Ok(rocket),
+        Ok(false) => Err(rocket),
+        Err(e) => {
+            error!(error = %e, "admin bootstrap failed");
+            Err(rocket)
+        },
+    }
+}
+
+// Makes the first admin when nobody holds authadmin, and refuses or warns
+// about the old seeded admin. Attach it after the migrations have run.
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Admin Bootstrap", run)
+}
@@ -0,0 +1,314 @@
+
+/*
+ * Where cached values are kept. Values arrive already encoded, with keys
+ * already prefixed, so a backend only has to store bytes until they expire.
+ *
+ * "redis" shares the cache between every node, "memory" keeps it inside
+ * this process, and "none" doesn't cache at all.
+ */
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use lru::LruCache;
+use redis;
+use redis::Commands;
+
+use crate::Result;
+use crate::rocktypes::CachedDb;
+use crate::localcache::CHANNEL;
+
+#[rocket::async_trait]
+pub trait Backend: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn get(&self, cdb: &CachedDb<'_>, key: String) -> Result<Option<Vec<u8>>>;
+    async fn get_many(&self, cdb: &CachedDb<'_>, keys: Vec<String>) -> Result<Vec<Option<Vec<u8>>>>;
+    async fn set(&self, cdb: &CachedDb<'_>, key: String, val: Vec<u8>, secs: usize) -> Result<()>;
+    // Returns the number of keys that were removed.
+    async fn del(&self, cdb: &CachedDb<'_>, keys: Vec<String>) -> Result<usize>;
+    // As del, for a delete that every node has to see, so it can't be skipped.
+    async fn evict(&self, cdb: &CachedDb<'_>, keys: Vec<String>) -> Result<usize> {
+        self.del(cdb, keys).await
+    }
+    // Every key starting with prefix.
+    async fn keys(&self, cdb: &CachedDb<'_>, prefix: String) -> Result<Vec<String>>;
+    // Add one to the count at key and keep it for secs. Returns the new count.
+    async fn incr(&self, cdb: &CachedDb<'_>, key: String, secs: usize) -> Result<u64>;
+    // Keep key for secs from now, if it is there.
+    async fn expire(&self, cdb: &CachedDb<'_>, key: String, secs: usize) -> Result<()>;
+    // Seconds until each key expires, 0 for those that aren't there.
+    async fn ttl(&self, cdb: &CachedDb<'_>, keys: Vec<String>) -> Result<Vec<u64>>;
+}
+
+pub fn new(name: &str, size: usize) -> std::result::Result<Box<dyn Backend>, String> {
+    match name {
+        "redis" => Ok(Box::new(Redis)),
+        "memory" if size > 0 => Ok(Box::new(Memory::new(size))),
+        "memory" => Err("memory cache needs a cache_size".to_owned()),
+        "none" => Ok(Box::new(Nothing)),
+        _ => Err(format!("unknown cache backend {}", name)),
+    }
+}
+
+pub struct Redis;
+
+#[rocket::async_trait]
+impl Backend for Redis {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn get(&self, cdb: &CachedDb<'_>, key: String) -> Result<Option<Vec<u8>>> {
+        let v = cdb.cache().await?.run(move |c| c.0.get(&key)).await?;
+        Ok(v)
+    }
+
+    async fn get_many(&self, cdb: &CachedDb<'_>, keys: Vec<String>) -> Result<Vec<Option<Vec<u8>>>> {
+        if keys.is_empty() { return Ok(Vec::new()); }
+        let vs = cdb.cache().await?.run(move |c| {
+            let mut p = redis::pipe();
+            for k in keys.iter() {
+                p.get(k);
+            }
+            p.query(&mut c.0)
+        }).await?;
+        Ok(vs)
+    }
+
+    async fn set(&self, cdb: &CachedDb<'_>, key: String, val: Vec<u8>, secs: usize) -> Result<()> {
+        let _: () = cdb.cache().await?.run(move |c| c.0.set_ex(&key, &*val, secs)).await?;
+        Ok(())
+    }
+
+    // Deletes are announced so every node can drop the keys from its local cache.
+    async fn del(&self, cdb: &CachedDb<'_>, keys: Vec<String>) -> Result<usize> {
+        if keys.is_empty() { return Ok(0); }
+        let (cnt,): (usize,) = cdb.cache().await?.run(move |c| {
+            let mut p = redis::pipe();
+            p.del(&keys);
+            for k in keys.iter() {
+                p.publish(CHANNEL, k).ignore();
+            }
+            p.query(&mut c.0)
+        }).await?;
+        Ok(cnt)
+    }
+
+    // Tried even while redis is being left alone, or other nodes would keep what was evicted.
+    async fn evict(&self, cdb: &CachedDb<'_>, keys: Vec<String>) -> Result<usize> {
+        cdb.cache_for_delete().await?;
+        self.del(cdb, keys).await
+    }
+
+    async fn keys(&self, cdb: &CachedDb<'_>, prefix: String) -> Result<Vec<String>> {
+        let keys = cdb.cache().await?.run(move |c| -> redis::RedisResult<Vec<String>> {
+            Ok(c.0.scan_match::<_, String>(format!("{}*", prefix))?.collect())
+        }).await?;
+        Ok(keys)
+    }
+
+    async fn incr(&self, cdb: &CachedDb<'_>, key: String, secs: usize) -> Result<u64> {
+        let (cnt,): (u64,) = cdb.cache().await?.run(move |c| {
+            redis::pipe().atomic()
+                .incr(&key, 1)
+                .expire(&key, secs).ignore()
+                .query(&mut c.0)
+        }).await?;
+        Ok(cnt)
+    }
+
+    async fn expire(&self, cdb: &CachedDb<'_>, key: String, secs: usize) -> Result<()> {
+        let _: () = cdb.cache().await?.run(move |c| c.0.expire(&key, secs)).await?;
+        Ok(())
+    }
+
+    async fn ttl(&self, cdb: &CachedDb<'_>, keys: Vec<String>) -> Result<Vec<u64>> {
+        if keys.is_empty() { return Ok(Vec::new()); }
+        let ttls: Vec<i64> = cdb.cache().await?.run(move |c| {
+            let mut p = redis::pipe();
+            for k in keys.iter() {
+                p.ttl(k);
+            }
+            p.query(&mut c.0)
+        }).await?;
+        // -2 is a missing key, -1 one that never expires, which we don't make
+        Ok(ttls.into_iter().map(|t| t.max(0) as u64).collect())
+    }
+}
+
+// Holds at most size values, dropping the least recently used first.
+pub struct Memory {
+    entries: Mutex<LruCache<String, (Vec<u8>, Instant)>>,
+}
+
+impl Memory {
+    pub fn new(size: usize) -> Memory {
+        Memory { entries: Mutex::new(LruCache::new(size)) }
+    }
+
+    fn lookup(entries: &mut LruCache<String, (Vec<u8>, Instant)>, key: &str) -> Option<Vec<u8>> {
+        let key = key.to_owned(); // lru can only look up by the key type
+        let expired = match entries.get(&key) {
+            Some((v, until)) if *until > Instant::now() => return Some(v.clone()),
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            entries.pop(&key);
+        }
+        None
+    }
+
+    // Whole seconds left, rounded up so a key about to expire still counts.
+    fn secs_left(until: Instant, now: Instant) -> u64 {
+        let left = until.saturating_duration_since(now);
+        left.as_secs() + if left.subsec_nanos() > 0 { 1 } else { 0 }
+    }
+}
+
+#[rocket::async_trait]
+impl Backend for Memory {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn get(&self, _cdb: &CachedDb<'_>, key: String) -> Result<Option<Vec<u8>>> {
+        let mut entries = self.entries.lock().unwrap(); // safe
+        Ok(Memory::lookup(&mut entries, &key))
+    }
+
+    async fn get_many(&self, _cdb: &CachedDb<'_>, keys: Vec<String>) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut entries = self.entries.lock().unwrap(); // safe
+        Ok(keys.iter().map(|k| Memory::lookup(&mut entries, k)).collect())
+    }
+
+    async fn set(&self, _cdb: &CachedDb<'_>, key: String, val: Vec<u8>, secs: usize) -> Result<()> {
+        let until = Instant::now() + Duration::from_secs(secs as u64);
+        self.entries.lock().unwrap().put(key, (val, until)); // safe
+        Ok(())
+    }
+
+    async fn del(&self, _cdb: &CachedDb<'_>, keys: Vec<String>) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap(); // safe
+        Ok(keys.iter().filter(|k| entries.pop(*k).is_some()).count())
+    }
+
+    async fn keys(&self, _cdb: &CachedDb<'_>, prefix: String) -> Result<Vec<String>> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap(); // safe
+        Ok(entries.iter()
+            .filter(|(k, (_, until))| k.starts_with(&prefix) && *until > now)
+            .map(|(k, _)| k.clone())
+            .collect())
+    }
+
+    // Counts are kept as decimal text, the same as redis keeps them.
+    async fn incr(&self, _cdb: &CachedDb<'_>, key: String, secs: usize) -> Result<u64> {
+        let mut entries = self.entries.lock().unwrap(); // safe
+        let cnt = Memory::lookup(&mut entries, &key)
+            .and_then(|v| String::from_utf8(v).ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(0) + 1;
+        let until = Instant::now() + Duration::from_secs(secs as u64);
+        entries.put(key, (cnt.to_string().into_bytes(), until));
+        Ok(cnt)
+    }
+
+    async fn expire(&self, _cdb: &CachedDb<'_>, key: String, secs: usize) -> Result<()> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap(); // safe
+        if let Some((_, until)) = entries.get_mut(&key) {
+            if *until > now {
+                *until = now + Duration::from_secs(secs as u64);
+            }
+        }
+        Ok(())
+    }
+
+    async fn ttl(&self, _cdb: &CachedDb<'_>, keys: Vec<String>) -> Result<Vec<u64>> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap(); // safe
+        Ok(keys.iter()
+            .map(|k| entries.peek(k).map(|(_, until)| Memory::secs_left(*until, now)).unwrap_or(0))
+            .collect())
+    }
+}
+
+// Caches nothing, every lookup misses.
+pub struct Nothing;
+
+#[rocket::async_trait]
+impl Backend for Nothing {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    async fn get(&self, _cdb: &CachedDb<'_>, _key: String) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    async fn get_many(&self, _cdb: &CachedDb<'_>, keys: Vec<String>) -> Result<Vec<Option<Vec<u8>>>> {
+        Ok(vec![None; keys.len()])
+    }
+
+    async fn set(&self, _cdb: &CachedDb<'_>, _key: String, _val: Vec<u8>, _secs: usize) -> Result<()> {
+        Ok(())
+    }
+
+    async fn del(&self, _cdb: &CachedDb<'_>, _keys: Vec<String>) -> Result<usize> {
+        Ok(0)
+    }
+
+    async fn keys(&self, _cdb: &CachedDb<'_>, _prefix: String) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    async fn incr(&self, _cdb: &CachedDb<'_>, _key: String, _secs: usize) -> Result<u64> {
+        Ok(0)
+    }
+
+    async fn expire(&self, _cdb: &CachedDb<'_>, _key: String, _secs: usize) -> Result<()> {
+        Ok(())
+    }
+
+    async fn ttl(&self, _cdb: &CachedDb<'_>, keys: Vec<String>) -> Result<Vec<u64>> {
+        Ok(vec![0; keys.len()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn memory_keeps_what_redis_would() {
+        let figment = rocket::Config::figment()
+            .merge(("log_level", "off"))
+            .merge(("storage", "sqlite"))
+            .merge(("databases.sqlite.url", ":memory:"));
+        let rocket = crate::custom(figment).ignite().await.expect("ignite");
+        let cdb = CachedDb::offline(&rocket);
+        let mem = new("memory", 4).expect("memory");
+        let k = |s: &str| s.to_owned();
+
+        mem.set(&cdb, k("user_alice"), b"alice".to_vec(), 60).await.expect("set");
+        mem.set(&cdb, k("user_bob"), b"bob".to_vec(), 60).await.expect("set");
+        assert_eq!(mem.get(&cdb, k("user_alice")).await.expect("get"), Some(b"alice".to_vec()));
+        assert_eq!(mem.get_many(&cdb, vec![k("user_bob"), k("user_carol")]).await.expect("get"), vec![Some(b"bob".to_vec()), None]);
+
+        // counts expire like redis's, and the oldest entry goes when it is full
+        assert_eq!(mem.incr(&cdb, k("fail_alice"), 60).await.expect("incr"), 1);
+        assert_eq!(mem.incr(&cdb, k("fail_alice"), 60).await.expect("incr"), 2);
+        mem.expire(&cdb, k("fail_alice"), 1).await.expect("expire");
+        assert_eq!(mem.ttl(&cdb, vec![k("fail_alice"), k("fail_bob")]).await.expect("ttl"), vec![1, 0]);
+        mem.set(&cdb, k("user_carol"), b"carol".to_vec(), 0).await.expect("set");
+        assert_eq!(mem.get(&cdb, k("user_carol")).await.expect("get"), None);
+        let mut users = mem.keys(&cdb, k("user_")).await.expect("keys");
+        users.sort();
+        assert_eq!(users, vec![k("user_alice"), k("user_bob")]);
+        mem.set(&cdb, k("user_dave"), b"dave".to_vec(), 60).await.expect("set");
+        mem.set(&cdb, k("user_erin"), b"erin".to_vec(), 60).await.expect("set");
+        assert_eq!(mem.get(&cdb, k("user_alice")).await.expect("get"), None);
+
+        assert_eq!(mem.del(&cdb, vec![k("user_alice"), k("user_bob")]).await.expect("del"), 1);
+        assert!(new("memory", 0).is_err());
+    }
+}
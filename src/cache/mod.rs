@@ -0,0 +1,191 @@
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use rocket::serde::{Serialize, DeserializeOwned};
+use rmp_serde;
+use tracing::instrument;
+
+use crate::Result;
+use crate::rocktypes::CachedDb;
+use crate::model::{token::Token, user::User};
+
+pub mod backend;
+
+// Every key authsrv keeps in redis starts with the configured prefix.
+pub fn key(cdb: &CachedDb<'_>, name: &str) -> String {
+    format!("{}{}", cdb.serv.cache_prefix, name)
+}
+
+// The kind of a key, the part of its name before the first '_'. Logged instead
+// of the whole key, which usually ends in a token hash.
+fn kind(name: &str) -> &str {
+    name.split('_').next().unwrap_or(name)
+}
+
+#[derive(Default)]
+pub struct Stats {
+    local_hits: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+// What the cache holds and how well it's doing, for the stats endpoint
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CacheStats {
+    pub backend: &'static str,
+    pub local_hits: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    pub local_entries: usize,
+    pub keys: BTreeMap<String, u64>, // by kind, eg. "token" or "lockout"
+}
+
+// Cached values that go stale at a known time shouldn't be cached past it.
+pub trait Expires {
+    fn expires(&self) -> Option<SystemTime> {
+        None
+    }
+}
+
+impl Expires for bool {}
+impl Expires for Vec<String> {}
+
+// Seconds to cache x in redis, or 0 if it shouldn't be cached at all.
+fn ttl(cdb: &CachedDb<'_>, x: &impl Expires) -> usize {
+    let life = cdb.serv.cache_lifetime as u64;
+    let left = match x.expires() {
+        Some(exp) => exp.duration_since(SystemTime::now()).map(|d| d.as_secs()).unwrap_or(0),
+        None => life,
+    };
+    life.min(left) as usize
+}
+
+/*
+ * Fetch key from the local cache, or failing that from the backend, and
+ * return it if there were no cache errors or parse errors.
+ */
+#[instrument(level = "debug", skip_all, fields(kind = kind(&key)))]
+pub async fn get<T>(cdb: &CachedDb<'_>, key: Arc<String>) -> Option<T>
+    where T: DeserializeOwned + Expires + Clone + Send + Sync + 'static
+{
+    let key = self::key(cdb, &key);
+    let stats = &cdb.serv.cache_stats;
+    let lookups = &cdb.serv.metrics.cache_lookups;
+    if let Some(local) = &cdb.serv.local_cache {
+        if let Some(x) = local.get(&key) {
+            stats.local_hits.fetch_add(1, Ordering::Relaxed);
+            lookups.with_label_values(&["local_hit"]).inc();
+            return Some(x);
+        }
+    }
+
+    let v = cdb.serv.cache_backend.get(cdb, key.clone()).await.ok().flatten();
+    let x: Option<T> = v.and_then(|v| rmp_serde::from_read_ref(&v).ok());
+    match &x {
+        Some(x) => {
+            stats.hits.fetch_add(1, Ordering::Relaxed);
+            lookups.with_label_values(&["hit"]).inc();
+            if let Some(local) = &cdb.serv.local_cache {
+                local.put(&key, x, x.expires());
+            }
+        }
+        None => {
+            stats.misses.fetch_add(1, Ordering::Relaxed);
+            lookups.with_label_values(&["miss"]).inc();
+        }
+    }
+    x
+}
+
+#[instrument(level = "debug", skip_all, fields(kind = kind(&key)))]
+pub async fn put<T>(cdb: &CachedDb<'_>, key: Arc<String>, x: &T) -> Result<()>
+    where T: Serialize + Expires + Clone + Send + Sync + 'static
+{
+    let lifetime = ttl(cdb, x);
+    if lifetime == 0 { return Ok(()); }
+    let key = self::key(cdb, &key);
+    if let Some(local) = &cdb.serv.local_cache {
+        local.put(&key, x, x.expires());
+    }
+    let v: Vec<u8> = rmp_serde::to_vec(x)?;
+    cdb.serv.cache_backend.set(cdb, key, v, lifetime).await
+}
+
+/*
+ * Delete key from the cache, and from the local cache of every node.
+ * Redis is tried even when it has been failing, and an error means
+ * other nodes may still have the old value.
+ */
+#[instrument(level = "debug", skip_all, fields(kind = kind(&key)))]
+pub async fn del(cdb: &CachedDb<'_>, key: Arc<String>) -> Result<()> {
+    let key = self::key(cdb, &key);
+    if let Some(local) = &cdb.serv.local_cache {
+        local.remove(&key);
+    }
+    cdb.serv.cache_backend.evict(cdb, vec![key]).await?;
+    Ok(())
+}
+
+// Whether a cached value is no longer worth keeping. Values that don't decode are stale too.
+fn is_stale(kind: &str, v: &[u8]) -> bool {
+    match kind {
+        "token_" => rmp_serde::from_read_ref::<_, Token>(v).map(|t| t.is_expired()).unwrap_or(true),
+        "user_" => rmp_serde::from_read_ref::<_, User>(v).map(|u| u.is_expired()).unwrap_or(true),
+        _ => false,
+    }
+}
+
+/*
+ * Remove cached tokens and users that have expired. The backend drops them
+ * on its own eventually, this just doesn't wait. Returns the number removed.
+ */
+#[instrument(level = "debug", skip_all)]
+pub async fn clean(cdb: &CachedDb<'_>) -> Result<usize> {
+    let backend = &cdb.serv.cache_backend;
+    let mut cnt = 0;
+    for kind in &["token_", "user_"] {
+        let keys = backend.keys(cdb, key(cdb, kind)).await?;
+        for chunk in keys.chunks(100) {
+            let vals = backend.get_many(cdb, chunk.to_vec()).await?;
+            let stale: Vec<String> = chunk.iter().zip(vals.iter())
+                .filter(|(_, v)| v.as_ref().map(|v| is_stale(kind, v)).unwrap_or(false))
+                .map(|(k, _)| k.clone())
+                .collect();
+            cnt += backend.del(cdb, stale).await?;
+        }
+    }
+    Ok(cnt)
+}
+
+// Count authsrv's cached keys by kind.
+async fn count_keys(cdb: &CachedDb<'_>) -> Result<BTreeMap<String, u64>> {
+    let prefix = &cdb.serv.cache_prefix;
+    let mut keys = BTreeMap::new();
+    for k in cdb.serv.cache_backend.keys(cdb, prefix.clone()).await? {
+        let name = k.get(prefix.len()..).unwrap_or("");
+        *keys.entry(kind(name).to_owned()).or_insert(0) += 1;
+    }
+    Ok(keys)
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn stats(cdb: &CachedDb<'_>) -> Result<CacheStats> {
+    let stats = &cdb.serv.cache_stats;
+    let local_hits = stats.local_hits.load(Ordering::Relaxed);
+    let hits = stats.hits.load(Ordering::Relaxed);
+    let misses = stats.misses.load(Ordering::Relaxed);
+    let lookups = local_hits + hits + misses;
+    Ok(CacheStats {
+        backend: cdb.serv.cache_backend.name(),
+        local_hits: local_hits,
+        hits: hits,
+        misses: misses,
+        hit_rate: if lookups == 0 { 0.0 } else { (local_hits + hits) as f64 / lookups as f64 },
+        local_entries: cdb.serv.local_cache.as_ref().map(|l| l.count()).unwrap_or(0),
+        keys: count_keys(cdb).await?,
+    })
+}
@@ -0,0 +1,66 @@
+
+use std::fmt;
+use diesel::result::{Error as DieselError, DatabaseErrorKind};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/*
+ * Errors from the model and cache layers.
+ * The api layer maps each kind to its own status code and error code.
+ */
+#[derive(Debug)]
+pub enum Error {
+    // The requested record doesn't exist
+    NotFound,
+    // The record already exists
+    Conflict(String),
+    // The request was malformed or violates a constraint
+    Invalid(String),
+    // A scope asked for doesn't exist, or can't be given or taken away
+    BadScopes(String),
+    // The db or cache couldn't be reached or failed
+    Unavailable(String),
+    // The caller isn't allowed to do this
+    Auth,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "not found"),
+            Error::Conflict(msg) => write!(f, "conflict: {}", msg),
+            Error::Invalid(msg) => write!(f, "invalid: {}", msg),
+            Error::BadScopes(msg) => write!(f, "bad scopes: {}", msg),
+            Error::Unavailable(msg) => write!(f, "unavailable: {}", msg),
+            Error::Auth => write!(f, "auth failure"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<DieselError> for Error {
+    fn from(e: DieselError) -> Self {
+        match e {
+            DieselError::NotFound => Error::NotFound,
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info) =>
+                Error::Conflict(info.message().to_owned()),
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, info) =>
+                Error::Invalid(info.message().to_owned()),
+            DieselError::QueryBuilderError(e) => Error::Invalid(e.to_string()),
+            e => Error::Unavailable(e.to_string()),
+        }
+    }
+}
+
+impl From<redis::RedisError> for Error {
+    fn from(e: redis::RedisError) -> Self {
+        Error::Unavailable(e.to_string())
+    }
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        Error::Invalid(e.to_string())
+    }
+}
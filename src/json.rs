@@ -1,29 +1,110 @@
 
 use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
 use rocket::serde::{Serialize, json::Json};
+use tracing::error;
 
-pub struct StatusErr(&'static str, Status);
+use crate::Error;
 
-pub const ERR_FAILED: StatusErr = StatusErr("failed", Status::Ok);
-pub const ERR_BADAUTH: StatusErr = StatusErr("auth failure", Status::Unauthorized);
-pub const ERR_BADSCOPES: StatusErr = StatusErr("bad scopes", Status::Unauthorized);
-pub const ERR_EXPIRED: StatusErr = StatusErr("expired", Status::Unauthorized);
+// An error code, a human readable message and the status to send them with,
+// and for errors that will go away, how many seconds until they do.
+pub struct StatusErr(&'static str, &'static str, Status, Option<u64>);
+
+const fn err(code: &'static str, msg: &'static str, status: Status) -> StatusErr {
+    StatusErr(code, msg, status, None)
+}
+
+pub const ERR_FAILED: StatusErr = err("failed", "failed", Status::InternalServerError);
+pub const ERR_BADAUTH: StatusErr = err("badauth", "auth failure", Status::Unauthorized);
+// A good token that isn't allowed to do what was asked.
+pub const ERR_FORBIDDEN: StatusErr = err("forbidden", "forbidden", Status::Forbidden);
+pub const ERR_BADSCOPES: StatusErr = err("badscopes", "bad scopes", Status::Unauthorized);
+pub const ERR_EXPIRED: StatusErr = err("expired", "expired", Status::Unauthorized);
+pub const ERR_MFA_REQUIRED: StatusErr = err("mfarequired", "second factor required", Status::Unauthorized);
+pub const ERR_NOTFOUND: StatusErr = err("notfound", "not found", Status::NotFound);
+pub const ERR_CONFLICT: StatusErr = err("conflict", "already exists", Status::Conflict);
+pub const ERR_INVALID: StatusErr = err("invalid", "invalid request", Status::BadRequest);
+pub const ERR_LOCKED: StatusErr = err("locked", "too many failures", Status::TooManyRequests);
+pub const ERR_BADGRANT: StatusErr = err("badgrant", "grant type not allowed for this client", Status::BadRequest);
+pub const ERR_UNAVAILABLE: StatusErr = err("unavailable", "service unavailable", Status::ServiceUnavailable);
+// Only for telling disabled users apart in metrics and the audit log, clients are sent ERR_BADAUTH.
+pub const ERR_DISABLED: StatusErr = err("disabled", "auth failure", Status::Unauthorized);
+
+impl StatusErr {
+    pub fn code(&self) -> &'static str {
+        self.0
+    }
+
+    pub fn status(&self) -> Status {
+        self.2
+    }
+
+    pub fn retry_after(&self) -> Option<u64> {
+        self.3
+    }
+
+    pub fn with_retry_after(self, secs: u64) -> StatusErr {
+        StatusErr(self.0, self.1, self.2, Some(secs))
+    }
+}
+
+impl From<Error> for StatusErr {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::NotFound => ERR_NOTFOUND,
+            Error::Conflict(_) => ERR_CONFLICT,
+            Error::Invalid(_) => ERR_INVALID,
+            Error::BadScopes(_) => ERR_BADSCOPES,
+            Error::Auth => ERR_BADAUTH,
+            Error::Unavailable(msg) => {
+                error!(error = %msg, "unavailable");
+                ERR_UNAVAILABLE
+            },
+        }
+    }
+}
+
+// Convert an Error into a StatusErr, reporting missing records as an auth failure.
+pub fn notfound_badauth(e: Error) -> StatusErr {
+    match e {
+        Error::NotFound => ERR_BADAUTH,
+        e => e.into(),
+    }
+}
 
 // A result with a status message
 #[derive(Serialize)]
 #[serde(crate = "rocket::serde")]
-pub struct WithStatus<T: Serialize> {
+pub struct WithStatus<T> {
     status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
     result: T,
 }
 
 // Results wrapped up as JSON
-type Error = WithStatus<&'static str>;
-type JsonError = Json<Error>;
+type ErrorMsg = WithStatus<&'static str>;
+type JsonError = Json<ErrorMsg>;
 type JsonWithStatus<T> = Json<WithStatus<T>>;
 
 // A JsonRes<T> is success or error wrapped in a Json message with a status field.
-pub type JsonRes<T> = (Status, Result<JsonWithStatus<T>, JsonError>);
+pub struct JsonRes<T> {
+    status: Status,
+    retry_after: Option<u64>,
+    body: Result<JsonWithStatus<T>, JsonError>,
+}
+
+impl<'r, T: Serialize> Responder<'r, 'static> for JsonRes<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut resp = self.body.respond_to(req)?;
+        resp.set_status(self.status);
+        if let Some(secs) = self.retry_after {
+            resp.set_raw_header("Retry-After", secs.to_string());
+        }
+        Ok(resp)
+    }
+}
 
 // A StrRes is success or an error string and a status code.
 pub type StrRes<T> = Result<T, StatusErr>;
@@ -31,12 +112,50 @@ pub type StrRes<T> = Result<T, StatusErr>;
 // Convert a StrRes<T> into a JsonRes<T> with a status code
 pub fn json_res<T: Serialize>(res: StrRes<T>) -> JsonRes<T> {
     match res {
-        Ok(v) => 
-            (Status::Ok, 
-             Ok(Json(WithStatus{ status: "ok", result: v, }))),
-        Err(StatusErr(msg, code)) => 
-            (code, 
-             Err(Json(WithStatus{ status: "error", result: msg, }))),
+        Ok(v) => JsonRes {
+            status: Status::Ok,
+            retry_after: None,
+            body: Ok(Json(WithStatus{ status: "ok", code: None, result: v, })),
+        },
+        Err(StatusErr(code, msg, status, retry_after)) => JsonRes {
+            status: status,
+            retry_after: retry_after,
+            body: Err(Json(WithStatus{ status: "error", code: Some(code), result: msg, })),
+        },
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::result::Error as DieselError;
+
+    #[test]
+    fn errors_are_never_ok() {
+        let cases = vec![
+            (Error::NotFound, "notfound", Status::NotFound),
+            (Error::Conflict("x".to_owned()), "conflict", Status::Conflict),
+            (Error::Invalid("x".to_owned()), "invalid", Status::BadRequest),
+            (Error::BadScopes("x".to_owned()), "badscopes", Status::Unauthorized),
+            (Error::Auth, "badauth", Status::Unauthorized),
+            (Error::Unavailable("x".to_owned()), "unavailable", Status::ServiceUnavailable),
+        ];
+        for (e, code, status) in cases.into_iter() {
+            let se = StatusErr::from(e);
+            assert_eq!((se.code(), se.status()), (code, status));
+            let res = json_res::<()>(Err(se));
+            assert_eq!(res.status, status);
+            assert!(res.body.is_err());
+        }
+        assert_eq!(ERR_FAILED.status(), Status::InternalServerError);
+    }
+
+    #[test]
+    fn missing_records_can_be_auth_failures() {
+        assert_eq!(notfound_badauth(Error::NotFound).code(), "badauth");
+        assert_eq!(notfound_badauth(Error::Conflict("x".to_owned())).code(), "conflict");
+        assert!(matches!(Error::from(DieselError::NotFound), Error::NotFound));
+        assert!(matches!(Error::from(DieselError::RollbackTransaction), Error::Unavailable(_)));
+    }
+}
@@ -0,0 +1,240 @@
+
+/*
+ * Signed JWT access tokens (RFC 7519) and the JWKS (RFC 7517) that
+ * lets other services verify them without asking us.
+ */
+use std::fs;
+use ring::rand::SystemRandom;
+use ring::signature::{self, KeyPair, Ed25519KeyPair, EcdsaKeyPair, RsaKeyPair, UnparsedPublicKey};
+use rocket::serde::{Serialize, Deserialize, json};
+
+use crate::{Result, Error};
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct KeyConfig {
+    pub kid: String,
+    pub alg: String,
+    pub key_file: String,
+}
+
+// The claims we put in every access token
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Claims {
+    pub sub: String,
+    pub scope: String,
+    pub exp: u64,
+    pub iat: u64,
+    pub jti: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Header {
+    alg: String,
+    typ: String,
+    kid: String,
+}
+
+// A public key as published in the JWKS
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Jwk {
+    kid: String,
+    alg: &'static str,
+    kty: &'static str,
+    #[serde(rename = "use")]
+    use_: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crv: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    y: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    e: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+enum Pair {
+    Ed25519(Ed25519KeyPair),
+    Es256(EcdsaKeyPair),
+    Rs256(RsaKeyPair),
+}
+
+struct Key {
+    kid: String,
+    pair: Pair,
+    jwk: Jwk,
+}
+
+/*
+ * Our signing keys. The first key signs new tokens. The rest are
+ * only used to verify tokens signed before the key was rotated out.
+ */
+pub struct Keys {
+    keys: Vec<Key>,
+    rng: SystemRandom,
+}
+
+fn b64(x: &[u8]) -> String {
+    base64::encode_config(x, base64::URL_SAFE_NO_PAD)
+}
+
+fn unb64(x: &str) -> Option<Vec<u8>> {
+    base64::decode_config(x, base64::URL_SAFE_NO_PAD).ok()
+}
+
+// Read a PKCS#8 key from a PEM or DER file
+fn read_pkcs8(path: &str) -> std::result::Result<Vec<u8>, String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    if !data.starts_with(b"-----BEGIN") {
+        return Ok(data);
+    }
+    let text = String::from_utf8(data).map_err(|e| format!("{}: {}", path, e))?;
+    let body: String = text.lines().filter(|l| !l.starts_with("-----")).collect();
+    base64::decode(body.trim()).map_err(|e| format!("{}: {}", path, e))
+}
+
+impl Key {
+    fn load(cfg: &KeyConfig) -> std::result::Result<Key, String> {
+        let der = read_pkcs8(&cfg.key_file)?;
+        let bad = |e: ring::error::KeyRejected| format!("{}: {}", cfg.key_file, e);
+        let (pair, jwk) = match cfg.alg.as_str() {
+            "EdDSA" => {
+                let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der).map_err(bad)?;
+                let jwk = Jwk {
+                    kty: "OKP",
+                    crv: Some("Ed25519"),
+                    x: Some(b64(pair.public_key().as_ref())),
+                    ..Jwk::new(&cfg.kid, "EdDSA")
+                };
+                (Pair::Ed25519(pair), jwk)
+            },
+            "ES256" => {
+                let pair = EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &der).map_err(bad)?;
+                // uncompressed point: 0x04 || x || y
+                let point = pair.public_key().as_ref();
+                let jwk = Jwk {
+                    kty: "EC",
+                    crv: Some("P-256"),
+                    x: Some(b64(&point[1..33])),
+                    y: Some(b64(&point[33..65])),
+                    ..Jwk::new(&cfg.kid, "ES256")
+                };
+                (Pair::Es256(pair), jwk)
+            },
+            "RS256" => {
+                let pair = RsaKeyPair::from_pkcs8(&der).map_err(bad)?;
+                let public = pair.public_key();
+                let jwk = Jwk {
+                    kty: "RSA",
+                    n: Some(b64(public.modulus().big_endian_without_leading_zero())),
+                    e: Some(b64(public.exponent().big_endian_without_leading_zero())),
+                    ..Jwk::new(&cfg.kid, "RS256")
+                };
+                (Pair::Rs256(pair), jwk)
+            },
+            alg => return Err(format!("unsupported jwt alg {}", alg)),
+        };
+        Ok(Key{ kid: cfg.kid.clone(), pair: pair, jwk: jwk })
+    }
+
+    fn sign(&self, rng: &SystemRandom, msg: &[u8]) -> Result<Vec<u8>> {
+        let failed = |_| Error::Unavailable("jwt signing failed".to_owned());
+        match &self.pair {
+            Pair::Ed25519(pair) => Ok(pair.sign(msg).as_ref().to_vec()),
+            Pair::Es256(pair) => Ok(pair.sign(rng, msg).map_err(failed)?.as_ref().to_vec()),
+            Pair::Rs256(pair) => {
+                let mut sig = vec![0; pair.public_modulus_len()];
+                pair.sign(&signature::RSA_PKCS1_SHA256, rng, msg, &mut sig).map_err(failed)?;
+                Ok(sig)
+            },
+        }
+    }
+
+    fn verify(&self, msg: &[u8], sig: &[u8]) -> bool {
+        let res = match &self.pair {
+            Pair::Ed25519(pair) =>
+                UnparsedPublicKey::new(&signature::ED25519, pair.public_key().as_ref()).verify(msg, sig),
+            Pair::Es256(pair) =>
+                UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, pair.public_key().as_ref()).verify(msg, sig),
+            Pair::Rs256(pair) =>
+                UnparsedPublicKey::new(&signature::RSA_PKCS1_2048_8192_SHA256, pair.public_key().as_ref()).verify(msg, sig),
+        };
+        res.is_ok()
+    }
+}
+
+impl Jwk {
+    fn new(kid: &str, alg: &'static str) -> Jwk {
+        Jwk {
+            kid: kid.to_owned(),
+            alg: alg,
+            kty: "",
+            use_: "sig",
+            crv: None,
+            x: None,
+            y: None,
+            n: None,
+            e: None,
+        }
+    }
+}
+
+impl Keys {
+    pub fn load(cfgs: &[KeyConfig]) -> std::result::Result<Keys, String> {
+        if cfgs.is_empty() {
+            return Err("jwt tokens need at least one key in jwt_keys".to_owned());
+        }
+        let keys = cfgs.iter().map(Key::load).collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(Keys{ keys: keys, rng: SystemRandom::new() })
+    }
+
+    pub fn sign(&self, claims: &Claims) -> Result<String> {
+        let key = &self.keys[0];
+        let hdr = Header {
+            alg: key.jwk.alg.to_owned(),
+            typ: "JWT".to_owned(),
+            kid: key.kid.clone(),
+        };
+        let hdr = json::serde_json::to_string(&hdr).map_err(|e| Error::Invalid(e.to_string()))?;
+        let body = json::serde_json::to_string(claims).map_err(|e| Error::Invalid(e.to_string()))?;
+        let msg = format!("{}.{}", b64(hdr.as_bytes()), b64(body.as_bytes()));
+        let sig = key.sign(&self.rng, msg.as_bytes())?;
+        Ok(format!("{}.{}", msg, b64(&sig)))
+    }
+
+    // Check the signature on a JWT and return its claims. Expiration is left to the caller.
+    pub fn verify(&self, tok: &str) -> Option<Claims> {
+        let mut parts = tok.rsplitn(2, '.');
+        let sig = unb64(parts.next()?)?;
+        let msg = parts.next()?;
+        let (hdr, body) = msg.split_once('.')?;
+        let hdr: Header = json::from_slice(&unb64(hdr)?).ok()?;
+        let key = self.keys.iter().find(|k| k.kid == hdr.kid)?;
+        if hdr.alg != key.jwk.alg || !key.verify(msg.as_bytes(), &sig) {
+            return None;
+        }
+        json::from_slice(&unb64(body)?).ok()
+    }
+
+    pub fn jwks(&self) -> Jwks {
+        Jwks{ keys: self.keys.iter().map(|k| k.jwk.clone()).collect() }
+    }
+}
+
+// Tokens we issue are hex, so anything with a dot in it must be a JWT.
+pub fn is_jwt(tok: &str) -> bool {
+    tok.contains('.')
+}
@@ -0,0 +1,213 @@
+
+#[macro_use] extern crate diesel;
+#[macro_use] extern crate diesel_migrations;
+#[macro_use] extern crate rocket;
+
+mod api;
+pub mod bootstrap;
+mod cache;
+mod error;
+mod json;
+mod jwt;
+mod localcache;
+pub mod logging;
+pub mod manage;
+mod metrics;
+pub mod model;
+mod otp;
+mod password;
+mod redis_support;
+pub mod rocktypes;
+pub mod storage;
+mod throttle;
+
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rocket::{Rocket, State, Build, Orbit};
+use rocket::serde::Deserialize;
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use tracing::info;
+
+use crate::rocktypes::{Db, SqliteDb, Cache};
+
+pub use crate::error::{Error, Result};
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct AppConfig {
+    use_tests: bool,
+    storage: String,
+    cache_backend: String,
+    #[serde(default)]
+    cache_size: usize,
+    cache_lifetime: u32,
+    cache_prefix: String,
+    token_lifetime: u64,
+    refresh_lifetime: u64,
+    token_format: String,
+    #[serde(default)]
+    jwt_keys: Vec<jwt::KeyConfig>,
+    #[serde(default)]
+    trusted_proxies: Vec<IpAddr>,
+    #[serde(default)]
+    throttle: throttle::Config,
+    #[serde(default)]
+    password: password::Config,
+    #[serde(default)]
+    local_cache: localcache::Config,
+}
+
+pub type Server = State<ServerState>;
+pub struct ServerState {
+    pub rng: Mutex<StdRng>,
+    pub storage: Box<dyn storage::Storage>,
+    pub cache_backend: Box<dyn cache::backend::Backend>,
+    pub cache_lifetime: u32,
+    pub cache_prefix: String, // starts every key in redis
+    pub cache_stats: cache::Stats,
+    pub token_lifetime: u64,
+    pub refresh_lifetime: u64,
+    pub jwt: Option<jwt::Keys>, // None when issuing opaque tokens
+    pub trusted_proxies: Vec<IpAddr>, // believed about the client they forward for
+    pub throttle: throttle::Config,
+    pub throttle_store: Box<dyn cache::backend::Backend>, // failed login counts
+    pub password: password::Hasher,
+    pub cache_down_until: AtomicU64, // unix time, redis isn't tried until then
+    pub local_cache: Option<Arc<localcache::LocalCache>>, // None when disabled
+    pub db_pool_size: u32,
+    pub db_in_use: AtomicUsize, // connections checked out by requests
+    pub cache_pool_size: u32,
+    pub cache_in_use: AtomicUsize,
+    pub metrics: metrics::Metrics,
+}
+
+impl ServerState {
+    fn new(cfg: &AppConfig, rocket: &Rocket<Build>) -> Self {
+        let jwt = match cfg.token_format.as_str() {
+            "opaque" => None,
+            "jwt" => Some(jwt::Keys::load(&cfg.jwt_keys).expect("jwt keys")),
+            fmt => panic!("unknown token_format {}", fmt),
+        };
+        let storage = storage::new(&cfg.storage).expect("storage config");
+        let pool = rocket_sync_db_pools::Config::from(storage.database(), rocket).expect("database config");
+        let cache_pool = rocket_sync_db_pools::Config::from("redis", rocket).expect("redis config");
+        let metrics = metrics::Metrics::new().expect("metrics");
+        ServerState {
+            rng: Mutex::new(StdRng::from_entropy()),
+            storage: storage,
+            cache_backend: cache::backend::new(&cfg.cache_backend, cfg.cache_size).expect("cache config"),
+            cache_lifetime: cfg.cache_lifetime,
+            cache_prefix: cfg.cache_prefix.clone(),
+            cache_stats: cache::Stats::default(),
+            token_lifetime: cfg.token_lifetime,
+            refresh_lifetime: cfg.refresh_lifetime,
+            jwt: jwt,
+            trusted_proxies: cfg.trusted_proxies.clone(),
+            throttle: cfg.throttle.clone(),
+            throttle_store: throttle::store(&cfg.cache_backend, &cfg.throttle).expect("throttle config"),
+            password: password::Hasher::new(&cfg.password, metrics.hash_seconds.clone()).expect("password config"),
+            cache_down_until: AtomicU64::new(0),
+            // only a shared cache needs a local one in front of it
+            local_cache: match cfg.cache_backend.as_str() {
+                "redis" => localcache::LocalCache::new(&cfg.local_cache).map(Arc::new),
+                _ => None,
+            },
+            db_pool_size: pool.pool_size,
+            db_in_use: AtomicUsize::new(0),
+            cache_pool_size: cache_pool.pool_size,
+            cache_in_use: AtomicUsize::new(0),
+            metrics: metrics,
+        }
+    }
+}
+
+async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
+    let serv = rocket.state::<ServerState>().expect("server state");
+    serv.storage.migrate(&rocket).await.expect("diesel migrations");
+    rocket
+}
+
+// Keep the local cache in step with every other node's deletes.
+fn subscribe_invalidations(rocket: &Rocket<Orbit>) {
+    let serv = rocket.state::<ServerState>().expect("server state");
+    if let Some(local) = &serv.local_cache {
+        let url: String = rocket.figment().extract_inner("databases.redis.url").expect("redis url");
+        localcache::subscribe(local.clone(), url);
+    }
+}
+
+// Everything but the logging, which the caller sets up first.
+pub fn build() -> Rocket<Build> {
+    custom(rocket::Config::figment())
+}
+
+// As build, with the config taken from figment instead of Rocket.toml and the environment.
+pub fn custom(figment: Figment) -> Rocket<Build> {
+    let mut b = rocket::custom(figment);
+    let conf: AppConfig = b.figment().extract().expect("config");
+
+    info!(storage = %conf.storage, cache = %conf.cache_backend, "starting");
+    if conf.use_tests {
+        b = b.mount("/test", routes![
+                api::test::health,
+                api::test::hasher,
+                api::test::crasher])
+    }
+
+    // only the pool for the storage in use is configured
+    b = match conf.storage.as_str() {
+        "sqlite" => b.attach(SqliteDb::fairing()),
+        _ => b.attach(Db::fairing()),
+    };
+
+    let serv = ServerState::new(&conf, &b);
+    b.manage(serv)
+        .attach(AdHoc::on_ignite("Diesel Migrations", run_migrations))
+        .attach(Cache::fairing())
+        .attach(metrics::Fairing)
+        .attach(logging::Fairing)
+        .attach(AdHoc::on_liftoff("Cache Invalidation", |rocket| Box::pin(async move {
+            subscribe_invalidations(rocket)
+        })))
+        .mount("/", routes![api::health::healthz,
+                            api::health::readyz,
+                            api::metrics::metrics])
+        .mount("/auth", routes![api::auth::auth,
+                                api::auth::check_auth,
+                                api::auth::auth_refresh,
+                                api::auth::logout,
+                                api::auth::totp_enroll,
+                                api::auth::totp_confirm])
+        .mount("/oauth", routes![api::oauth::token,
+                                 api::oauth::introspect])
+        .mount("/.well-known", routes![api::oauth::jwks])
+        .mount("/admin", routes![api::admin::create_user,
+                                 api::admin::get_user,
+                                 api::admin::list_users,
+                                 api::admin::update_user,
+                                 api::admin::delete_user,
+                                 api::admin::reset_totp,
+                                 api::admin::create_scope,
+                                 api::admin::list_scopes,
+                                 api::admin::update_scope,
+                                 api::admin::delete_scope,
+                                 api::admin::create_client,
+                                 api::admin::get_client,
+                                 api::admin::list_clients,
+                                 api::admin::update_client,
+                                 api::admin::rotate_secret,
+                                 api::admin::delete_client,
+                                 api::admin::revoke_token,
+                                 api::admin::list_lockouts,
+                                 api::admin::get_lockout,
+                                 api::admin::clear_lockout,
+                                 api::admin::list_audit,
+                                 api::admin::hash_stats,
+                                 api::admin::cache_stats,
+                                 api::admin::flush_cache,
+                                 api::admin::clean])
+}
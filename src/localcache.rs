@@ -0,0 +1,173 @@
+
+/*
+ * A small in-process cache in front of redis, so the values read on every
+ * request don't each need a round trip and a decode. Entries only live for
+ * a short while, and never past the expiration of what they hold.
+ *
+ * Every node deleting a key from redis announces it on a pub/sub channel,
+ * and every node drops the key when it hears about it. While the channel
+ * is down nothing can be heard, so the whole cache is dropped instead.
+ */
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use lru::LruCache;
+use rocket::serde::Deserialize;
+use tracing::warn;
+
+pub const CHANNEL: &str = "authsrv_invalidate";
+
+// Wait this long before trying to resubscribe
+const RETRY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct Config {
+    pub size: usize,   // entries to keep, 0 to disable
+    pub lifetime: u64, // seconds an entry can live
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            size: 0,
+            lifetime: 30,
+        }
+    }
+}
+
+struct Entry {
+    value: Arc<dyn Any + Send + Sync>,
+    until: Instant,
+}
+
+pub struct LocalCache {
+    entries: Mutex<LruCache<String, Entry>>,
+    lifetime: Duration,
+}
+
+impl LocalCache {
+    pub fn new(cfg: &Config) -> Option<LocalCache> {
+        if cfg.size == 0 {
+            return None;
+        }
+        Some(LocalCache {
+            entries: Mutex::new(LruCache::new(cfg.size)),
+            lifetime: Duration::from_secs(cfg.lifetime),
+        })
+    }
+
+    pub fn get<T: Clone + 'static>(&self, key: &str) -> Option<T> {
+        let key = key.to_owned(); // lru can only look up by the key type
+        let mut entries = self.entries.lock().unwrap(); // safe
+        let expired = match entries.get(&key) {
+            Some(e) if e.until > Instant::now() => return e.value.downcast_ref::<T>().cloned(),
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            entries.pop(&key);
+        }
+        None
+    }
+
+    // Keep a value until the lifetime is up, or until it expires if that's sooner.
+    pub fn put<T: Clone + Send + Sync + 'static>(&self, key: &str, x: &T, expires: Option<SystemTime>) {
+        let mut life = self.lifetime;
+        if let Some(exp) = expires {
+            life = life.min(exp.duration_since(SystemTime::now()).unwrap_or(Duration::from_secs(0)));
+        }
+        if life == Duration::from_secs(0) {
+            return;
+        }
+        let entry = Entry {
+            value: Arc::new(x.clone()),
+            until: Instant::now() + life,
+        };
+        self.entries.lock().unwrap().put(key.to_owned(), entry); // safe
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().pop(&key.to_owned()); // safe
+    }
+
+    pub fn count(&self) -> usize {
+        self.entries.lock().unwrap().len() // safe
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear(); // safe
+    }
+}
+
+fn listen(cache: &LocalCache, url: &str) -> redis::RedisResult<()> {
+    let client = redis::Client::open(url)?;
+    let mut conn = client.get_connection()?;
+    let mut ps = conn.as_pubsub();
+    ps.subscribe(CHANNEL)?;
+    // anything could have changed before we were listening
+    cache.clear();
+    loop {
+        let msg = ps.get_message()?;
+        let key: String = msg.get_payload()?;
+        cache.remove(&key);
+    }
+}
+
+// Listen for keys deleted by any node, for as long as the server runs.
+pub fn subscribe(cache: Arc<LocalCache>, url: String) {
+    thread::spawn(move || loop {
+        if let Err(e) = listen(&cache, &url) {
+            warn!(error = %e, "cache invalidation channel failed");
+        }
+        cache.clear();
+        thread::sleep(RETRY);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(size: usize) -> LocalCache {
+        LocalCache::new(&Config { size, lifetime: 30 }).expect("cache")
+    }
+
+    #[test]
+    fn size_zero_disables_it() {
+        assert!(LocalCache::new(&Config { size: 0, lifetime: 30 }).is_none());
+    }
+
+    #[test]
+    fn keeps_the_most_recently_used() {
+        let c = cache(2);
+        c.put("a", &1u32, None);
+        c.put("b", &2u32, None);
+        assert_eq!(c.get::<u32>("a"), Some(1));
+        c.put("c", &3u32, None);
+        assert_eq!(c.get::<u32>("b"), None);
+        assert_eq!(c.get::<u32>("a"), Some(1));
+        assert_eq!(c.get::<u32>("c"), Some(3));
+        // the wrong type is a miss, not a panic
+        assert_eq!(c.get::<String>("a"), None);
+
+        c.remove("a");
+        assert_eq!(c.get::<u32>("a"), None);
+        c.clear();
+        assert_eq!(c.count(), 0);
+    }
+
+    #[test]
+    fn never_outlives_what_it_holds() {
+        let c = cache(2);
+        c.put("gone", &1u32, Some(SystemTime::now() - Duration::from_secs(1)));
+        assert_eq!(c.count(), 0);
+
+        c.put("soon", &1u32, Some(SystemTime::now() + Duration::from_millis(50)));
+        assert_eq!(c.get::<u32>("soon"), Some(1));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(c.get::<u32>("soon"), None);
+        assert_eq!(c.count(), 0);
+    }
+}
@@ -0,0 +1,155 @@
+
+/*
+ * Structured logs, one JSON object per line on stdout. The level comes from
+ * RUST_LOG and is "info" if that isn't set. "debug" adds a span for every
+ * db and cache call. Rocket's own log lines come through here too.
+ *
+ * Every request gets an id, taken from its X-Request-Id header or made up,
+ * which is sent back in the response and logged with everything it does.
+ *
+ * Secrets never go in a log line. Spans skip their arguments, and types
+ * holding passwords, tokens or hashes print those fields as <redacted>.
+ */
+use std::fmt;
+use std::time::Instant;
+use rand::Rng;
+use rocket::{Request, Response, Data};
+use rocket::fairing::{self, Info, Kind};
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+use crate::ServerState;
+
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(filter)
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_span_events(FmtSpan::CLOSE) // so every span logs how long it took
+        .init();
+}
+
+// Plain text on stderr for the admin tool, and only when something is wrong.
+// Rocket has a lot to say about starting up that doesn't matter there.
+pub fn init_cli() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn,rocket=error,_=error"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+// Stands in for a secret when a struct holding one is printed.
+pub struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<redacted>")
+    }
+}
+
+// The id of the request being handled, kept with the request.
+pub struct RequestId(pub String);
+
+// Ids from clients are only trusted if they are short and plain.
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+}
+
+fn new_id(serv: Option<&ServerState>) -> String {
+    let bytes: [u8; 8] = match serv {
+        Some(serv) => serv.rng.lock().unwrap().gen(), // safe
+        None => rand::thread_rng().gen(),
+    };
+    hex::encode(bytes)
+}
+
+// When a request arrived, kept with the request.
+struct Started(Instant);
+
+// Gives every request an id, echoes it in the response and logs how the request went.
+pub struct Fairing;
+
+#[rocket::async_trait]
+impl fairing::Fairing for Fairing {
+    fn info(&self) -> Info {
+        Info { name: "Request Ids", kind: Kind::Request | Kind::Response }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        let id = match req.headers().get_one("X-Request-Id") {
+            Some(id) if valid_id(id) => id.to_owned(),
+            _ => new_id(req.rocket().state::<ServerState>()),
+        };
+        req.local_cache(|| RequestId(id));
+        req.local_cache(|| Started(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let id = &req.local_cache(|| RequestId(String::new())).0;
+        let started = req.local_cache(|| Started(Instant::now()));
+        res.set_raw_header("X-Request-Id", id.clone());
+
+        // the route rather than the uri, which can have names in it
+        let route = req.route().map(|r| r.uri.as_str()).unwrap_or("none");
+        info!(request_id = %id,
+              method = req.method().as_str(),
+              route = route,
+              status = res.status().code,
+              ms = started.0.elapsed().as_millis() as u64,
+              "request");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+    use rocket::serde::json;
+    use super::*;
+    use crate::api::auth::AuthReq;
+    use crate::model::{client::Client, totp::Totp, user::User};
+
+    #[test]
+    fn ids_from_clients() {
+        assert!(valid_id("3f2a-b_c.9"));
+        assert!(!valid_id(""));
+        assert!(!valid_id("has space"));
+        assert!(!valid_id("line\nbreak"));
+        assert!(!valid_id(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let req: AuthReq = json::from_str(r#"{"name":"alice","secret":"hunter2","scopes":["reports"],"otp":"314159"}"#).unwrap();
+        let c = Client {
+            client_id: "app".to_owned(),
+            secrets: vec!["$argon2id$clienthash".to_owned()],
+            scopes: vec![],
+            grant_types: vec![],
+            redirect_uris: vec![],
+            owner: "alice".to_owned(),
+            enabled: true,
+            created: SystemTime::now(),
+        };
+        let t = Totp { username: "alice".to_owned(), secret: "JBSWY3DPEHPK3PXP".to_owned(), confirmed: true, last_step: 0 };
+        let u = User {
+            name: "alice".to_owned(),
+            hash: "$argon2id$userhash".to_owned(),
+            expiration: SystemTime::now(),
+            enabled: true,
+            scopes: vec![],
+            require_mfa: false,
+            mfa_enroll: false,
+        };
+
+        let out = format!("{:?} {:?} {:?} {:?}", req, c, t, u);
+        for secret in &["hunter2", "314159", "clienthash", "JBSWY3DPEHPK3PXP", "userhash"] {
+            assert!(!out.contains(secret), "{} in {}", secret, out);
+        }
+        assert!(out.contains("alice") && out.contains("reports"), "{}", out);
+    }
+}
@@ -0,0 +1,191 @@
+/*
+ * The changes an admin makes, shared by the admin api and authsrv-admin.
+ * Each checks what it is given, makes the change, and records it in the
+ * audit log. The actor is the admin's token, or None from the command line.
+ * Deciding who may ask is left to the caller.
+ */
+use std::time::{Duration, SystemTime};
+
+use crate::{Result, Error};
+use crate::bootstrap::ADMIN_SCOPE;
+use crate::rocktypes::CachedDb;
+use crate::model::{user, scopes, token, refresh, totp, audit, name_valid};
+use crate::model::token::Token;
+
+// Fail unless every scope wanted can still be granted.
+async fn check_scopes(cdb: &CachedDb<'_>, want: &[String]) -> Result<()> {
+    let active = scopes::get_scopes(cdb).await?;
+    match want.iter().find(|s| !active.contains(*s)) {
+        Some(bad) => Err(Error::BadScopes(format!("no scope {}", bad))),
+        None => Ok(()),
+    }
+}
+
+pub struct NewUser<'a> {
+    pub name: &'a str,
+    pub secret: &'a str,
+    pub life: u64, // seconds
+    pub scopes: Vec<String>,
+    pub require_mfa: bool,
+    pub mfa_enroll: bool,
+}
+
+pub async fn create_user(cdb: &CachedDb<'_>, actor: Option<&Token>, nu: NewUser<'_>) -> Result<()> {
+    if !name_valid(nu.name) {
+        return Err(Error::Invalid("bad user name".to_owned()));
+    }
+    check_scopes(cdb, &nu.scopes).await?;
+
+    let hash = cdb.serv.password.hash(&cdb.serv.rng, nu.secret).await?;
+    let detail = format!("scopes={} require_mfa={} mfa_enroll={}", nu.scopes.join(","), nu.require_mfa, nu.mfa_enroll);
+    let u = user::User {
+        name: nu.name.to_owned(),
+        hash: hash,
+        expiration: SystemTime::now() + Duration::from_secs(nu.life),
+        enabled: true,
+        scopes: nu.scopes,
+        require_mfa: nu.require_mfa,
+        mfa_enroll: nu.mfa_enroll,
+    };
+    // the db insert fails with a conflict if the user already exists
+    user::put_user(cdb, u).await?;
+    audit::record(cdb, "user_created", actor, Some(nu.name), detail).await;
+    Ok(())
+}
+
+// Changes to a user. None fields are left alone.
+#[derive(Default)]
+pub struct UserChanges<'a> {
+    pub secret: Option<&'a str>,
+    pub life: Option<u64>,
+    pub enabled: Option<bool>,
+    pub scopes: Option<Vec<String>>,
+    pub require_mfa: Option<bool>,
+    pub mfa_enroll: Option<bool>,
+}
+
+/*
+ * Change a user. One that can no longer log in shouldn't keep using the
+ * tokens it already has, so they are revoked. Returns how many were.
+ */
+pub async fn update_user(cdb: &CachedDb<'_>, actor: Option<&Token>, name: &str, ch: UserChanges<'_>) -> Result<usize> {
+    let mut u = user::get_user(cdb, name.to_owned()).await?;
+    let mut changes: Vec<String> = Vec::new();
+
+    if let Some(secret) = ch.secret {
+        u.hash = cdb.serv.password.hash(&cdb.serv.rng, secret).await?;
+        changes.push("secret".to_owned());
+    }
+    if let Some(life) = ch.life {
+        u.expiration = SystemTime::now() + Duration::from_secs(life);
+        changes.push(format!("life={}", life));
+    }
+    if let Some(enabled) = ch.enabled {
+        u.enabled = enabled;
+        changes.push(format!("enabled={}", enabled));
+    }
+    if let Some(require_mfa) = ch.require_mfa {
+        u.require_mfa = require_mfa;
+        changes.push(format!("require_mfa={}", require_mfa));
+    }
+    if let Some(mfa_enroll) = ch.mfa_enroll {
+        u.mfa_enroll = mfa_enroll;
+        changes.push(format!("mfa_enroll={}", mfa_enroll));
+    }
+    if let Some(want) = ch.scopes {
+        check_scopes(cdb, &want).await?;
+        u.scopes = want;
+        changes.push(format!("scopes={}", u.scopes.join(",")));
+    }
+
+    let revoke = !u.is_enabled();
+    user::update_user(cdb, u).await?;
+    let mut cnt = 0;
+    if revoke {
+        cnt = revoke_all(cdb, name).await?;
+    }
+    audit::record(cdb, "user_updated", actor, Some(name), changes.join(" ")).await;
+    Ok(cnt)
+}
+
+// Every access and refresh token a user has. Returns the number of access tokens.
+async fn revoke_all(cdb: &CachedDb<'_>, name: &str) -> Result<usize> {
+    let cnt = token::del_user_tokens(cdb, name.to_owned()).await?;
+    refresh::del_user_refresh(cdb, name.to_owned()).await?;
+    Ok(cnt)
+}
+
+// Revoke every token a user has, leaving the user able to log in again. Returns how many were.
+pub async fn revoke_user_tokens(cdb: &CachedDb<'_>, actor: Option<&Token>, name: &str) -> Result<usize> {
+    let cnt = revoke_all(cdb, name).await?;
+    audit::record(cdb, "token_revoked", actor, Some(name), format!("admin all={}", cnt)).await;
+    Ok(cnt)
+}
+
+// Delete a user along with its tokens and second factor.
+pub async fn delete_user(cdb: &CachedDb<'_>, actor: Option<&Token>, name: &str) -> Result<()> {
+    let cnt = user::del_user(cdb, name.to_owned()).await?;
+    // cleared even when the user is already gone, in case it was deleted some other way
+    revoke_all(cdb, name).await?;
+    totp::del_totp(cdb, name.to_owned()).await?;
+    if cnt == 0 {
+        return Err(Error::NotFound);
+    }
+    audit::record(cdb, "user_deleted", actor, Some(name), String::new()).await;
+    Ok(())
+}
+
+pub async fn create_scope(cdb: &CachedDb<'_>, actor: Option<&Token>, name: &str) -> Result<()> {
+    if !name_valid(name) {
+        return Err(Error::Invalid("bad scope name".to_owned()));
+    }
+    scopes::put_scope(cdb, &name.to_owned()).await?;
+    audit::record(cdb, "scope_created", actor, Some(name), String::new()).await;
+    Ok(())
+}
+
+// Retiring or deleting authadmin would lock every admin out.
+pub async fn update_scope(cdb: &CachedDb<'_>, actor: Option<&Token>, name: &str, ch: scopes::ScopeChanges) -> Result<()> {
+    if ch.description.is_none() && ch.retired.is_none() && ch.require_mfa.is_none() {
+        return Err(Error::Invalid("nothing to change".to_owned()));
+    }
+    if name == ADMIN_SCOPE && ch.retired == Some(true) {
+        return Err(Error::BadScopes("authadmin can't be retired".to_owned()));
+    }
+
+    let mut detail: Vec<String> = Vec::new();
+    if let Some(retired) = ch.retired {
+        detail.push(format!("retired={}", retired));
+    }
+    if let Some(require_mfa) = ch.require_mfa {
+        detail.push(format!("require_mfa={}", require_mfa));
+    }
+    if ch.description.is_some() {
+        detail.push("description".to_owned());
+    }
+    scopes::update_scope(cdb, name.to_owned(), ch).await?;
+    audit::record(cdb, "scope_updated", actor, Some(name), detail.join(" ")).await;
+    Ok(())
+}
+
+pub async fn delete_scope(cdb: &CachedDb<'_>, actor: Option<&Token>, name: &str) -> Result<()> {
+    if name == ADMIN_SCOPE {
+        return Err(Error::BadScopes("authadmin can't be deleted".to_owned()));
+    }
+    if scopes::del_scope(cdb, name.to_owned()).await? == 0 {
+        return Err(Error::NotFound);
+    }
+    audit::record(cdb, "scope_deleted", actor, Some(name), String::new()).await;
+    Ok(())
+}
+
+// Revoke a token as it was presented, along with its refresh token's family.
+pub async fn revoke_token(cdb: &CachedDb<'_>, actor: Option<&Token>, presented: String) -> Result<()> {
+    let name = token::token_id(cdb, presented);
+    let owner = token::get_token(cdb, name.clone()).await.ok().map(|t| t.username);
+    if token::revoke(cdb, name).await? == 0 {
+        return Err(Error::NotFound);
+    }
+    audit::record(cdb, "token_revoked", actor, owner.as_deref(), "admin".to_owned()).await;
+    Ok(())
+}
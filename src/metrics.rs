@@ -0,0 +1,131 @@
+
+/*
+ * Counters for the /metrics endpoint, in the Prometheus text format.
+ * Requests are counted by a fairing, everything else where it happens.
+ * Gauges for things we can just look at are only set when scraped.
+ */
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+use prometheus::{Encoder, TextEncoder, Registry, Opts, HistogramOpts};
+use prometheus::{IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Histogram, HistogramVec};
+use rocket::{Request, Response, Data};
+use rocket::fairing::{self, Info, Kind};
+use tracing::error;
+
+use crate::ServerState;
+
+// argon2 runs take tens to hundreds of milliseconds
+const HASH_BUCKETS: &[f64] = &[0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    pub logins: IntCounterVec, // by outcome, "ok" or an error code
+    pub cache_lookups: IntCounterVec, // by result, "local_hit", "hit" or "miss"
+    pub hash_seconds: Histogram,
+    pub tokens_issued: IntCounter,
+    pub tokens_revoked: IntCounter,
+    pub audit_failures: IntCounter,
+    pool_in_use: IntGaugeVec,
+    pool_size: IntGaugeVec,
+    hashes_running: IntGauge,
+    hashes_waiting: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Metrics> {
+        let m = Metrics {
+            registry: Registry::new(),
+            requests: IntCounterVec::new(
+                Opts::new("authsrv_http_requests_total", "HTTP requests by route and status"),
+                &["method", "route", "status"])?,
+            latency: HistogramVec::new(
+                HistogramOpts::new("authsrv_http_request_duration_seconds", "HTTP request latency by route"),
+                &["method", "route"])?,
+            logins: IntCounterVec::new(
+                Opts::new("authsrv_logins_total", "Logins by outcome"),
+                &["outcome"])?,
+            cache_lookups: IntCounterVec::new(
+                Opts::new("authsrv_cache_lookups_total", "Cache lookups by result"),
+                &["result"])?,
+            hash_seconds: Histogram::with_opts(
+                HistogramOpts::new("authsrv_password_hash_duration_seconds", "Time spent hashing and verifying passwords")
+                    .buckets(HASH_BUCKETS.to_vec()))?,
+            tokens_issued: IntCounter::new("authsrv_tokens_issued_total", "Access tokens issued")?,
+            tokens_revoked: IntCounter::new("authsrv_tokens_revoked_total", "Access tokens revoked or deleted before they expired")?,
+            audit_failures: IntCounter::new("authsrv_audit_failures_total", "Audit events that couldn't be written")?,
+            pool_in_use: IntGaugeVec::new(
+                Opts::new("authsrv_pool_connections_in_use", "Connections checked out by requests"),
+                &["pool"])?,
+            pool_size: IntGaugeVec::new(
+                Opts::new("authsrv_pool_connections_max", "Most connections a pool will hand out"),
+                &["pool"])?,
+            hashes_running: IntGauge::new("authsrv_password_hashes_running", "Password hashes running")?,
+            hashes_waiting: IntGauge::new("authsrv_password_hashes_waiting", "Password hashes waiting to run")?,
+        };
+        m.registry.register(Box::new(m.requests.clone()))?;
+        m.registry.register(Box::new(m.latency.clone()))?;
+        m.registry.register(Box::new(m.logins.clone()))?;
+        m.registry.register(Box::new(m.cache_lookups.clone()))?;
+        m.registry.register(Box::new(m.hash_seconds.clone()))?;
+        m.registry.register(Box::new(m.tokens_issued.clone()))?;
+        m.registry.register(Box::new(m.tokens_revoked.clone()))?;
+        m.registry.register(Box::new(m.audit_failures.clone()))?;
+        m.registry.register(Box::new(m.pool_in_use.clone()))?;
+        m.registry.register(Box::new(m.pool_size.clone()))?;
+        m.registry.register(Box::new(m.hashes_running.clone()))?;
+        m.registry.register(Box::new(m.hashes_waiting.clone()))?;
+        Ok(m)
+    }
+
+    // Everything in the text format, after setting the gauges from serv.
+    pub fn render(&self, serv: &ServerState) -> String {
+        let db = serv.storage.database();
+        self.pool_in_use.with_label_values(&[db]).set(serv.db_in_use.load(Ordering::Relaxed) as i64);
+        self.pool_size.with_label_values(&[db]).set(serv.db_pool_size as i64);
+        self.pool_in_use.with_label_values(&["redis"]).set(serv.cache_in_use.load(Ordering::Relaxed) as i64);
+        self.pool_size.with_label_values(&["redis"]).set(serv.cache_pool_size as i64);
+
+        let hs = serv.password.stats();
+        self.hashes_running.set(hs.running as i64);
+        self.hashes_waiting.set(hs.waiting as i64);
+
+        let mut buf = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buf) {
+            error!(error = %e, "encoding metrics failed");
+        }
+        String::from_utf8(buf).unwrap_or_default()
+    }
+}
+
+// When a request arrived, kept with the request.
+struct Started(Instant);
+
+// Counts and times every request by the route that answered it.
+pub struct Fairing;
+
+#[rocket::async_trait]
+impl fairing::Fairing for Fairing {
+    fn info(&self) -> Info {
+        Info { name: "Request Metrics", kind: Kind::Request | Kind::Response }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        req.local_cache(|| Started(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let serv = match req.rocket().state::<ServerState>() {
+            Some(serv) => serv,
+            None => return,
+        };
+        let started = req.local_cache(|| Started(Instant::now()));
+        // unmatched requests are lumped together, so scanners can't blow up the label count
+        let route = req.route().map(|r| r.uri.as_str()).unwrap_or("none");
+        let method = req.method().as_str();
+        let status = res.status().code.to_string();
+        serv.metrics.requests.with_label_values(&[method, route, &status]).inc();
+        serv.metrics.latency.with_label_values(&[method, route]).observe(started.0.elapsed().as_secs_f64());
+    }
+}
@@ -0,0 +1,75 @@
+
+use rocket::serde::Serialize;
+use std::time::SystemTime;
+use tracing::error;
+
+use crate::Result;
+use crate::rocktypes::CachedDb;
+use crate::model::token::Token;
+use crate::model::schema::audit_events;
+
+/*
+ * The audit log. Events are only ever added, and never cached.
+ */
+#[derive(Debug, Clone, Serialize, Queryable)]
+#[serde(crate = "rocket::serde")]
+pub struct Event {
+    pub id: i64,
+    pub time: SystemTime,
+    pub event: String,
+    pub actor: Option<String>,
+    pub actor_token: Option<String>,
+    pub subject: Option<String>,
+    pub client_ip: Option<String>,
+    pub detail: String,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="audit_events"]
+pub struct NewEvent {
+    pub event: &'static str,
+    pub actor: Option<String>,
+    pub actor_token: Option<String>,
+    pub subject: Option<String>,
+    pub client_ip: Option<String>,
+    pub detail: String,
+}
+
+// What to look for in the log. None fields match anything.
+#[derive(Debug)]
+pub struct Filter {
+    pub user: Option<String>, // matches either the actor or the subject
+    pub event: Option<String>,
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+}
+
+// Token names are already digests, so the log only keeps enough to tell them apart.
+fn fingerprint(tok: &Token) -> String {
+    tok.token.chars().take(16).collect()
+}
+
+/*
+ * Add an event to the log. actor is the token the request was made with, if any.
+ * The address logged is the one throttling uses, which a client can't forge.
+ * Failing to write the log shouldn't fail the request, so errors are only
+ * reported, in the error log and the audit failures metric to alert on.
+ */
+pub async fn record(cdb: &CachedDb<'_>, event: &'static str, actor: Option<&Token>, subject: Option<&str>, detail: String) {
+    let ev = NewEvent {
+        event: event,
+        actor: actor.map(|t| t.username.clone()),
+        actor_token: actor.map(fingerprint),
+        subject: subject.map(|s| s.to_owned()),
+        client_ip: cdb.ip.map(|ip| ip.to_string()),
+        detail: detail,
+    };
+    if let Err(e) = cdb.serv.storage.insert_event(cdb, ev).await {
+        cdb.serv.metrics.audit_failures.inc();
+        error!(event = event, error = %e, "audit failed");
+    }
+}
+
+pub async fn list_events(cdb: &CachedDb<'_>, f: Filter, offset: i64, limit: i64) -> Result<Vec<Event>> {
+    cdb.serv.storage.list_events(cdb, f, offset, limit).await
+}
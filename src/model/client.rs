@@ -0,0 +1,111 @@
+use std::fmt;
+use std::sync::Arc;
+use rocket::serde::{Serialize, Deserialize};
+use std::time::SystemTime;
+
+use crate::{Result, Error};
+use crate::rocktypes::CachedDb;
+use crate::cache;
+use crate::logging::Redacted;
+use crate::model::schema::clients;
+
+// The grants a client can be allowed to use at /oauth/token.
+pub const GRANT_TYPES: &[&str] = &["password", "client_credentials", "refresh_token"];
+
+/*
+ * An application registered to get tokens. With client_credentials it gets
+ * tokens of its own, named for its client_id. With password it gets them for
+ * its users, and only ever within its own scopes.
+ */
+#[derive(Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[table_name="clients"]
+pub struct Client {
+    pub client_id: String,
+    pub secrets: Vec<String>, // hashes, oldest first
+    pub scopes: Vec<String>,
+    pub grant_types: Vec<String>,
+    pub redirect_uris: Vec<String>,
+    pub owner: String,
+    pub enabled: bool,
+    pub created: SystemTime,
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("client_id", &self.client_id)
+            .field("secrets", &Redacted)
+            .field("scopes", &self.scopes)
+            .field("grant_types", &self.grant_types)
+            .field("redirect_uris", &self.redirect_uris)
+            .field("owner", &self.owner)
+            .field("enabled", &self.enabled)
+            .field("created", &self.created)
+            .finish()
+    }
+}
+
+impl Client {
+    pub fn allows(&self, grant: &str) -> bool {
+        self.grant_types.iter().any(|g| g == grant)
+    }
+}
+
+impl cache::Expires for Client {}
+
+fn cache_key(k: &str) -> Arc<String> {
+    Arc::new(format!("client_{}", k))
+}
+
+pub async fn get_client(cdb: &CachedDb<'_>, client_id: String) -> Result<Client> {
+    let key = cache_key(&client_id);
+    if let Some(c) = cache::get(cdb, key.clone()).await {
+        return Ok(c);
+    }
+
+    let c = cdb.serv.storage.get_client(cdb, client_id).await?;
+    let _ = cache::put(cdb, key, &c).await; // ignore any errors
+
+    Ok(c)
+}
+
+// Drop a client from the cache, so the next lookup goes to the db.
+pub async fn evict(cdb: &CachedDb<'_>, client_id: &str) -> Result<()> {
+    cache::del(cdb, cache_key(client_id)).await
+}
+
+// A client can't take a user's name, see user::put_user.
+pub async fn put_client(cdb: &CachedDb<'_>, c: Client) -> Result<()> {
+    match cdb.serv.storage.get_user(cdb, c.client_id.clone()).await {
+        Ok(_) => return Err(Error::Conflict(format!("{} is a user", c.client_id))),
+        Err(Error::NotFound) => {},
+        Err(e) => return Err(e),
+    }
+    let key = cache_key(&c.client_id);
+    let _ = cache::del(cdb, key).await; // ignore any errors
+    cdb.serv.storage.insert_client(cdb, c).await
+}
+
+pub async fn list_clients(cdb: &CachedDb<'_>, offset: i64, limit: i64) -> Result<Vec<Client>> {
+    cdb.serv.storage.list_clients(cdb, offset, limit).await
+}
+
+pub async fn update_client(cdb: &CachedDb<'_>, c: Client) -> Result<()> {
+    let key = cache_key(&c.client_id);
+    let cnt = cdb.serv.storage.update_client(cdb, c).await?;
+    // a stale copy elsewhere could still let a disabled client in
+    cache::del(cdb, key).await?;
+    if cnt == 0 {
+        return Err(Error::NotFound);
+    }
+    Ok(())
+}
+
+pub async fn del_client(cdb: &CachedDb<'_>, client_id: String) -> Result<usize> {
+    let key = cache_key(&client_id);
+    let cnt = cdb.serv.storage.del_client(cdb, client_id).await?;
+    cache::del(cdb, key).await?;
+    Ok(cnt)
+}
+
@@ -1,4 +1,22 @@
+pub mod audit;
+pub mod client;
+pub mod refresh;
+pub mod revoked;
 pub mod schema;
 pub mod scopes;
 pub mod token;
+pub mod totp;
 pub mod user;
+
+use ring::digest;
+use hex::ToHex;
+
+// User, scope and client names are stored as varchar(16)
+pub fn name_valid(name: &str) -> bool {
+    !name.is_empty() && name.chars().count() <= 16
+}
+
+// Tokens are stored and cached under a digest of the secret, never the secret itself.
+pub fn hash_secret(secret: &str) -> String {
+    digest::digest(&digest::SHA256, secret.as_bytes()).as_ref().encode_hex()
+}
@@ -0,0 +1,94 @@
+
+use std::fmt;
+use rocket::serde::{Serialize, Deserialize};
+use std::time::SystemTime;
+
+use crate::Result;
+use crate::rocktypes::CachedDb;
+use crate::logging::Redacted;
+use crate::model::token;
+use crate::model::schema::refresh_tokens;
+
+/*
+ * Refresh tokens are never cached. Each one can be used only once,
+ * and the db is the only place that can say so reliably.
+ */
+#[derive(Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[table_name="refresh_tokens"]
+pub struct RefreshToken {
+    pub token: String,
+    pub family: String,
+    pub access: String,
+    pub username: String,
+    pub expiration: SystemTime,
+    pub scopes: Vec<String>,
+    pub used: bool,
+    pub client_id: Option<String>,
+}
+
+impl fmt::Debug for RefreshToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RefreshToken")
+            .field("token", &Redacted)
+            .field("family", &self.family)
+            .field("access", &Redacted)
+            .field("username", &self.username)
+            .field("expiration", &self.expiration)
+            .field("scopes", &self.scopes)
+            .field("used", &self.used)
+            .field("client_id", &self.client_id)
+            .finish()
+    }
+}
+
+impl RefreshToken {
+    pub fn is_expired(&self) -> bool {
+        self.expiration.duration_since(SystemTime::now()).is_err()
+    }
+}
+
+pub async fn get_refresh(cdb: &CachedDb<'_>, name: String) -> Result<RefreshToken> {
+    cdb.serv.storage.get_refresh(cdb, name).await
+}
+
+pub async fn put_refresh(cdb: &CachedDb<'_>, tok: &RefreshToken) -> Result<()> {
+    cdb.serv.storage.insert_refresh(cdb, tok.clone()).await
+}
+
+// Mark a refresh token as used. Returns false if it was already used, so only one caller can win.
+pub async fn mark_used(cdb: &CachedDb<'_>, name: String) -> Result<bool> {
+    cdb.serv.storage.mark_used(cdb, name).await
+}
+
+// The family of the refresh token issued alongside an access token, if there was one.
+pub async fn family_of(cdb: &CachedDb<'_>, access: String) -> Result<Option<String>> {
+    cdb.serv.storage.refresh_family(cdb, access).await
+}
+
+// Delete every refresh token in a family and return the access tokens they were issued with.
+pub async fn del_family(cdb: &CachedDb<'_>, family: String) -> Result<Vec<String>> {
+    cdb.serv.storage.del_family(cdb, family).await
+}
+
+// Revoke every refresh token in a family and every access token they issued.
+pub async fn revoke_family(cdb: &CachedDb<'_>, family: String) -> Result<usize> {
+    let access = del_family(cdb, family).await?;
+    let mut cnt = 0;
+    for tok in access.into_iter() {
+        cnt += token::del_token(cdb, tok).await?;
+    }
+    Ok(cnt)
+}
+
+pub async fn del_user_refresh(cdb: &CachedDb<'_>, user: String) -> Result<usize> {
+    cdb.serv.storage.del_user_refresh(cdb, user).await
+}
+
+pub async fn del_client_refresh(cdb: &CachedDb<'_>, client_id: String) -> Result<usize> {
+    cdb.serv.storage.del_client_refresh(cdb, client_id).await
+}
+
+pub async fn clean(cdb: &CachedDb<'_>) -> Result<usize> {
+    cdb.serv.storage.clean_refresh(cdb).await
+}
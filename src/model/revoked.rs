@@ -0,0 +1,39 @@
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::Result;
+use crate::rocktypes::CachedDb;
+use crate::cache;
+
+fn cache_key(k: &str) -> Arc<String> {
+    Arc::new(format!("revoked_{}", k))
+}
+
+pub async fn is_revoked(cdb: &CachedDb<'_>, jti: String) -> Result<bool> {
+    let key = cache_key(&jti);
+    if let Some(x) = cache::get(cdb, key.clone()).await {
+        return Ok(x);
+    }
+
+    let x = cdb.serv.storage.is_revoked(cdb, jti).await?;
+    let _ = cache::put(cdb, key, &x).await; // ignore any errors
+    Ok(x)
+}
+
+// Remember that a token was revoked until it would have expired anyway.
+pub async fn revoke(cdb: &CachedDb<'_>, jti: String, expiration: SystemTime) -> Result<()> {
+    let key = cache_key(&jti);
+    cdb.serv.storage.revoke(cdb, jti, expiration).await?;
+    // other nodes may still have it cached as not revoked
+    cache::del(cdb, key).await
+}
+
+// Drop a jti from the cache, so the next check goes to the db.
+pub async fn evict(cdb: &CachedDb<'_>, jti: &str) -> Result<()> {
+    cache::del(cdb, cache_key(jti)).await
+}
+
+pub async fn clean(cdb: &CachedDb<'_>) -> Result<usize> {
+    cdb.serv.storage.clean_revoked(cdb).await
+}
@@ -1,6 +1,63 @@
+table! {
+    audit_events (id) {
+        id -> Int8,
+        time -> Timestamp,
+        event -> Varchar,
+        actor -> Nullable<Varchar>,
+        actor_token -> Nullable<Varchar>,
+        subject -> Nullable<Varchar>,
+        client_ip -> Nullable<Varchar>,
+        detail -> Text,
+    }
+}
+
+table! {
+    clients (client_id) {
+        client_id -> Varchar,
+        secrets -> Array<Text>,
+        scopes -> Array<Text>,
+        grant_types -> Array<Text>,
+        redirect_uris -> Array<Text>,
+        owner -> Varchar,
+        enabled -> Bool,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    recovery_codes (username, hash) {
+        username -> Varchar,
+        hash -> Varchar,
+    }
+}
+
+table! {
+    refresh_tokens (token) {
+        token -> Varchar,
+        family -> Varchar,
+        access -> Varchar,
+        username -> Varchar,
+        expiration -> Timestamp,
+        scopes -> Array<Text>,
+        used -> Bool,
+        client_id -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    revoked_tokens (jti) {
+        jti -> Varchar,
+        expiration -> Timestamp,
+    }
+}
+
 table! {
     scopes (name) {
         name -> Varchar,
+        description -> Text,
+        created -> Timestamp,
+        retired -> Bool,
+        require_mfa -> Bool,
     }
 }
 
@@ -10,6 +67,17 @@ table! {
         username -> Varchar,
         expiration -> Timestamp,
         scopes -> Array<Text>,
+        issued -> Timestamp,
+        client_id -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    totp (username) {
+        username -> Varchar,
+        secret -> Varchar,
+        confirmed -> Bool,
+        last_step -> Int8,
     }
 }
 
@@ -20,11 +88,19 @@ table! {
         expiration -> Timestamp,
         enabled -> Bool,
         scopes -> Array<Text>,
+        require_mfa -> Bool,
+        mfa_enroll -> Bool,
     }
 }
 
 allow_tables_to_appear_in_same_query!(
+    audit_events,
+    clients,
+    recovery_codes,
+    refresh_tokens,
+    revoked_tokens,
     scopes,
     tokens,
+    totp,
     users,
 );
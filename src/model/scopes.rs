@@ -1,41 +1,126 @@
 
 use std::sync::Arc;
+use std::time::SystemTime;
 use rocket::serde::{Serialize, Deserialize};
-use rocket_sync_db_pools::diesel::prelude::*;
 
-use crate::{Result, errstr};
+use crate::{Result, Error};
 use crate::rocktypes::CachedDb;
 use crate::cache;
+use crate::model::{user, client, token, revoked};
 use crate::model::schema::scopes;
 
-// XXX do we need a separate type here?
 #[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
 #[serde(crate = "rocket::serde")]
 #[table_name="scopes"]
 pub struct Scope {
     pub name: String,
+    pub description: String,
+    pub created: SystemTime,
+    pub retired: bool,
+    pub require_mfa: bool,
+}
+
+// Fields of a scope that can be changed after it is created. None fields are left alone.
+#[derive(Debug, AsChangeset)]
+#[table_name="scopes"]
+pub struct ScopeChanges {
+    pub description: Option<String>,
+    pub retired: Option<bool>,
+    pub require_mfa: Option<bool>,
+}
+
+// What deleting a scope changed, so the cached copies can be dropped.
+#[derive(Debug, Default)]
+pub struct ScopeRemoval {
+    pub scopes: usize, // 0 if there was no such scope
+    pub users: Vec<String>,
+    pub clients: Vec<String>,
+    pub tokens: Vec<String>, // that lost the scope, or were revoked for having it
 }
 
 fn cache_key() -> Arc<String> {
     Arc::new("scopes".to_string())
 }
 
+fn mfa_cache_key() -> Arc<String> {
+    Arc::new("scopes_mfa".to_string())
+}
+
+// Drop the scope lists from the cache, so the next lookup goes to the db.
+pub async fn evict(cdb: &CachedDb<'_>) -> Result<()> {
+    cache::del(cdb, cache_key()).await?;
+    cache::del(cdb, mfa_cache_key()).await
+}
+
+// Get the names of all scopes that can still be granted.
 pub async fn get_scopes(cdb: &CachedDb<'_>) -> Result<Vec<String>> {
     let key = cache_key();
     if let Some(u) = cache::get(cdb, key.clone()).await {
         return Ok(u);
     }
 
-    let scopes: Vec<Scope> = cdb.db.run(move |c| scopes::table.load(c)).await.map_err(errstr)?;
-    let names = scopes.into_iter().map(|sc| sc.name).collect();
-    cache::put(cdb, key, &names).await;
+    let names = cdb.serv.storage.active_scopes(cdb).await?;
+    let _ = cache::put(cdb, key, &names).await; // ignore any errors
     Ok(names)
 }
 
+// Get the names of all scopes that need a second factor to be granted.
+pub async fn get_mfa_scopes(cdb: &CachedDb<'_>) -> Result<Vec<String>> {
+    let key = mfa_cache_key();
+    if let Some(u) = cache::get(cdb, key.clone()).await {
+        return Ok(u);
+    }
+
+    let names = cdb.serv.storage.mfa_scopes(cdb).await?;
+    let _ = cache::put(cdb, key, &names).await; // ignore any errors
+    Ok(names)
+}
+
+// Get all scopes, including retired ones.
+pub async fn list_scopes(cdb: &CachedDb<'_>) -> Result<Vec<Scope>> {
+    cdb.serv.storage.list_scopes(cdb).await
+}
+
 pub async fn put_scope(cdb: &CachedDb<'_>, newscope: &String) -> Result<()> {
-    let key = cache_key();
-    cache::del(cdb, key).await;
-    let scope = Scope{ name: newscope.clone() };
-    cdb.db.run(move |c| diesel::insert_into(scopes::table).values(scope).execute(c)).await.map_err(errstr)?;
+    let _ = evict(cdb).await; // ignore any errors
+    cdb.serv.storage.insert_scope(cdb, newscope.clone()).await
+}
+
+pub async fn update_scope(cdb: &CachedDb<'_>, name: String, changes: ScopeChanges) -> Result<()> {
+    let cnt = cdb.serv.storage.update_scope(cdb, name, changes).await?;
+    let _ = evict(cdb).await; // ignore any errors
+    if cnt == 0 {
+        return Err(Error::NotFound);
+    }
     Ok(())
 }
+
+/*
+ * Delete a scope and take it away from every user, client and outstanding
+ * token, all in one transaction. Only once that has committed are the
+ * cached copies dropped, so none can be cached again from before it.
+ */
+pub async fn del_scope(cdb: &CachedDb<'_>, name: String) -> Result<usize> {
+    // a JWT carries its scopes with it, so the only way to take one away is to revoke the token
+    let revoke = cdb.serv.jwt.is_some();
+    let x = cdb.serv.storage.del_scope(cdb, name, revoke).await?;
+
+    if revoke {
+        cdb.serv.metrics.tokens_revoked.inc_by(x.tokens.len() as u64);
+    }
+    // every copy is tried, but one left behind would keep the scope working on other nodes
+    let mut res = evict(cdb).await;
+    for name in x.users.iter() {
+        res = res.and(user::evict(cdb, name).await);
+    }
+    for id in x.clients.iter() {
+        res = res.and(client::evict(cdb, id).await);
+    }
+    for name in x.tokens.iter() {
+        res = res.and(token::evict(cdb, name).await);
+        if revoke {
+            res = res.and(revoked::evict(cdb, name).await);
+        }
+    }
+    res.map(|_| x.scopes)
+}
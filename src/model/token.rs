@@ -1,15 +1,18 @@
 
+use std::fmt;
 use std::sync::Arc;
 use rocket::serde::{Serialize, Deserialize};
-use rocket_sync_db_pools::diesel::prelude::*;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::{Result, errstr};
+use crate::{Result, Error};
 use crate::rocktypes::CachedDb;
 use crate::cache;
+use crate::logging::Redacted;
+use crate::jwt;
+use crate::model::{hash_secret, refresh, revoked};
 use crate::model::schema::tokens;
 
-#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[derive(Clone, Deserialize, Serialize, Queryable, Insertable)]
 #[serde(crate = "rocket::serde")]
 #[table_name="tokens"]
 pub struct Token {
@@ -17,6 +20,22 @@ pub struct Token {
     pub username: String,
     pub expiration: SystemTime,
     pub scopes: Vec<String>,
+    pub issued: SystemTime,
+    #[serde(default)]
+    pub client_id: Option<String>, // the client that asked for it, if any
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("token", &Redacted)
+            .field("username", &self.username)
+            .field("expiration", &self.expiration)
+            .field("scopes", &self.scopes)
+            .field("issued", &self.issued)
+            .field("client_id", &self.client_id)
+            .finish()
+    }
 }
 
 impl Token {
@@ -29,38 +48,179 @@ impl Token {
     }
 }
 
+impl cache::Expires for Token {
+    fn expires(&self) -> Option<SystemTime> {
+        Some(self.expiration)
+    }
+}
+
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// The claims of a JWT for the token, carrying the secret it was stored under the hash of.
+pub fn claims(tok: &Token, secret: String) -> jwt::Claims {
+    jwt::Claims {
+        sub: tok.username.clone(),
+        scope: tok.scopes.join(" "),
+        exp: unix_secs(tok.expiration),
+        iat: unix_secs(tok.issued),
+        jti: secret,
+        client_id: tok.client_id.clone(),
+    }
+}
+
+impl From<jwt::Claims> for Token {
+    fn from(claims: jwt::Claims) -> Self {
+        Token {
+            token: hash_secret(&claims.jti),
+            username: claims.sub,
+            expiration: UNIX_EPOCH + Duration::from_secs(claims.exp),
+            scopes: claims.scope.split_whitespace().map(|s| s.to_owned()).collect(),
+            issued: UNIX_EPOCH + Duration::from_secs(claims.iat),
+            client_id: claims.client_id,
+        }
+    }
+}
+
 fn cache_key(k: &str) -> Arc<String> {
     Arc::new(format!("token_{}", k))
 }
 
+// Verify a JWT and return its claims, or None if it isn't a JWT we should check.
+fn verify_jwt(cdb: &CachedDb<'_>, presented: &str) -> Option<Result<jwt::Claims>> {
+    let keys = cdb.serv.jwt.as_ref()?;
+    if !jwt::is_jwt(presented) {
+        return None;
+    }
+    Some(keys.verify(presented).ok_or(Error::NotFound))
+}
+
+/*
+ * Lookup the token a client presented. JWTs are verified locally and only
+ * need a check against the revocation list. Anything else is looked up by
+ * its hash, unless we issue JWTs: then a JWT's jti is the secret its stored
+ * token is named for, and anyone holding the JWT can read it, so nothing else
+ * is accepted.
+ */
+pub async fn lookup_token(cdb: &CachedDb<'_>, presented: String) -> Result<Token> {
+    match verify_jwt(cdb, &presented) {
+        Some(claims) => {
+            let tok = Token::from(claims?);
+            if revoked::is_revoked(cdb, tok.token.clone()).await? {
+                return Err(Error::NotFound);
+            }
+            Ok(tok)
+        },
+        None if cdb.serv.jwt.is_some() => Err(Error::NotFound),
+        None => get_token(cdb, hash_secret(&presented)).await,
+    }
+}
+
+// The name a presented token is stored under.
+pub fn token_id(cdb: &CachedDb<'_>, presented: String) -> String {
+    match verify_jwt(cdb, &presented) {
+        Some(Ok(claims)) => hash_secret(&claims.jti),
+        _ => hash_secret(&presented),
+    }
+}
+
 pub async fn get_token(cdb: &CachedDb<'_>, name: String) -> Result<Token> {
     let key = cache_key(&name);
     if let Some(x) = cache::get(cdb, key.clone()).await {
         return Ok(x);
     }
 
-    let x = cdb.db.run(move |c| tokens::table.filter(tokens::token.eq(&name)).first(c)).await.map_err(errstr)?;
-    cache::put(cdb, key, &x).await;
+    let x = cdb.serv.storage.get_token(cdb, name).await?;
+    let _ = cache::put(cdb, key, &x).await; // ignore any errors
 
     Ok(x)
 }
 
+// Drop a token from the cache, so the next lookup goes to the db.
+pub async fn evict(cdb: &CachedDb<'_>, name: &str) -> Result<()> {
+    cache::del(cdb, cache_key(name)).await
+}
+
 pub async fn put_token(cdb: &CachedDb<'_>, tok: &Token) -> Result<()> {
-    let tok2 = tok.clone();
-    cdb.db.run(|c| diesel::insert_into(tokens::table).values(tok2).execute(c)).await.map_err(errstr)?;
+    cdb.serv.storage.insert_token(cdb, tok.clone()).await?;
+    cdb.serv.metrics.tokens_issued.inc();
     let key = cache_key(&tok.token);
     let _ = cache::put(cdb, key, tok).await; // ignore any errors
     Ok(())
 }
 
 pub async fn clean(cdb: &CachedDb<'_>) -> Result<usize> {
-    use diesel::dsl::now;
-    use self::tokens::dsl::*; // XXX figure out exactly what we need
-    let cnt = cdb.db.run(|c| 
-        diesel::delete(self::tokens::table)
-                .filter(expiration.lt(now))
-                .execute(c)
-            ).await.map_err(errstr)?;
+    cdb.serv.storage.clean_tokens(cdb).await
+}
+
+// JWTs are good until they expire unless we put them on the revocation list.
+async fn revoke_jwts(cdb: &CachedDb<'_>, toks: Vec<(String, SystemTime)>) -> Result<()> {
+    if cdb.serv.jwt.is_none() {
+        return Ok(());
+    }
+    for (name, exp) in toks.into_iter() {
+        revoked::revoke(cdb, name, exp).await?;
+    }
+    Ok(())
+}
+
+/*
+ * Evict revoked tokens from the cache. One left there would still work on
+ * other nodes, so this fails if any can't be, after trying them all.
+ */
+async fn evict_all(cdb: &CachedDb<'_>, names: &[String]) -> Result<()> {
+    let mut res = Ok(());
+    for name in names.iter() {
+        res = res.and(evict(cdb, name).await);
+    }
+    res
+}
+
+// Remove a token from the db and evict it from the cache so it can't be used anymore.
+pub async fn del_token(cdb: &CachedDb<'_>, name: String) -> Result<usize> {
+    let toks = cdb.serv.storage.del_token(cdb, name.clone()).await?;
+    let cnt = toks.len();
+    cdb.serv.metrics.tokens_revoked.inc_by(cnt as u64);
+    revoke_jwts(cdb, toks).await?;
+    // evicted even if the db no longer had it, in case it is a retry after the evict failed
+    evict(cdb, &name).await?;
+    Ok(cnt)
+}
+
+/*
+ * Revoke an access token, and the refresh token issued with it along with
+ * the rest of its family, or the refresh token would just get another.
+ * Returns the number of access tokens revoked.
+ */
+pub async fn revoke(cdb: &CachedDb<'_>, name: String) -> Result<usize> {
+    let family = refresh::family_of(cdb, name.clone()).await?;
+    let mut cnt = del_token(cdb, name).await?;
+    if let Some(family) = family {
+        cnt += refresh::revoke_family(cdb, family).await?;
+    }
+    Ok(cnt)
+}
+
+// Remove all of a user's tokens from the db and evict them from the cache.
+pub async fn del_user_tokens(cdb: &CachedDb<'_>, user: String) -> Result<usize> {
+    let toks = cdb.serv.storage.del_user_tokens(cdb, user).await?;
+    let names: Vec<String> = toks.iter().map(|(name, _)| name.clone()).collect();
+    let cnt = toks.len();
+    cdb.serv.metrics.tokens_revoked.inc_by(cnt as u64);
+    revoke_jwts(cdb, toks).await?;
+    evict_all(cdb, &names).await?;
+    Ok(cnt)
+}
+
+// Remove all of the tokens a client asked for from the db and evict them from the cache.
+pub async fn del_client_tokens(cdb: &CachedDb<'_>, client_id: String) -> Result<usize> {
+    let toks = cdb.serv.storage.del_client_tokens(cdb, client_id).await?;
+    let names: Vec<String> = toks.iter().map(|(name, _)| name.clone()).collect();
+    let cnt = toks.len();
+    cdb.serv.metrics.tokens_revoked.inc_by(cnt as u64);
+    revoke_jwts(cdb, toks).await?;
+    evict_all(cdb, &names).await?;
     Ok(cnt)
 }
 
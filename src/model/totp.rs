@@ -0,0 +1,66 @@
+
+use std::fmt;
+use rocket::serde::{Serialize, Deserialize};
+
+use crate::Result;
+use crate::rocktypes::CachedDb;
+use crate::logging::Redacted;
+use crate::model::schema::totp;
+
+/*
+ * A user's TOTP secret. Like refresh tokens these aren't cached,
+ * since last_step has to be checked and updated atomically.
+ */
+#[derive(Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[table_name="totp"]
+pub struct Totp {
+    pub username: String,
+    pub secret: String,
+    pub confirmed: bool,
+    pub last_step: i64,
+}
+
+impl fmt::Debug for Totp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Totp")
+            .field("username", &self.username)
+            .field("secret", &Redacted)
+            .field("confirmed", &self.confirmed)
+            .field("last_step", &self.last_step)
+            .finish()
+    }
+}
+
+pub async fn get_totp(cdb: &CachedDb<'_>, name: String) -> Result<Totp> {
+    cdb.serv.storage.get_totp(cdb, name).await
+}
+
+// Store an unconfirmed secret, replacing any earlier unconfirmed one.
+pub async fn put_totp(cdb: &CachedDb<'_>, t: Totp) -> Result<()> {
+    cdb.serv.storage.put_totp(cdb, t).await
+}
+
+pub async fn confirm_totp(cdb: &CachedDb<'_>, name: String, step: i64) -> Result<()> {
+    cdb.serv.storage.confirm_totp(cdb, name, step).await
+}
+
+// Record that a code for a time step was used. Returns false if that step, or a later one, was already used.
+pub async fn use_step(cdb: &CachedDb<'_>, name: String, step: i64) -> Result<bool> {
+    cdb.serv.storage.use_step(cdb, name, step).await
+}
+
+// Remove a user's second factor and its recovery codes.
+pub async fn del_totp(cdb: &CachedDb<'_>, name: String) -> Result<usize> {
+    cdb.serv.storage.del_totp(cdb, name).await
+}
+
+// Replace a user's recovery codes with a new set of code hashes.
+pub async fn put_recovery_codes(cdb: &CachedDb<'_>, name: String, hashes: Vec<String>) -> Result<()> {
+    cdb.serv.storage.put_recovery_codes(cdb, name, hashes).await
+}
+
+// Use up a recovery code. Returns false if the user has no such code.
+pub async fn use_recovery_code(cdb: &CachedDb<'_>, name: String, hash: String) -> Result<bool> {
+    cdb.serv.storage.use_recovery_code(cdb, name, hash).await
+}
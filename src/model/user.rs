@@ -1,16 +1,16 @@
 
+use std::fmt;
 use std::sync::Arc;
-use diesel;
 use rocket::serde::{Serialize, Deserialize};
-use rocket_sync_db_pools::diesel::prelude::*;
 use std::time::SystemTime;
 
-use crate::{Result, errstr};
+use crate::{Result, Error};
 use crate::rocktypes::CachedDb;
 use crate::cache;
+use crate::logging::Redacted;
 use crate::model::schema::users;
 
-#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[derive(Clone, Deserialize, Serialize, Queryable, Insertable)]
 #[serde(crate = "rocket::serde")]
 #[table_name="users"]
 pub struct User {
@@ -20,6 +20,22 @@ pub struct User {
     //pub expiration: chrono::NaiveDateTime,
     pub enabled: bool,
     pub scopes: Vec<String>,
+    pub require_mfa: bool,
+    pub mfa_enroll: bool, // may enroll a second factor with just its password
+}
+
+impl fmt::Debug for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("User")
+            .field("name", &self.name)
+            .field("hash", &Redacted)
+            .field("expiration", &self.expiration)
+            .field("enabled", &self.enabled)
+            .field("scopes", &self.scopes)
+            .field("require_mfa", &self.require_mfa)
+            .field("mfa_enroll", &self.mfa_enroll)
+            .finish()
+    }
 }
 
 impl User {
@@ -31,8 +47,15 @@ impl User {
     pub fn is_enabled(&self) -> bool {
         self.enabled && !self.is_expired()
     }
+
+    pub fn seconds_left(&self) -> u64 {
+        self.expiration.duration_since(SystemTime::now()).map(|d| d.as_secs()).unwrap_or(0)
+    }
 }
 
+// Expiration is checked on every use, so a cached user can outlive it.
+impl cache::Expires for User {}
+
 fn cache_key(k: &str) -> Arc<String> {
     Arc::new(format!("user_{}", k))
 }
@@ -43,16 +66,65 @@ pub async fn get_user(cdb: &CachedDb<'_>, name: String) -> Result<User> {
         return Ok(u);
     }
 
-    let u = cdb.db.run(move |c| users::table.filter(users::name.eq(&name)).first(c)).await.map_err(errstr)?;
-    cache::put(cdb, key, &u).await;
+    let u = cdb.serv.storage.get_user(cdb, name).await?;
+    let _ = cache::put(cdb, key, &u).await; // ignore any errors
 
     Ok(u)
 }
 
+// Drop a user from the cache, so the next lookup goes to the db.
+pub async fn evict(cdb: &CachedDb<'_>, name: &str) -> Result<()> {
+    cache::del(cdb, cache_key(name)).await
+}
+
+// Users and clients share a namespace, so a client's token can never pass for a user's.
 pub async fn put_user(cdb: &CachedDb<'_>, u: User) -> Result<()> {
+    match cdb.serv.storage.get_client(cdb, u.name.clone()).await {
+        Ok(_) => return Err(Error::Conflict(format!("{} is a client", u.name))),
+        Err(Error::NotFound) => {},
+        Err(e) => return Err(e),
+    }
+    let key = cache_key(&u.name);
+    let _ = cache::del(cdb, key).await; // ignore any errors
+    cdb.serv.storage.insert_user(cdb, u).await
+}
+
+
+pub async fn list_users(cdb: &CachedDb<'_>, offset: i64, limit: i64) -> Result<Vec<User>> {
+    cdb.serv.storage.list_users(cdb, offset, limit).await
+}
+
+pub async fn update_user(cdb: &CachedDb<'_>, u: User) -> Result<()> {
     let key = cache_key(&u.name);
-    cache::del(cdb, key).await;
-    cdb.db.run(move |c| diesel::insert_into(users::table).values(u).execute(c)).await.map_err(errstr)?;
+    let cnt = cdb.serv.storage.update_user(cdb, u).await?;
+    // a stale copy elsewhere could still let a disabled user in
+    cache::del(cdb, key).await?;
+    if cnt == 0 {
+        return Err(Error::NotFound);
+    }
     Ok(())
 }
 
+/*
+ * Upgrade a user's hash, leaving the rest of the user as it is. Does nothing
+ * if the hash has changed since it was read, e.g. an admin set a new password.
+ */
+pub async fn set_hash(cdb: &CachedDb<'_>, name: String, old_hash: String, new_hash: String) -> Result<usize> {
+    let key = cache_key(&name);
+    let cnt = cdb.serv.storage.set_user_hash(cdb, name, old_hash, new_hash).await?;
+    let _ = cache::del(cdb, key).await; // ignore any errors
+    Ok(cnt)
+}
+
+pub async fn del_user(cdb: &CachedDb<'_>, name: String) -> Result<usize> {
+    let key = cache_key(&name);
+    let cnt = cdb.serv.storage.del_user(cdb, name).await?;
+    cache::del(cdb, key).await?;
+    Ok(cnt)
+}
+
+// The users that have the scope and can still log in.
+pub async fn scope_holders(cdb: &CachedDb<'_>, scope: String) -> Result<Vec<String>> {
+    cdb.serv.storage.scope_holders(cdb, scope).await
+}
+
@@ -0,0 +1,353 @@
+
+/*
+ * Password hashing with argon2. The parameters come from the config, and a
+ * hash made with weaker parameters, or before the pepper was set, is
+ * replaced the next time its user logs in.
+ *
+ * argon2 is slow on purpose, so it runs on the blocking thread pool rather
+ * than tying up an async worker. Only `concurrency` hashes run at once and
+ * only `queue` more can wait. Anything past that is turned away, so a burst
+ * of logins can't starve everything else.
+ */
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+use rand::{Rng, rngs::StdRng};
+use rocket::serde::{Serialize, Deserialize};
+use rocket::tokio::{sync::Semaphore, task};
+use argon2::{Variant, Version, ThreadMode};
+use prometheus::Histogram;
+
+use crate::{Result, Error};
+use crate::logging::Redacted;
+
+#[derive(Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct Config {
+    pub variant: String,        // argon2i, argon2d or argon2id
+    pub mem_cost: u32,          // KiB
+    pub time_cost: u32,         // iterations
+    pub lanes: u32,             // parallelism
+    pub pepper: Option<String>, // server side secret mixed into every hash
+    pub concurrency: usize,     // hashes that can run at once
+    pub queue: usize,           // hashes that can wait for a turn
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("variant", &self.variant)
+            .field("mem_cost", &self.mem_cost)
+            .field("time_cost", &self.time_cost)
+            .field("lanes", &self.lanes)
+            .field("pepper", &self.pepper.as_ref().map(|_| Redacted))
+            .field("concurrency", &self.concurrency)
+            .field("queue", &self.queue)
+            .finish()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            variant: "argon2id".to_owned(),
+            mem_cost: 19456,
+            time_cost: 2,
+            lanes: 1,
+            pepper: None,
+            concurrency: 4,
+            queue: 64,
+        }
+    }
+}
+
+// The outcome of checking a password against a hash.
+#[derive(Debug, PartialEq)]
+pub enum Check {
+    Bad,
+    Good,
+    Rehash, // good, but the hash should be replaced with one using the current parameters
+}
+
+// Starts hashes made with the pepper, ahead of the usual argon2 encoding.
+const PEPPERED: &str = "$peppered";
+
+struct Params {
+    variant: Variant,
+    mem_cost: u32,
+    time_cost: u32,
+    lanes: u32,
+    pepper: Vec<u8>,
+}
+
+impl Params {
+    fn config(&self) -> argon2::Config<'_> {
+        argon2::Config {
+            variant: self.variant,
+            version: Version::Version13,
+            mem_cost: self.mem_cost,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            thread_mode: ThreadMode::Sequential,
+            secret: &self.pepper,
+            ad: &[],
+            hash_length: 32,
+        }
+    }
+
+    fn hash(&self, salt: &[u8], pw: &str) -> String {
+        // only fails for parameters Hasher::new already rejected
+        let hash = argon2::hash_encoded(pw.as_bytes(), salt, &self.config()).unwrap();
+        if self.pepper.is_empty() {
+            return hash;
+        }
+        format!("{}{}", PEPPERED, hash)
+    }
+
+    // Each hash is only ever checked one way, so a wrong password costs one argon2 run.
+    fn verify(&self, hash: &str, pw: &str) -> Check {
+        match hash.strip_prefix(PEPPERED) {
+            Some(hash) => {
+                // can't be checked once the pepper is gone
+                if self.pepper.is_empty() || !argon2::verify_encoded_ext(hash, pw.as_bytes(), &self.pepper, &[]).unwrap_or(false) {
+                    return Check::Bad;
+                }
+                if self.is_current(hash) { Check::Good } else { Check::Rehash }
+            },
+            None => {
+                if !argon2::verify_encoded(hash, pw.as_bytes()).unwrap_or(false) {
+                    return Check::Bad;
+                }
+                // hashes made before the pepper was set don't have it
+                if self.pepper.is_empty() && self.is_current(hash) { Check::Good } else { Check::Rehash }
+            },
+        }
+    }
+
+    // Whether a hash was made with the current variant and at least the current costs.
+    // Encoded hashes look like $argon2id$v=19$m=19456,t=2,p=1$salt$hash
+    fn is_current(&self, hash: &str) -> bool {
+        let parts: Vec<&str> = hash.split('$').collect();
+        if parts.len() != 6 || parts[1] != self.variant.as_lowercase_str() || parts[2] != "v=19" {
+            return false;
+        }
+        let mut have = (0, 0, 0);
+        for param in parts[3].split(',') {
+            let val = match param.get(2..).and_then(|v| v.parse().ok()) {
+                Some(val) => val,
+                None => return false,
+            };
+            match param.get(..2) {
+                Some("m=") => have.0 = val,
+                Some("t=") => have.1 = val,
+                Some("p=") => have.2 = val,
+                _ => return false,
+            }
+        }
+        have.0 >= self.mem_cost && have.1 >= self.time_cost && have.2 >= self.lanes
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    waiting: AtomicUsize,
+    rejected: AtomicU64,
+    hashes: AtomicU64,
+    total_us: AtomicU64,
+    max_us: AtomicU64,
+}
+
+// What the hasher is doing, for the stats endpoint
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct HashStats {
+    pub running: usize,
+    pub waiting: usize,
+    pub rejected: u64,
+    pub hashes: u64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+}
+
+pub struct Hasher {
+    params: Arc<Params>,
+    concurrency: usize,
+    queue: usize,
+    permits: Arc<Semaphore>,
+    stats: Stats,
+    durations: Histogram, // seconds, for metrics
+    dummy: String, // checked when there is no hash, so that takes as long as a wrong password
+}
+
+// Counts a caller as waiting until it is dropped, even if its request goes away first.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Hasher {
+    pub fn new(cfg: &Config, durations: Histogram) -> std::result::Result<Hasher, String> {
+        let variant = Variant::from_str(&cfg.variant).map_err(|e| format!("argon2 variant {}: {}", cfg.variant, e))?;
+        if cfg.mem_cost < 8 * cfg.lanes || cfg.time_cost < 1 || cfg.lanes < 1 {
+            return Err(format!("bad argon2 parameters m={} t={} p={}", cfg.mem_cost, cfg.time_cost, cfg.lanes));
+        }
+        if cfg.concurrency < 1 {
+            return Err("password hashing concurrency must be at least 1".to_owned());
+        }
+        let params = Params {
+            variant: variant,
+            mem_cost: cfg.mem_cost,
+            time_cost: cfg.time_cost,
+            lanes: cfg.lanes,
+            pepper: cfg.pepper.as_ref().map(|p| p.as_bytes().to_vec()).unwrap_or_default(),
+        };
+        let dummy = params.hash(&[0; 20], "");
+        Ok(Hasher {
+            params: Arc::new(params),
+            concurrency: cfg.concurrency,
+            queue: cfg.queue,
+            permits: Arc::new(Semaphore::new(cfg.concurrency)),
+            stats: Stats::default(),
+            durations: durations,
+            dummy: dummy,
+        })
+    }
+
+    // Run f on the blocking pool once there is room, or fail if too many are already waiting.
+    async fn run<T, F>(&self, f: F) -> Result<T>
+        where T: Send + 'static, F: FnOnce(&Params) -> T + Send + 'static
+    {
+        let ahead = self.stats.waiting.fetch_add(1, Ordering::SeqCst);
+        let waiting = Waiting(&self.stats.waiting);
+        if ahead >= self.queue + self.permits.available_permits() {
+            self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(Error::Unavailable("password hashing overloaded".to_owned()));
+        }
+        let permit = self.permits.clone().acquire_owned().await
+            .map_err(|e| Error::Unavailable(e.to_string()))?;
+        drop(waiting);
+
+        // the permit goes with the hash, which runs to the end even if the request goes away
+        let params = self.params.clone();
+        let start = Instant::now();
+        let x = task::spawn_blocking(move || {
+            let _permit = permit;
+            f(&params)
+        }).await
+            .map_err(|e| Error::Unavailable(e.to_string()))?;
+
+        let elapsed = start.elapsed();
+        self.durations.observe(elapsed.as_secs_f64());
+        let us = elapsed.as_micros() as u64;
+        self.stats.hashes.fetch_add(1, Ordering::Relaxed);
+        self.stats.total_us.fetch_add(us, Ordering::Relaxed);
+        self.stats.max_us.fetch_max(us, Ordering::Relaxed);
+        Ok(x)
+    }
+
+    pub async fn hash(&self, rng: &Mutex<StdRng>, pw: &str) -> Result<String> {
+        let salt: [u8; 20] = rng.lock().unwrap().gen(); // safe
+        let pw = pw.to_owned();
+        self.run(move |p| p.hash(&salt, &pw)).await
+    }
+
+    pub async fn verify(&self, hash: &str, pw: &str) -> Result<Check> {
+        let (hash, pw) = (hash.to_owned(), pw.to_owned());
+        self.run(move |p| p.verify(&hash, &pw)).await
+    }
+
+    // Take as long as checking a password would, when there is nobody to check it for.
+    pub async fn verify_nobody(&self, pw: &str) -> Result<()> {
+        self.verify(&self.dummy, pw).await.map(|_| ())
+    }
+
+    pub fn stats(&self) -> HashStats {
+        let hashes = self.stats.hashes.load(Ordering::Relaxed);
+        let total_us = self.stats.total_us.load(Ordering::Relaxed);
+        HashStats {
+            running: self.concurrency - self.permits.available_permits(),
+            waiting: self.stats.waiting.load(Ordering::SeqCst),
+            rejected: self.stats.rejected.load(Ordering::Relaxed),
+            hashes: hashes,
+            avg_ms: if hashes == 0 { 0.0 } else { total_us as f64 / hashes as f64 / 1000.0 },
+            max_ms: self.stats.max_us.load(Ordering::Relaxed) as f64 / 1000.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pepper: &str) -> Params {
+        Params {
+            variant: Variant::Argon2id,
+            mem_cost: 64,
+            time_cost: 1,
+            lanes: 1,
+            pepper: pepper.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn peppered_hashes_are_marked() {
+        let p = params("pepper");
+        let hash = p.hash(b"somesaltsomesalt", "secret");
+        assert!(hash.starts_with(PEPPERED));
+        assert_eq!(p.verify(&hash, "secret"), Check::Good);
+        assert_eq!(p.verify(&hash, "wrong"), Check::Bad);
+        // without the pepper it can't be checked at all
+        assert_eq!(params("").verify(&hash, "secret"), Check::Bad);
+    }
+
+    #[test]
+    fn unpeppered_hashes_get_the_pepper() {
+        let hash = params("").hash(b"somesaltsomesalt", "secret");
+        assert!(!hash.starts_with(PEPPERED));
+        assert_eq!(params("").verify(&hash, "secret"), Check::Good);
+        assert_eq!(params("pepper").verify(&hash, "secret"), Check::Rehash);
+        assert_eq!(params("pepper").verify(&hash, "wrong"), Check::Bad);
+    }
+
+    #[test]
+    fn weaker_hashes_are_rehashed() {
+        let hash = params("").hash(b"somesaltsomesalt", "secret");
+        let stronger = Params { mem_cost: 128, ..params("") };
+        assert_eq!(stronger.verify(&hash, "secret"), Check::Rehash);
+        assert_eq!(stronger.verify(&hash, "wrong"), Check::Bad);
+        // a hash stronger than asked for is left alone
+        let hash = stronger.hash(b"somesaltsomesalt", "secret");
+        assert_eq!(params("").verify(&hash, "secret"), Check::Good);
+        let argon2i = Params { variant: Variant::Argon2i, ..params("") };
+        assert_eq!(argon2i.verify(&hash, "secret"), Check::Rehash);
+    }
+
+    #[rocket::async_test]
+    async fn abandoned_hashes_keep_their_permit() {
+        let cfg = Config { concurrency: 1, ..Config::default() };
+        let h = Hasher::new(&cfg, Histogram::with_opts(prometheus::HistogramOpts::new("h", "h")).unwrap()).unwrap();
+        let slow = h.run(|_| std::thread::sleep(std::time::Duration::from_millis(300)));
+        assert!(rocket::tokio::time::timeout(std::time::Duration::from_millis(50), slow).await.is_err());
+        // the request went away, but the hash is still running
+        assert_eq!(h.stats().running, 1);
+        rocket::tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+        assert_eq!(h.stats().running, 0);
+    }
+
+    #[rocket::async_test]
+    async fn turns_away_more_than_the_queue_holds() {
+        let cfg = Config { concurrency: 1, queue: 1, ..Config::default() };
+        let h = Hasher::new(&cfg, Histogram::with_opts(prometheus::HistogramOpts::new("h", "h")).unwrap()).unwrap();
+        let slow = |_: &Params| std::thread::sleep(std::time::Duration::from_millis(200));
+        // one running, one waiting, and the third has no room
+        let (a, b, c) = rocket::tokio::join!(h.run(slow), h.run(slow), h.run(slow));
+        assert!(a.is_ok() && b.is_ok());
+        assert!(matches!(c, Err(Error::Unavailable(_))));
+        let stats = h.stats();
+        assert_eq!((stats.hashes, stats.rejected, stats.waiting), (2, 1, 0));
+    }
+}
@@ -15,6 +15,11 @@ pub type PoolResult<P: Poolable> = Result<r2d2::Pool<P::Manager>, Error<P::Error
 
 pub struct Connection(pub redis::Connection);
 
+// A connection is good if redis answers a PING on it.
+pub fn ping(conn: &mut redis::Connection) -> redis::RedisResult<()> {
+    redis::cmd("PING").query(conn)
+}
+
 pub struct ConnectionManager {
     connection_info: redis::ConnectionInfo,
 }
@@ -37,7 +42,7 @@ impl r2d2::ManageConnection for ConnectionManager {
         }
     }
     fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
-        redis::cmd("PING").query(&mut conn.0)
+        ping(&mut conn.0)
     }
     fn has_broken(&self, conn: &mut Self::Connection) -> bool {
         !conn.0.is_open()
@@ -51,10 +56,11 @@ impl Poolable for Connection {
     fn pool(db_name: &str, rocket: &Rocket<Build>) -> PoolResult<Self> {
         let config = Config::from(db_name, rocket)?;
         let manager = ConnectionManager::new(&*config.url).map_err(Error::Custom)?;
+        // unchecked, so we still start when redis is down and run without the cache
         let pool = r2d2::Pool::builder()
             .max_size(config.pool_size)
             .connection_timeout(Duration::from_secs(config.timeout as u64))
-            .build(manager)?;
+            .build_unchecked(manager);
 
         Ok(pool)
     }
@@ -1,17 +1,29 @@
 
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
 use rocket_sync_db_pools::database;
+use rocket::{Rocket, Build, Ignite, Orbit, State};
+use rocket::http::RawStr;
 use rocket::request::{self, FromRequest, Request};
 use rocket::outcome::{Outcome, IntoOutcome};
+use rocket::tokio::sync::OnceCell;
+use tracing::warn;
 
-use crate::json::{StrRes, ERR_BADAUTH, ERR_EXPIRED};
+use crate::{Result, Error};
+use crate::json::{StrRes, notfound_badauth, ERR_BADAUTH, ERR_EXPIRED, ERR_FORBIDDEN};
 use crate::model::token;
 use crate::redis_support;
+use crate::logging::RequestId;
 
 pub use crate::Server;
 
 #[database("diesel")]
 pub struct Db(diesel::PgConnection);
 
+#[database("sqlite")]
+pub struct SqliteDb(diesel::SqliteConnection);
+
 #[database("redis")]
 pub struct Cache(redis_support::Connection);
 
@@ -33,23 +45,33 @@ impl BearerToken {
     // Lookup the token data associated with the bearer token and return it or an auth error
     pub async fn lookup(&self, cdb: &CachedDb<'_>) -> StrRes<token::Token> {
         let header = self.header.clone().ok_or(ERR_BADAUTH)?;
-        let tok = token::get_token(cdb, header).await.or(Err(ERR_BADAUTH))?;
+        let tok = token::lookup_token(cdb, header).await.map_err(notfound_badauth)?;
         let valid = !tok.is_expired();
         valid.then(|| tok).ok_or(ERR_EXPIRED)
     }
 
-    // Return an auth error if scope isn't associated with the bearer token
-    pub async fn require_scope(&self, cdb: &CachedDb<'_>, scope: &str) -> StrRes<()> {
+    // Return the token if scope is associated with it, an auth error if the token is bad, or forbidden if it hasn't the scope
+    pub async fn require_scope(&self, cdb: &CachedDb<'_>, scope: &str) -> StrRes<token::Token> {
         let tok = self.lookup(cdb).await?;
         let valid = tok.scopes.iter().any(|have| have == scope);
-        valid.then(|| ()).ok_or(ERR_BADAUTH)
+        valid.then(|| tok).ok_or(ERR_FORBIDDEN)
+    }
+
+    // Return forbidden unless the bearer token was issued to a user by logging in directly
+    pub async fn require_user(&self, cdb: &CachedDb<'_>) -> StrRes<token::Token> {
+        let tok = self.lookup(cdb).await?;
+        // tokens from a client are either the client's own or limited to what the client may do
+        let valid = tok.client_id.is_none();
+        valid.then(|| tok).ok_or(ERR_FORBIDDEN)
     }
 
-    // Return an auth error unless the bearer token is associated with the user or the scope
-    pub async fn require_user_or_scope(&self, cdb: &CachedDb<'_>, user: &str, scope: &str) -> StrRes<()> {
+    // Return forbidden unless the bearer token is associated with the user or the scope
+    pub async fn require_user_or_scope(&self, cdb: &CachedDb<'_>, user: &str, scope: &str) -> StrRes<token::Token> {
         let tok = self.lookup(cdb).await?;
-        let valid = tok.username == user || tok.scopes.iter().any(|have| have == scope);
-        valid.then(|| ()).ok_or(ERR_BADAUTH)
+        // a client's own token is named for the client, and never stands for a user of the same name
+        let is_user = tok.username == user && tok.client_id.is_none();
+        let valid = is_user || tok.scopes.iter().any(|have| have == scope);
+        valid.then(|| tok).ok_or(ERR_FORBIDDEN)
     }
 }
 
@@ -61,17 +83,212 @@ impl<'r> FromRequest<'r> for BearerToken {
     async fn from_request(request: &'r Request<'_>) -> request::Outcome<BearerToken, Self::Error> {
         let opthdr = request.headers()
                         .get_one("Authorization")
-                        .and_then(|s| s.strip_prefix("bearer "));
+                        .and_then(|s| s.strip_prefix("bearer ").or_else(|| s.strip_prefix("Bearer ")));
         let bt = BearerToken::new(opthdr);
         Outcome::Success(bt)
     }
 }
 
-// Wraps up Cache and Db and Server, since they're all needed together
+/*
+ * Client credentials from an HTTP Basic authorization header.
+ * Forwards if there is no such header, so routes should ask for an Option<BasicAuth>.
+ * OAuth clients form-urlencode the id and secret before joining them
+ * with ':' (RFC 6749 2.3.1), so each is decoded after the split.
+ */
+pub struct BasicAuth {
+    pub name: String,
+    pub secret: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BasicAuth {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<BasicAuth, Self::Error> {
+        let creds = request.headers()
+                        .get_one("Authorization")
+                        .and_then(|s| s.strip_prefix("Basic ").or_else(|| s.strip_prefix("basic ")))
+                        .and_then(|s| base64::decode(s).ok())
+                        .and_then(|v| String::from_utf8(v).ok());
+        let ba = creds.as_deref()
+                        .and_then(|s| s.split_once(':'))
+                        .and_then(|(name, secret)| Some(BasicAuth{
+                            name: RawStr::new(name).url_decode().ok()?.into_owned(),
+                            secret: RawStr::new(secret).url_decode().ok()?.into_owned(),
+                        }));
+        match ba {
+            Some(ba) => Outcome::Success(ba),
+            None => Outcome::Forward(()),
+        }
+    }
+}
+
+/*
+ * The address a request came from. Only a proxy we trust is believed about
+ * who it forwarded for; a client could put anything in those headers.
+ * X-Forwarded-For is read from the right, each trusted proxy naming the hop
+ * before it, and the first address that isn't a trusted proxy is the client.
+ */
+pub fn client_addr(request: &Request<'_>, trusted: &[IpAddr]) -> Option<IpAddr> {
+    let remote = request.remote()?.ip();
+    if !trusted.contains(&remote) {
+        return Some(remote);
+    }
+    let hops: Vec<&str> = request.headers().get("X-Forwarded-For")
+                        .flat_map(|h| h.split(','))
+                        .map(|hop| hop.trim())
+                        .collect();
+    if hops.is_empty() {
+        return Some(request.real_ip().unwrap_or(remote));
+    }
+    let mut addr = remote;
+    for hop in hops.iter().rev() {
+        match hop.parse::<IpAddr>() {
+            Ok(ip) if trusted.contains(&ip) => addr = ip,
+            Ok(ip) => return Some(ip),
+            Err(_) => break, // garbled, so nothing further left is believed
+        }
+    }
+    Some(addr)
+}
+
+/*
+ * Wraps up Cache and Db and Server, since they're all needed together,
+ * along with the address of the client the request came from and the
+ * id it is logged under.
+ * Connections are only checked out of their pools when first used,
+ * so a request answered from the cache never ties up a db connection.
+ */
 pub struct CachedDb<'r> {
-    pub cache: Cache,
-    pub db: Db,
+    rocket: RocketRef<'r>,
+    db: OnceCell<Db>,
+    sqlite: OnceCell<SqliteDb>,
+    cache: OnceCell<Option<Cache>>,
     pub serv: &'r Server,
+    pub ip: Option<IpAddr>,
+    pub request_id: String,
+}
+
+// Requests come from a launched rocket, the admin tool only ignites one,
+// and fairings can need the db before either.
+enum RocketRef<'r> {
+    Build(&'r Rocket<Build>),
+    Ignite(&'r Rocket<Ignite>),
+    Orbit(&'r Rocket<Orbit>),
+}
+
+// Check a connection out of a pool, whichever phase rocket is in.
+macro_rules! get_one {
+    ($rocket:expr, $pool:ty) => {
+        match $rocket {
+            RocketRef::Build(r) => <$pool>::get_one(r).await,
+            RocketRef::Ignite(r) => <$pool>::get_one(r).await,
+            RocketRef::Orbit(r) => <$pool>::get_one(r).await,
+        }
+    };
+}
+
+// After failing to get a redis connection, don't try again for this many seconds.
+const CACHE_RETRY: u64 = 10;
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl<'r> CachedDb<'r> {
+    fn detached(rocket: RocketRef<'r>, serv: &'r Server, id: &str) -> CachedDb<'r> {
+        CachedDb {
+            rocket: rocket,
+            db: OnceCell::new(),
+            sqlite: OnceCell::new(),
+            cache: OnceCell::new(),
+            serv: serv,
+            ip: None,
+            request_id: id.to_owned(),
+        }
+    }
+
+    // For working outside of any request, as the admin tool does.
+    pub fn offline(rocket: &'r Rocket<Ignite>) -> CachedDb<'r> {
+        let serv = State::get(rocket).expect("server state");
+        CachedDb::detached(RocketRef::Ignite(rocket), serv, "offline")
+    }
+
+    // For fairings that need the db before launch. Only after the pools are set up.
+    pub fn igniting(rocket: &'r Rocket<Build>) -> CachedDb<'r> {
+        let serv = State::get(rocket).expect("server state");
+        CachedDb::detached(RocketRef::Build(rocket), serv, "ignite")
+    }
+
+    pub async fn db(&self) -> Result<&Db> {
+        self.db.get_or_try_init(|| async {
+            let db = get_one!(self.rocket, Db)
+                .ok_or_else(|| Error::Unavailable("no database connection".to_owned()))?;
+            self.serv.db_in_use.fetch_add(1, Ordering::Relaxed);
+            Ok(db)
+        }).await
+    }
+
+    pub async fn sqlite(&self) -> Result<&SqliteDb> {
+        self.sqlite.get_or_try_init(|| async {
+            let db = get_one!(self.rocket, SqliteDb)
+                .ok_or_else(|| Error::Unavailable("no database connection".to_owned()))?;
+            self.serv.db_in_use.fetch_add(1, Ordering::Relaxed);
+            Ok(db)
+        }).await
+    }
+
+    /*
+     * Redis is only a cache, so when it is down requests carry on without it.
+     * Waiting out the pool timeout on every request would be as bad as
+     * failing, so once it can't be reached we stop asking for a while.
+     */
+    pub async fn cache(&self) -> Result<&Cache> {
+        self.checkout_cache(false).await
+    }
+
+    /*
+     * For deletes, which can't be skipped: other nodes would go on serving
+     * what was deleted. These try redis even while it is being left alone.
+     */
+    pub async fn cache_for_delete(&self) -> Result<&Cache> {
+        self.checkout_cache(true).await
+    }
+
+    // Redis is tried at most once a request, and while it is being left alone only for a delete.
+    async fn checkout_cache(&self, force: bool) -> Result<&Cache> {
+        let resting = now_secs() < self.serv.cache_down_until.load(Ordering::Relaxed);
+        if resting && !force && self.cache.get().is_none() {
+            return Err(Error::Unavailable("no cache connection".to_owned()));
+        }
+        let cache = self.cache.get_or_init(|| async {
+            let cache = get_one!(self.rocket, Cache);
+            match cache {
+                Some(_) => { self.serv.cache_in_use.fetch_add(1, Ordering::Relaxed); },
+                None => {
+                    warn!(retry_secs = CACHE_RETRY, "redis unavailable");
+                    self.serv.cache_down_until.store(now_secs() + CACHE_RETRY, Ordering::Relaxed);
+                },
+            }
+            cache
+        }).await;
+        cache.as_ref().ok_or_else(|| Error::Unavailable("no cache connection".to_owned()))
+    }
+}
+
+// The pool doesn't say how busy it is, so we count our own checkouts.
+impl Drop for CachedDb<'_> {
+    fn drop(&mut self) {
+        if self.db.get().is_some() {
+            self.serv.db_in_use.fetch_sub(1, Ordering::Relaxed);
+        }
+        if self.sqlite.get().is_some() {
+            self.serv.db_in_use.fetch_sub(1, Ordering::Relaxed);
+        }
+        if let Some(Some(_)) = self.cache.get() {
+            self.serv.cache_in_use.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
 }
 
 // Automatically provide wrapped CacheDb when asked for
@@ -80,10 +297,17 @@ impl <'r> FromRequest<'r> for CachedDb<'r> {
     type Error = std::convert::Infallible;
 
     async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
-        let cache = request.guard::<Cache>().await.expect("cant get cache pool");
-        let db = request.guard::<Db>().await.expect("cant get db pool");
         let serv = request.guard::<&Server>().await.expect("cant get server state");
-        Ok(CachedDb{ cache: cache, db: db, serv: serv })
+        let cdb = CachedDb {
+            rocket: RocketRef::Orbit(request.rocket()),
+            db: OnceCell::new(),
+            sqlite: OnceCell::new(),
+            cache: OnceCell::new(),
+            serv: serv,
+            ip: client_addr(request, &serv.trusted_proxies),
+            request_id: request.local_cache(|| RequestId(String::new())).0.clone(),
+        };
+        Ok(cdb)
             .or_forward(())
     }
 }
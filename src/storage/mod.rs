@@ -0,0 +1,113 @@
+
+/*
+ * Where users, clients, scopes and tokens are kept. The model layer decides what to
+ * cache and when, and asks the storage backend for everything else.
+ *
+ * "postgres" is the default and can be shared by many nodes. "sqlite"
+ * keeps everything in a single file, for small deployments and testing.
+ */
+use std::time::SystemTime;
+use rocket::{Rocket, Build};
+
+use crate::Result;
+use crate::rocktypes::CachedDb;
+use crate::model::user::User;
+use crate::model::client::Client;
+use crate::model::scopes::{Scope, ScopeChanges, ScopeRemoval};
+use crate::model::token::Token;
+use crate::model::refresh::RefreshToken;
+use crate::model::totp::Totp;
+use crate::model::audit::{Event, NewEvent, Filter};
+
+pub mod postgres;
+pub mod sqlite;
+
+#[rocket::async_trait]
+pub trait Storage: Send + Sync {
+    fn name(&self) -> &'static str;
+    // The pool it uses, as named under databases in the config.
+    fn database(&self) -> &'static str;
+    async fn migrate(&self, rocket: &Rocket<Build>) -> Result<()>;
+    // The version of the newest migration built in, and of the newest one run.
+    fn latest_migration(&self) -> &'static str;
+    async fn migration_version(&self, cdb: &CachedDb<'_>) -> Result<Option<String>>;
+    async fn ping(&self, cdb: &CachedDb<'_>) -> Result<()>;
+
+    async fn get_user(&self, cdb: &CachedDb<'_>, name: String) -> Result<User>;
+    async fn insert_user(&self, cdb: &CachedDb<'_>, u: User) -> Result<()>;
+    async fn list_users(&self, cdb: &CachedDb<'_>, offset: i64, limit: i64) -> Result<Vec<User>>;
+    async fn update_user(&self, cdb: &CachedDb<'_>, u: User) -> Result<usize>;
+    // Replace the user's hash, only if it is still old_hash.
+    async fn set_user_hash(&self, cdb: &CachedDb<'_>, name: String, old_hash: String, new_hash: String) -> Result<usize>;
+    async fn del_user(&self, cdb: &CachedDb<'_>, name: String) -> Result<usize>;
+    // The users that have the scope and can still log in.
+    async fn scope_holders(&self, cdb: &CachedDb<'_>, scope: String) -> Result<Vec<String>>;
+
+    async fn get_client(&self, cdb: &CachedDb<'_>, client_id: String) -> Result<Client>;
+    async fn insert_client(&self, cdb: &CachedDb<'_>, c: Client) -> Result<()>;
+    async fn list_clients(&self, cdb: &CachedDb<'_>, offset: i64, limit: i64) -> Result<Vec<Client>>;
+    async fn update_client(&self, cdb: &CachedDb<'_>, c: Client) -> Result<usize>;
+    async fn del_client(&self, cdb: &CachedDb<'_>, client_id: String) -> Result<usize>;
+
+    async fn active_scopes(&self, cdb: &CachedDb<'_>) -> Result<Vec<String>>;
+    async fn mfa_scopes(&self, cdb: &CachedDb<'_>) -> Result<Vec<String>>;
+    async fn list_scopes(&self, cdb: &CachedDb<'_>) -> Result<Vec<Scope>>;
+    async fn insert_scope(&self, cdb: &CachedDb<'_>, name: String) -> Result<()>;
+    async fn update_scope(&self, cdb: &CachedDb<'_>, name: String, changes: ScopeChanges) -> Result<usize>;
+    /*
+     * Delete a scope and take it away from every user, client, token and
+     * refresh token, in one transaction. With revoke the tokens that have it
+     * are deleted and put on the revocation list instead, as JWTs can't lose a scope.
+     */
+    async fn del_scope(&self, cdb: &CachedDb<'_>, name: String, revoke: bool) -> Result<ScopeRemoval>;
+
+    async fn get_token(&self, cdb: &CachedDb<'_>, name: String) -> Result<Token>;
+    async fn insert_token(&self, cdb: &CachedDb<'_>, tok: Token) -> Result<()>;
+    async fn clean_tokens(&self, cdb: &CachedDb<'_>) -> Result<usize>;
+    // The deleting calls return the name and expiration of every token deleted.
+    async fn del_token(&self, cdb: &CachedDb<'_>, name: String) -> Result<Vec<(String, SystemTime)>>;
+    async fn del_user_tokens(&self, cdb: &CachedDb<'_>, user: String) -> Result<Vec<(String, SystemTime)>>;
+    async fn del_client_tokens(&self, cdb: &CachedDb<'_>, client_id: String) -> Result<Vec<(String, SystemTime)>>;
+
+    async fn get_refresh(&self, cdb: &CachedDb<'_>, name: String) -> Result<RefreshToken>;
+    async fn insert_refresh(&self, cdb: &CachedDb<'_>, tok: RefreshToken) -> Result<()>;
+    // The family of the refresh token issued alongside an access token, if there was one.
+    async fn refresh_family(&self, cdb: &CachedDb<'_>, access: String) -> Result<Option<String>>;
+    async fn mark_used(&self, cdb: &CachedDb<'_>, name: String) -> Result<bool>;
+    async fn del_family(&self, cdb: &CachedDb<'_>, family: String) -> Result<Vec<String>>;
+    async fn del_user_refresh(&self, cdb: &CachedDb<'_>, user: String) -> Result<usize>;
+    async fn del_client_refresh(&self, cdb: &CachedDb<'_>, client_id: String) -> Result<usize>;
+    async fn clean_refresh(&self, cdb: &CachedDb<'_>) -> Result<usize>;
+
+    async fn is_revoked(&self, cdb: &CachedDb<'_>, jti: String) -> Result<bool>;
+    async fn revoke(&self, cdb: &CachedDb<'_>, jti: String, expiration: SystemTime) -> Result<()>;
+    async fn clean_revoked(&self, cdb: &CachedDb<'_>) -> Result<usize>;
+
+    async fn get_totp(&self, cdb: &CachedDb<'_>, name: String) -> Result<Totp>;
+    async fn put_totp(&self, cdb: &CachedDb<'_>, t: Totp) -> Result<()>;
+    async fn confirm_totp(&self, cdb: &CachedDb<'_>, name: String, step: i64) -> Result<()>;
+    async fn use_step(&self, cdb: &CachedDb<'_>, name: String, step: i64) -> Result<bool>;
+    async fn del_totp(&self, cdb: &CachedDb<'_>, name: String) -> Result<usize>;
+    async fn put_recovery_codes(&self, cdb: &CachedDb<'_>, name: String, hashes: Vec<String>) -> Result<()>;
+    async fn use_recovery_code(&self, cdb: &CachedDb<'_>, name: String, hash: String) -> Result<bool>;
+
+    async fn insert_event(&self, cdb: &CachedDb<'_>, ev: NewEvent) -> Result<()>;
+    async fn list_events(&self, cdb: &CachedDb<'_>, f: Filter, offset: i64, limit: i64) -> Result<Vec<Event>>;
+}
+
+// Diesel records every migration it has run, by version.
+const MIGRATION_VERSION: &str = "SELECT max(version) AS version FROM __diesel_schema_migrations";
+
+#[derive(QueryableByName)]
+struct MigrationVersion {
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Text>"]
+    version: Option<String>,
+}
+
+pub fn new(name: &str) -> std::result::Result<Box<dyn Storage>, String> {
+    match name {
+        "postgres" => Ok(Box::new(postgres::Postgres)),
+        "sqlite" => Ok(Box::new(sqlite::Sqlite)),
+        _ => Err(format!("unknown storage {}", name)),
+    }
+}
@@ -0,0 +1,502 @@
+
+use std::time::SystemTime;
+use rocket::{Rocket, Build};
+use tracing::instrument;
+use rocket_sync_db_pools::diesel::prelude::*;
+use diesel::dsl::now;
+use diesel::sql_types::{Array, Text};
+
+use crate::{Result, Error};
+use crate::rocktypes::{CachedDb, Db};
+use crate::model::schema::{users, clients, scopes, tokens, refresh_tokens, revoked_tokens, totp, recovery_codes, audit_events};
+use crate::model::user::User;
+use crate::model::client::Client;
+use crate::model::scopes::{Scope, ScopeChanges, ScopeRemoval};
+use crate::model::token::Token;
+use crate::model::refresh::RefreshToken;
+use crate::model::totp::Totp;
+use crate::model::audit::{Event, NewEvent, Filter};
+use crate::storage::{Storage, MigrationVersion, MIGRATION_VERSION};
+
+/*
+ * Everything in postgres, through the "diesel" database pool.
+ * Scopes are kept in text[] columns.
+ */
+pub struct Postgres;
+
+// array_remove(column, e), written out because sql_function! would also
+// register it with sqlite, which has no arrays, and fail to build.
+macro_rules! array_remove {
+    ($column:literal, $e:expr) => {
+        diesel::dsl::sql::<Array<Text>>(concat!("array_remove(", $column, ", "))
+            .bind::<Text, _>($e)
+            .sql(")")
+    };
+}
+
+embed_migrations!("migrations");
+
+#[rocket::async_trait]
+impl Storage for Postgres {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn database(&self) -> &'static str {
+        "diesel"
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn migrate(&self, rocket: &Rocket<Build>) -> Result<()> {
+        let conn = Db::get_one(rocket).await
+            .ok_or_else(|| Error::Unavailable("no database connection".to_owned()))?;
+        conn.run(|c| embedded_migrations::run(c)).await
+            .map_err(|e| Error::Unavailable(e.to_string()))
+    }
+
+    fn latest_migration(&self) -> &'static str {
+        "20261018110000"
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn migration_version(&self, cdb: &CachedDb<'_>) -> Result<Option<String>> {
+        let v: MigrationVersion = cdb.db().await?.run(|c| diesel::sql_query(MIGRATION_VERSION).get_result(c)).await?;
+        Ok(v.version)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn ping(&self, cdb: &CachedDb<'_>) -> Result<()> {
+        cdb.db().await?.run(|c| diesel::sql_query("SELECT 1").execute(c)).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn get_user(&self, cdb: &CachedDb<'_>, name: String) -> Result<User> {
+        let u = cdb.db().await?.run(move |c| users::table.filter(users::name.eq(&name)).first(c)).await?;
+        Ok(u)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn insert_user(&self, cdb: &CachedDb<'_>, u: User) -> Result<()> {
+        cdb.db().await?.run(move |c| diesel::insert_into(users::table).values(u).execute(c)).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn list_users(&self, cdb: &CachedDb<'_>, offset: i64, limit: i64) -> Result<Vec<User>> {
+        let us = cdb.db().await?.run(move |c|
+            users::table.order(users::name).offset(offset).limit(limit).load(c)
+                ).await?;
+        Ok(us)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn update_user(&self, cdb: &CachedDb<'_>, u: User) -> Result<usize> {
+        let cnt = cdb.db().await?.run(move |c|
+            diesel::update(users::table.filter(users::name.eq(&u.name)))
+                .set((users::hash.eq(&u.hash),
+                      users::expiration.eq(u.expiration),
+                      users::enabled.eq(u.enabled),
+                      users::scopes.eq(&u.scopes),
+                      users::require_mfa.eq(u.require_mfa),
+                      users::mfa_enroll.eq(u.mfa_enroll)))
+                .execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn set_user_hash(&self, cdb: &CachedDb<'_>, name: String, old_hash: String, new_hash: String) -> Result<usize> {
+        let cnt = cdb.db().await?.run(move |c|
+            diesel::update(users::table.filter(users::name.eq(&name)).filter(users::hash.eq(&old_hash)))
+                .set(users::hash.eq(&new_hash))
+                .execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_user(&self, cdb: &CachedDb<'_>, name: String) -> Result<usize> {
+        let cnt = cdb.db().await?.run(move |c|
+            diesel::delete(users::table.filter(users::name.eq(&name))).execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn scope_holders(&self, cdb: &CachedDb<'_>, scope: String) -> Result<Vec<String>> {
+        let names = cdb.db().await?.run(move |c|
+            users::table.select(users::name)
+                .filter(users::scopes.contains(vec![scope]))
+                .filter(users::enabled.eq(true))
+                .filter(users::expiration.gt(now))
+                .load(c)
+                ).await?;
+        Ok(names)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn get_client(&self, cdb: &CachedDb<'_>, client_id: String) -> Result<Client> {
+        let x = cdb.db().await?.run(move |c| clients::table.filter(clients::client_id.eq(&client_id)).first(c)).await?;
+        Ok(x)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn insert_client(&self, cdb: &CachedDb<'_>, cl: Client) -> Result<()> {
+        cdb.db().await?.run(move |c| diesel::insert_into(clients::table).values(cl).execute(c)).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn list_clients(&self, cdb: &CachedDb<'_>, offset: i64, limit: i64) -> Result<Vec<Client>> {
+        let cls = cdb.db().await?.run(move |c|
+            clients::table.order(clients::client_id).offset(offset).limit(limit).load(c)
+                ).await?;
+        Ok(cls)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn update_client(&self, cdb: &CachedDb<'_>, cl: Client) -> Result<usize> {
+        let cnt = cdb.db().await?.run(move |c|
+            diesel::update(clients::table.filter(clients::client_id.eq(&cl.client_id)))
+                .set((clients::secrets.eq(&cl.secrets),
+                      clients::scopes.eq(&cl.scopes),
+                      clients::grant_types.eq(&cl.grant_types),
+                      clients::redirect_uris.eq(&cl.redirect_uris),
+                      clients::owner.eq(&cl.owner),
+                      clients::enabled.eq(cl.enabled)))
+                .execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_client(&self, cdb: &CachedDb<'_>, client_id: String) -> Result<usize> {
+        let cnt = cdb.db().await?.run(move |c|
+            diesel::delete(clients::table.filter(clients::client_id.eq(&client_id))).execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn active_scopes(&self, cdb: &CachedDb<'_>) -> Result<Vec<String>> {
+        let names = cdb.db().await?.run(move |c|
+            scopes::table.select(scopes::name).filter(scopes::retired.eq(false)).load(c)
+                ).await?;
+        Ok(names)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn mfa_scopes(&self, cdb: &CachedDb<'_>) -> Result<Vec<String>> {
+        let names = cdb.db().await?.run(move |c|
+            scopes::table.select(scopes::name).filter(scopes::require_mfa.eq(true)).load(c)
+                ).await?;
+        Ok(names)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn list_scopes(&self, cdb: &CachedDb<'_>) -> Result<Vec<Scope>> {
+        let scs = cdb.db().await?.run(move |c| scopes::table.order(scopes::name).load(c)).await?;
+        Ok(scs)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn insert_scope(&self, cdb: &CachedDb<'_>, name: String) -> Result<()> {
+        cdb.db().await?.run(move |c| diesel::insert_into(scopes::table).values(scopes::name.eq(name)).execute(c)).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn update_scope(&self, cdb: &CachedDb<'_>, name: String, changes: ScopeChanges) -> Result<usize> {
+        let cnt = cdb.db().await?.run(move |c|
+            diesel::update(scopes::table.filter(scopes::name.eq(&name))).set(&changes).execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_scope(&self, cdb: &CachedDb<'_>, name: String, revoke: bool) -> Result<ScopeRemoval> {
+        let x = cdb.db().await?.run(move |c| c.transaction::<_, diesel::result::Error, _>(|| {
+            let cnt = diesel::delete(scopes::table.filter(scopes::name.eq(&name))).execute(c)?;
+            let users = diesel::update(users::table.filter(users::scopes.contains(vec![name.clone()])))
+                .set(users::scopes.eq(array_remove!("scopes", &name)))
+                .returning(users::name)
+                .get_results(c)?;
+            let clients = diesel::update(clients::table.filter(clients::scopes.contains(vec![name.clone()])))
+                .set(clients::scopes.eq(array_remove!("scopes", &name)))
+                .returning(clients::client_id)
+                .get_results(c)?;
+            let toks = if revoke {
+                let toks: Vec<(String, SystemTime)> = diesel::delete(tokens::table.filter(tokens::scopes.contains(vec![name.clone()])))
+                    .returning((tokens::token, tokens::expiration))
+                    .get_results(c)?;
+                let rows: Vec<_> = toks.iter()
+                    .map(|(jti, exp)| (revoked_tokens::jti.eq(jti), revoked_tokens::expiration.eq(exp)))
+                    .collect();
+                if !rows.is_empty() {
+                    diesel::insert_into(revoked_tokens::table).values(rows).on_conflict_do_nothing().execute(c)?;
+                }
+                toks.into_iter().map(|(jti, _)| jti).collect()
+            } else {
+                diesel::update(tokens::table.filter(tokens::scopes.contains(vec![name.clone()])))
+                    .set(tokens::scopes.eq(array_remove!("scopes", &name)))
+                    .returning(tokens::token)
+                    .get_results(c)?
+            };
+            diesel::update(refresh_tokens::table.filter(refresh_tokens::scopes.contains(vec![name.clone()])))
+                .set(refresh_tokens::scopes.eq(array_remove!("scopes", &name)))
+                .execute(c)?;
+            Ok(ScopeRemoval{ scopes: cnt, users: users, clients: clients, tokens: toks })
+        })).await?;
+        Ok(x)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn get_token(&self, cdb: &CachedDb<'_>, name: String) -> Result<Token> {
+        let x = cdb.db().await?.run(move |c| tokens::table.filter(tokens::token.eq(&name)).first(c)).await?;
+        Ok(x)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn insert_token(&self, cdb: &CachedDb<'_>, tok: Token) -> Result<()> {
+        cdb.db().await?.run(|c| diesel::insert_into(tokens::table).values(tok).execute(c)).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn clean_tokens(&self, cdb: &CachedDb<'_>) -> Result<usize> {
+        let cnt = cdb.db().await?.run(|c|
+            diesel::delete(tokens::table)
+                    .filter(tokens::expiration.lt(now))
+                    .execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_token(&self, cdb: &CachedDb<'_>, name: String) -> Result<Vec<(String, SystemTime)>> {
+        let toks = cdb.db().await?.run(move |c|
+            diesel::delete(tokens::table.filter(tokens::token.eq(&name)))
+                .returning((tokens::token, tokens::expiration))
+                .get_results(c)
+                ).await?;
+        Ok(toks)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_user_tokens(&self, cdb: &CachedDb<'_>, user: String) -> Result<Vec<(String, SystemTime)>> {
+        let toks = cdb.db().await?.run(move |c|
+            diesel::delete(tokens::table.filter(tokens::username.eq(&user)))
+                .returning((tokens::token, tokens::expiration))
+                .get_results(c)
+                ).await?;
+        Ok(toks)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_client_tokens(&self, cdb: &CachedDb<'_>, client_id: String) -> Result<Vec<(String, SystemTime)>> {
+        let toks = cdb.db().await?.run(move |c|
+            diesel::delete(tokens::table.filter(tokens::client_id.eq(&client_id)))
+                .returning((tokens::token, tokens::expiration))
+                .get_results(c)
+                ).await?;
+        Ok(toks)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn get_refresh(&self, cdb: &CachedDb<'_>, name: String) -> Result<RefreshToken> {
+        let x = cdb.db().await?.run(move |c| refresh_tokens::table.filter(refresh_tokens::token.eq(&name)).first(c)).await?;
+        Ok(x)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn insert_refresh(&self, cdb: &CachedDb<'_>, tok: RefreshToken) -> Result<()> {
+        cdb.db().await?.run(|c| diesel::insert_into(refresh_tokens::table).values(tok).execute(c)).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn refresh_family(&self, cdb: &CachedDb<'_>, access: String) -> Result<Option<String>> {
+        let x = cdb.db().await?.run(move |c|
+            refresh_tokens::table.filter(refresh_tokens::access.eq(&access)).select(refresh_tokens::family).first(c).optional()
+                ).await?;
+        Ok(x)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn mark_used(&self, cdb: &CachedDb<'_>, name: String) -> Result<bool> {
+        let cnt = cdb.db().await?.run(move |c|
+            diesel::update(refresh_tokens::table
+                    .filter(refresh_tokens::token.eq(&name))
+                    .filter(refresh_tokens::used.eq(false)))
+                .set(refresh_tokens::used.eq(true))
+                .execute(c)
+                ).await?;
+        Ok(cnt == 1)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_family(&self, cdb: &CachedDb<'_>, family: String) -> Result<Vec<String>> {
+        let access = cdb.db().await?.run(move |c|
+            diesel::delete(refresh_tokens::table.filter(refresh_tokens::family.eq(&family)))
+                .returning(refresh_tokens::access)
+                .get_results(c)
+                ).await?;
+        Ok(access)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_user_refresh(&self, cdb: &CachedDb<'_>, user: String) -> Result<usize> {
+        let cnt = cdb.db().await?.run(move |c|
+            diesel::delete(refresh_tokens::table.filter(refresh_tokens::username.eq(&user))).execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_client_refresh(&self, cdb: &CachedDb<'_>, client_id: String) -> Result<usize> {
+        let cnt = cdb.db().await?.run(move |c|
+            diesel::delete(refresh_tokens::table.filter(refresh_tokens::client_id.eq(&client_id))).execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn clean_refresh(&self, cdb: &CachedDb<'_>) -> Result<usize> {
+        let cnt = cdb.db().await?.run(|c|
+            diesel::delete(refresh_tokens::table)
+                    .filter(refresh_tokens::expiration.lt(now))
+                    .execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn is_revoked(&self, cdb: &CachedDb<'_>, jti: String) -> Result<bool> {
+        let cnt: i64 = cdb.db().await?.run(move |c|
+            revoked_tokens::table.filter(revoked_tokens::jti.eq(&jti)).count().get_result(c)
+                ).await?;
+        Ok(cnt > 0)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn revoke(&self, cdb: &CachedDb<'_>, jti: String, expiration: SystemTime) -> Result<()> {
+        cdb.db().await?.run(move |c|
+            diesel::insert_into(revoked_tokens::table)
+                .values((revoked_tokens::jti.eq(&jti), revoked_tokens::expiration.eq(expiration)))
+                .on_conflict_do_nothing()
+                .execute(c)
+                ).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn clean_revoked(&self, cdb: &CachedDb<'_>) -> Result<usize> {
+        let cnt = cdb.db().await?.run(|c|
+            diesel::delete(revoked_tokens::table)
+                    .filter(revoked_tokens::expiration.lt(now))
+                    .execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn get_totp(&self, cdb: &CachedDb<'_>, name: String) -> Result<Totp> {
+        let x = cdb.db().await?.run(move |c| totp::table.filter(totp::username.eq(&name)).first(c)).await?;
+        Ok(x)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn put_totp(&self, cdb: &CachedDb<'_>, t: Totp) -> Result<()> {
+        cdb.db().await?.run(move |c|
+            diesel::insert_into(totp::table)
+                .values(&t)
+                .on_conflict(totp::username)
+                .do_update()
+                .set((totp::secret.eq(&t.secret), totp::confirmed.eq(false), totp::last_step.eq(0)))
+                .execute(c)
+                ).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn confirm_totp(&self, cdb: &CachedDb<'_>, name: String, step: i64) -> Result<()> {
+        cdb.db().await?.run(move |c|
+            diesel::update(totp::table.filter(totp::username.eq(&name)))
+                .set((totp::confirmed.eq(true), totp::last_step.eq(step)))
+                .execute(c)
+                ).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn use_step(&self, cdb: &CachedDb<'_>, name: String, step: i64) -> Result<bool> {
+        let cnt = cdb.db().await?.run(move |c|
+            diesel::update(totp::table
+                    .filter(totp::username.eq(&name))
+                    .filter(totp::last_step.lt(step)))
+                .set(totp::last_step.eq(step))
+                .execute(c)
+                ).await?;
+        Ok(cnt == 1)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_totp(&self, cdb: &CachedDb<'_>, name: String) -> Result<usize> {
+        let cnt = cdb.db().await?.run(move |c| c.transaction(|| {
+            diesel::delete(recovery_codes::table.filter(recovery_codes::username.eq(&name))).execute(c)?;
+            diesel::delete(totp::table.filter(totp::username.eq(&name))).execute(c)
+        })).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn put_recovery_codes(&self, cdb: &CachedDb<'_>, name: String, hashes: Vec<String>) -> Result<()> {
+        cdb.db().await?.run(move |c| c.transaction(|| {
+            diesel::delete(recovery_codes::table.filter(recovery_codes::username.eq(&name))).execute(c)?;
+            let rows: Vec<_> = hashes.iter()
+                .map(|h| (recovery_codes::username.eq(&name), recovery_codes::hash.eq(h)))
+                .collect();
+            diesel::insert_into(recovery_codes::table).values(rows).execute(c)
+        })).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn use_recovery_code(&self, cdb: &CachedDb<'_>, name: String, hash: String) -> Result<bool> {
+        let cnt = cdb.db().await?.run(move |c|
+            diesel::delete(recovery_codes::table
+                    .filter(recovery_codes::username.eq(&name))
+                    .filter(recovery_codes::hash.eq(&hash)))
+                .execute(c)
+                ).await?;
+        Ok(cnt == 1)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn insert_event(&self, cdb: &CachedDb<'_>, ev: NewEvent) -> Result<()> {
+        cdb.db().await?.run(move |c| diesel::insert_into(audit_events::table).values(&ev).execute(c)).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn list_events(&self, cdb: &CachedDb<'_>, f: Filter, offset: i64, limit: i64) -> Result<Vec<Event>> {
+        let evs = cdb.db().await?.run(move |c| {
+            let mut q = audit_events::table.into_boxed();
+            if let Some(user) = f.user {
+                q = q.filter(audit_events::actor.eq(user.clone()).or(audit_events::subject.eq(user)));
+            }
+            if let Some(event) = f.event {
+                q = q.filter(audit_events::event.eq(event));
+            }
+            if let Some(since) = f.since {
+                q = q.filter(audit_events::time.ge(since));
+            }
+            if let Some(until) = f.until {
+                q = q.filter(audit_events::time.lt(until));
+            }
+            q.order(audit_events::id.desc()).offset(offset).limit(limit).load(c)
+        }).await?;
+        Ok(evs)
+    }
+}
@@ -0,0 +1,867 @@
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rocket::{Rocket, Build};
+use rocket::serde::json::serde_json;
+use rocket_sync_db_pools::diesel::prelude::*;
+use tracing::instrument;
+
+use crate::{Result, Error};
+use crate::rocktypes::{CachedDb, SqliteDb};
+use crate::model::user::User;
+use crate::model::client::Client;
+use crate::model::scopes::{Scope, ScopeChanges, ScopeRemoval};
+use crate::model::token::Token;
+use crate::model::refresh::RefreshToken;
+use crate::model::totp::Totp;
+use crate::model::audit::{Event, NewEvent, Filter};
+use crate::storage::{Storage, MigrationVersion, MIGRATION_VERSION};
+
+use self::schema::{users, clients, scopes, tokens, refresh_tokens, revoked_tokens, totp, recovery_codes, audit_events};
+
+/*
+ * Everything in a single sqlite file, through the "sqlite" database pool.
+ * sqlite has no arrays or timestamps, so scopes and other lists are kept as json lists
+ * and times as unix seconds.
+ */
+pub struct Sqlite;
+
+embed_migrations!("migrations_sqlite");
+
+mod schema {
+    table! {
+        audit_events (id) {
+            id -> BigInt,
+            time -> BigInt,
+            event -> Text,
+            actor -> Nullable<Text>,
+            actor_token -> Nullable<Text>,
+            subject -> Nullable<Text>,
+            client_ip -> Nullable<Text>,
+            detail -> Text,
+        }
+    }
+
+    table! {
+        clients (client_id) {
+            client_id -> Text,
+            secrets -> Text,
+            scopes -> Text,
+            grant_types -> Text,
+            redirect_uris -> Text,
+            owner -> Text,
+            enabled -> Bool,
+            created -> BigInt,
+        }
+    }
+
+    table! {
+        recovery_codes (username, hash) {
+            username -> Text,
+            hash -> Text,
+        }
+    }
+
+    table! {
+        refresh_tokens (token) {
+            token -> Text,
+            family -> Text,
+            access -> Text,
+            username -> Text,
+            expiration -> BigInt,
+            scopes -> Text,
+            used -> Bool,
+            client_id -> Nullable<Text>,
+        }
+    }
+
+    table! {
+        revoked_tokens (jti) {
+            jti -> Text,
+            expiration -> BigInt,
+        }
+    }
+
+    table! {
+        scopes (name) {
+            name -> Text,
+            description -> Text,
+            created -> BigInt,
+            retired -> Bool,
+            require_mfa -> Bool,
+        }
+    }
+
+    table! {
+        tokens (token) {
+            token -> Text,
+            username -> Text,
+            expiration -> BigInt,
+            scopes -> Text,
+            issued -> BigInt,
+            client_id -> Nullable<Text>,
+        }
+    }
+
+    table! {
+        totp (username) {
+            username -> Text,
+            secret -> Text,
+            confirmed -> Bool,
+            last_step -> BigInt,
+        }
+    }
+
+    table! {
+        users (name) {
+            name -> Text,
+            hash -> Text,
+            expiration -> BigInt,
+            enabled -> Bool,
+            scopes -> Text,
+            require_mfa -> Bool,
+            mfa_enroll -> Bool,
+        }
+    }
+}
+
+fn secs(t: SystemTime) -> i64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+fn time(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+fn now_secs() -> i64 {
+    secs(SystemTime::now())
+}
+
+fn to_json(scopes: &[String]) -> String {
+    serde_json::to_string(scopes).unwrap() // safe
+}
+
+fn from_json(scopes: &str) -> Vec<String> {
+    serde_json::from_str(scopes).unwrap_or_default()
+}
+
+// A LIKE pattern matching every json list that might hold scope. Matches still need checking.
+fn scope_pattern(scope: &str) -> String {
+    format!("%{}%", serde_json::to_string(scope).unwrap()) // safe
+}
+
+// The json list without scope, or None if it didn't have it.
+fn without(scopes: &str, scope: &str) -> Option<String> {
+    let mut v = from_json(scopes);
+    let len = v.len();
+    v.retain(|s| s != scope);
+    (v.len() != len).then(|| to_json(&v))
+}
+
+#[derive(Queryable)]
+struct UserRow {
+    name: String,
+    hash: String,
+    expiration: i64,
+    enabled: bool,
+    scopes: String,
+    require_mfa: bool,
+    mfa_enroll: bool,
+}
+
+impl From<UserRow> for User {
+    fn from(r: UserRow) -> Self {
+        User {
+            name: r.name,
+            hash: r.hash,
+            expiration: time(r.expiration),
+            enabled: r.enabled,
+            scopes: from_json(&r.scopes),
+            require_mfa: r.require_mfa,
+            mfa_enroll: r.mfa_enroll,
+        }
+    }
+}
+
+#[derive(Queryable)]
+struct ClientRow {
+    client_id: String,
+    secrets: String,
+    scopes: String,
+    grant_types: String,
+    redirect_uris: String,
+    owner: String,
+    enabled: bool,
+    created: i64,
+}
+
+impl From<ClientRow> for Client {
+    fn from(r: ClientRow) -> Self {
+        Client {
+            client_id: r.client_id,
+            secrets: from_json(&r.secrets),
+            scopes: from_json(&r.scopes),
+            grant_types: from_json(&r.grant_types),
+            redirect_uris: from_json(&r.redirect_uris),
+            owner: r.owner,
+            enabled: r.enabled,
+            created: time(r.created),
+        }
+    }
+}
+
+#[derive(Queryable)]
+struct ScopeRow {
+    name: String,
+    description: String,
+    created: i64,
+    retired: bool,
+    require_mfa: bool,
+}
+
+impl From<ScopeRow> for Scope {
+    fn from(r: ScopeRow) -> Self {
+        Scope {
+            name: r.name,
+            description: r.description,
+            created: time(r.created),
+            retired: r.retired,
+            require_mfa: r.require_mfa,
+        }
+    }
+}
+
+#[derive(Queryable)]
+struct TokenRow {
+    token: String,
+    username: String,
+    expiration: i64,
+    scopes: String,
+    issued: i64,
+    client_id: Option<String>,
+}
+
+impl From<TokenRow> for Token {
+    fn from(r: TokenRow) -> Self {
+        Token {
+            token: r.token,
+            username: r.username,
+            expiration: time(r.expiration),
+            scopes: from_json(&r.scopes),
+            issued: time(r.issued),
+            client_id: r.client_id,
+        }
+    }
+}
+
+#[derive(Queryable)]
+struct RefreshRow {
+    token: String,
+    family: String,
+    access: String,
+    username: String,
+    expiration: i64,
+    scopes: String,
+    used: bool,
+    client_id: Option<String>,
+}
+
+impl From<RefreshRow> for RefreshToken {
+    fn from(r: RefreshRow) -> Self {
+        RefreshToken {
+            token: r.token,
+            family: r.family,
+            access: r.access,
+            username: r.username,
+            expiration: time(r.expiration),
+            scopes: from_json(&r.scopes),
+            used: r.used,
+            client_id: r.client_id,
+        }
+    }
+}
+
+#[derive(Queryable)]
+struct EventRow {
+    id: i64,
+    time: i64,
+    event: String,
+    actor: Option<String>,
+    actor_token: Option<String>,
+    subject: Option<String>,
+    client_ip: Option<String>,
+    detail: String,
+}
+
+impl From<EventRow> for Event {
+    fn from(r: EventRow) -> Self {
+        Event {
+            id: r.id,
+            time: time(r.time),
+            event: r.event,
+            actor: r.actor,
+            actor_token: r.actor_token,
+            subject: r.subject,
+            client_ip: r.client_ip,
+            detail: r.detail,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for Sqlite {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn database(&self) -> &'static str {
+        "sqlite"
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn migrate(&self, rocket: &Rocket<Build>) -> Result<()> {
+        let conn = SqliteDb::get_one(rocket).await
+            .ok_or_else(|| Error::Unavailable("no database connection".to_owned()))?;
+        conn.run(|c| embedded_migrations::run(c)).await
+            .map_err(|e| Error::Unavailable(e.to_string()))
+    }
+
+    fn latest_migration(&self) -> &'static str {
+        "20261018100000"
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn migration_version(&self, cdb: &CachedDb<'_>) -> Result<Option<String>> {
+        let v: MigrationVersion = cdb.sqlite().await?.run(|c| diesel::sql_query(MIGRATION_VERSION).get_result(c)).await?;
+        Ok(v.version)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn ping(&self, cdb: &CachedDb<'_>) -> Result<()> {
+        cdb.sqlite().await?.run(|c| diesel::sql_query("SELECT 1").execute(c)).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn get_user(&self, cdb: &CachedDb<'_>, name: String) -> Result<User> {
+        let r: UserRow = cdb.sqlite().await?.run(move |c| users::table.filter(users::name.eq(&name)).first(c)).await?;
+        Ok(r.into())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn insert_user(&self, cdb: &CachedDb<'_>, u: User) -> Result<()> {
+        cdb.sqlite().await?.run(move |c|
+            diesel::insert_into(users::table)
+                .values((users::name.eq(&u.name),
+                         users::hash.eq(&u.hash),
+                         users::expiration.eq(secs(u.expiration)),
+                         users::enabled.eq(u.enabled),
+                         users::scopes.eq(to_json(&u.scopes)),
+                         users::require_mfa.eq(u.require_mfa),
+                         users::mfa_enroll.eq(u.mfa_enroll)))
+                .execute(c)
+                ).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn list_users(&self, cdb: &CachedDb<'_>, offset: i64, limit: i64) -> Result<Vec<User>> {
+        let rs: Vec<UserRow> = cdb.sqlite().await?.run(move |c|
+            users::table.order(users::name).offset(offset).limit(limit).load(c)
+                ).await?;
+        Ok(rs.into_iter().map(User::from).collect())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn update_user(&self, cdb: &CachedDb<'_>, u: User) -> Result<usize> {
+        let cnt = cdb.sqlite().await?.run(move |c|
+            diesel::update(users::table.filter(users::name.eq(&u.name)))
+                .set((users::hash.eq(&u.hash),
+                      users::expiration.eq(secs(u.expiration)),
+                      users::enabled.eq(u.enabled),
+                      users::scopes.eq(to_json(&u.scopes)),
+                      users::require_mfa.eq(u.require_mfa),
+                      users::mfa_enroll.eq(u.mfa_enroll)))
+                .execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn set_user_hash(&self, cdb: &CachedDb<'_>, name: String, old_hash: String, new_hash: String) -> Result<usize> {
+        let cnt = cdb.sqlite().await?.run(move |c|
+            diesel::update(users::table.filter(users::name.eq(&name)).filter(users::hash.eq(&old_hash)))
+                .set(users::hash.eq(&new_hash))
+                .execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_user(&self, cdb: &CachedDb<'_>, name: String) -> Result<usize> {
+        let cnt = cdb.sqlite().await?.run(move |c|
+            diesel::delete(users::table.filter(users::name.eq(&name))).execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn scope_holders(&self, cdb: &CachedDb<'_>, scope: String) -> Result<Vec<String>> {
+        let pattern = scope_pattern(&scope);
+        let rows: Vec<(String, String)> = cdb.sqlite().await?.run(move |c|
+            users::table.select((users::name, users::scopes))
+                .filter(users::scopes.like(pattern))
+                .filter(users::enabled.eq(true))
+                .filter(users::expiration.gt(now_secs()))
+                .load(c)
+                ).await?;
+        Ok(rows.into_iter()
+            .filter(|(_, scs)| from_json(scs).contains(&scope))
+            .map(|(name, _)| name)
+            .collect())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn get_client(&self, cdb: &CachedDb<'_>, client_id: String) -> Result<Client> {
+        let r: ClientRow = cdb.sqlite().await?.run(move |c|
+            clients::table.filter(clients::client_id.eq(&client_id)).first(c)
+                ).await?;
+        Ok(r.into())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn insert_client(&self, cdb: &CachedDb<'_>, cl: Client) -> Result<()> {
+        cdb.sqlite().await?.run(move |c|
+            diesel::insert_into(clients::table)
+                .values((clients::client_id.eq(&cl.client_id),
+                         clients::secrets.eq(to_json(&cl.secrets)),
+                         clients::scopes.eq(to_json(&cl.scopes)),
+                         clients::grant_types.eq(to_json(&cl.grant_types)),
+                         clients::redirect_uris.eq(to_json(&cl.redirect_uris)),
+                         clients::owner.eq(&cl.owner),
+                         clients::enabled.eq(cl.enabled),
+                         clients::created.eq(secs(cl.created))))
+                .execute(c)
+                ).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn list_clients(&self, cdb: &CachedDb<'_>, offset: i64, limit: i64) -> Result<Vec<Client>> {
+        let rs: Vec<ClientRow> = cdb.sqlite().await?.run(move |c|
+            clients::table.order(clients::client_id).offset(offset).limit(limit).load(c)
+                ).await?;
+        Ok(rs.into_iter().map(Client::from).collect())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn update_client(&self, cdb: &CachedDb<'_>, cl: Client) -> Result<usize> {
+        let cnt = cdb.sqlite().await?.run(move |c|
+            diesel::update(clients::table.filter(clients::client_id.eq(&cl.client_id)))
+                .set((clients::secrets.eq(to_json(&cl.secrets)),
+                      clients::scopes.eq(to_json(&cl.scopes)),
+                      clients::grant_types.eq(to_json(&cl.grant_types)),
+                      clients::redirect_uris.eq(to_json(&cl.redirect_uris)),
+                      clients::owner.eq(&cl.owner),
+                      clients::enabled.eq(cl.enabled)))
+                .execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_client(&self, cdb: &CachedDb<'_>, client_id: String) -> Result<usize> {
+        let cnt = cdb.sqlite().await?.run(move |c|
+            diesel::delete(clients::table.filter(clients::client_id.eq(&client_id))).execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn active_scopes(&self, cdb: &CachedDb<'_>) -> Result<Vec<String>> {
+        let names = cdb.sqlite().await?.run(move |c|
+            scopes::table.select(scopes::name).filter(scopes::retired.eq(false)).load(c)
+                ).await?;
+        Ok(names)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn mfa_scopes(&self, cdb: &CachedDb<'_>) -> Result<Vec<String>> {
+        let names = cdb.sqlite().await?.run(move |c|
+            scopes::table.select(scopes::name).filter(scopes::require_mfa.eq(true)).load(c)
+                ).await?;
+        Ok(names)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn list_scopes(&self, cdb: &CachedDb<'_>) -> Result<Vec<Scope>> {
+        let rs: Vec<ScopeRow> = cdb.sqlite().await?.run(move |c| scopes::table.order(scopes::name).load(c)).await?;
+        Ok(rs.into_iter().map(Scope::from).collect())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn insert_scope(&self, cdb: &CachedDb<'_>, name: String) -> Result<()> {
+        cdb.sqlite().await?.run(move |c|
+            diesel::insert_into(scopes::table)
+                .values((scopes::name.eq(name), scopes::created.eq(now_secs())))
+                .execute(c)
+                ).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn update_scope(&self, cdb: &CachedDb<'_>, name: String, changes: ScopeChanges) -> Result<usize> {
+        let cnt = cdb.sqlite().await?.run(move |c|
+            diesel::update(scopes::table.filter(scopes::name.eq(&name)))
+                .set((changes.description.map(|d| scopes::description.eq(d)),
+                      changes.retired.map(|r| scopes::retired.eq(r)),
+                      changes.require_mfa.map(|m| scopes::require_mfa.eq(m))))
+                .execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_scope(&self, cdb: &CachedDb<'_>, name: String, revoke: bool) -> Result<ScopeRemoval> {
+        let x = cdb.sqlite().await?.run(move |c| c.transaction::<_, diesel::result::Error, _>(|| {
+            let pattern = scope_pattern(&name);
+            let cnt = diesel::delete(scopes::table.filter(scopes::name.eq(&name))).execute(c)?;
+
+            let rows: Vec<(String, String)> = users::table
+                .select((users::name, users::scopes))
+                .filter(users::scopes.like(&pattern))
+                .load(c)?;
+            let mut users = Vec::new();
+            for (user, scs) in rows.into_iter() {
+                if let Some(scs) = without(&scs, &name) {
+                    diesel::update(users::table.filter(users::name.eq(&user))).set(users::scopes.eq(scs)).execute(c)?;
+                    users.push(user);
+                }
+            }
+
+            let rows: Vec<(String, String)> = clients::table
+                .select((clients::client_id, clients::scopes))
+                .filter(clients::scopes.like(&pattern))
+                .load(c)?;
+            let mut clients = Vec::new();
+            for (id, scs) in rows.into_iter() {
+                if let Some(scs) = without(&scs, &name) {
+                    diesel::update(clients::table.filter(clients::client_id.eq(&id))).set(clients::scopes.eq(scs)).execute(c)?;
+                    clients.push(id);
+                }
+            }
+
+            let rows: Vec<(String, i64, String)> = tokens::table
+                .select((tokens::token, tokens::expiration, tokens::scopes))
+                .filter(tokens::scopes.like(&pattern))
+                .load(c)?;
+            let mut toks = Vec::new();
+            for (tok, exp, scs) in rows.into_iter() {
+                let scs = match without(&scs, &name) {
+                    Some(scs) => scs,
+                    None => continue,
+                };
+                if revoke {
+                    diesel::delete(tokens::table.filter(tokens::token.eq(&tok))).execute(c)?;
+                    diesel::insert_or_ignore_into(revoked_tokens::table)
+                        .values((revoked_tokens::jti.eq(&tok), revoked_tokens::expiration.eq(exp)))
+                        .execute(c)?;
+                } else {
+                    diesel::update(tokens::table.filter(tokens::token.eq(&tok))).set(tokens::scopes.eq(scs)).execute(c)?;
+                }
+                toks.push(tok);
+            }
+
+            let rows: Vec<(String, String)> = refresh_tokens::table
+                .select((refresh_tokens::token, refresh_tokens::scopes))
+                .filter(refresh_tokens::scopes.like(&pattern))
+                .load(c)?;
+            for (tok, scs) in rows.into_iter() {
+                if let Some(scs) = without(&scs, &name) {
+                    diesel::update(refresh_tokens::table.filter(refresh_tokens::token.eq(&tok)))
+                        .set(refresh_tokens::scopes.eq(scs))
+                        .execute(c)?;
+                }
+            }
+            Ok(ScopeRemoval{ scopes: cnt, users: users, clients: clients, tokens: toks })
+        })).await?;
+        Ok(x)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn get_token(&self, cdb: &CachedDb<'_>, name: String) -> Result<Token> {
+        let r: TokenRow = cdb.sqlite().await?.run(move |c| tokens::table.filter(tokens::token.eq(&name)).first(c)).await?;
+        Ok(r.into())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn insert_token(&self, cdb: &CachedDb<'_>, tok: Token) -> Result<()> {
+        cdb.sqlite().await?.run(move |c|
+            diesel::insert_into(tokens::table)
+                .values((tokens::token.eq(&tok.token),
+                         tokens::username.eq(&tok.username),
+                         tokens::expiration.eq(secs(tok.expiration)),
+                         tokens::scopes.eq(to_json(&tok.scopes)),
+                         tokens::issued.eq(secs(tok.issued)),
+                         tokens::client_id.eq(&tok.client_id)))
+                .execute(c)
+                ).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn clean_tokens(&self, cdb: &CachedDb<'_>) -> Result<usize> {
+        let cnt = cdb.sqlite().await?.run(|c|
+            diesel::delete(tokens::table.filter(tokens::expiration.lt(now_secs()))).execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    // sqlite can't return what it deleted, so it is looked up first.
+    #[instrument(level = "debug", skip_all)]
+    async fn del_token(&self, cdb: &CachedDb<'_>, name: String) -> Result<Vec<(String, SystemTime)>> {
+        let toks: Vec<(String, i64)> = cdb.sqlite().await?.run(move |c| c.transaction::<_, diesel::result::Error, _>(|| {
+            let toks = tokens::table.filter(tokens::token.eq(&name)).select((tokens::token, tokens::expiration)).load(c)?;
+            diesel::delete(tokens::table.filter(tokens::token.eq(&name))).execute(c)?;
+            Ok(toks)
+        })).await?;
+        Ok(toks.into_iter().map(|(name, exp)| (name, time(exp))).collect())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_user_tokens(&self, cdb: &CachedDb<'_>, user: String) -> Result<Vec<(String, SystemTime)>> {
+        let toks: Vec<(String, i64)> = cdb.sqlite().await?.run(move |c| c.transaction::<_, diesel::result::Error, _>(|| {
+            let toks = tokens::table.filter(tokens::username.eq(&user)).select((tokens::token, tokens::expiration)).load(c)?;
+            diesel::delete(tokens::table.filter(tokens::username.eq(&user))).execute(c)?;
+            Ok(toks)
+        })).await?;
+        Ok(toks.into_iter().map(|(name, exp)| (name, time(exp))).collect())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_client_tokens(&self, cdb: &CachedDb<'_>, client_id: String) -> Result<Vec<(String, SystemTime)>> {
+        let toks: Vec<(String, i64)> = cdb.sqlite().await?.run(move |c| c.transaction::<_, diesel::result::Error, _>(|| {
+            let toks = tokens::table.filter(tokens::client_id.eq(&client_id)).select((tokens::token, tokens::expiration)).load(c)?;
+            diesel::delete(tokens::table.filter(tokens::client_id.eq(&client_id))).execute(c)?;
+            Ok(toks)
+        })).await?;
+        Ok(toks.into_iter().map(|(name, exp)| (name, time(exp))).collect())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn get_refresh(&self, cdb: &CachedDb<'_>, name: String) -> Result<RefreshToken> {
+        let r: RefreshRow = cdb.sqlite().await?.run(move |c|
+            refresh_tokens::table.filter(refresh_tokens::token.eq(&name)).first(c)
+                ).await?;
+        Ok(r.into())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn insert_refresh(&self, cdb: &CachedDb<'_>, tok: RefreshToken) -> Result<()> {
+        cdb.sqlite().await?.run(move |c|
+            diesel::insert_into(refresh_tokens::table)
+                .values((refresh_tokens::token.eq(&tok.token),
+                         refresh_tokens::family.eq(&tok.family),
+                         refresh_tokens::access.eq(&tok.access),
+                         refresh_tokens::username.eq(&tok.username),
+                         refresh_tokens::expiration.eq(secs(tok.expiration)),
+                         refresh_tokens::scopes.eq(to_json(&tok.scopes)),
+                         refresh_tokens::used.eq(tok.used),
+                         refresh_tokens::client_id.eq(&tok.client_id)))
+                .execute(c)
+                ).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn refresh_family(&self, cdb: &CachedDb<'_>, access: String) -> Result<Option<String>> {
+        let x = cdb.sqlite().await?.run(move |c|
+            refresh_tokens::table.filter(refresh_tokens::access.eq(&access)).select(refresh_tokens::family).first(c).optional()
+                ).await?;
+        Ok(x)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn mark_used(&self, cdb: &CachedDb<'_>, name: String) -> Result<bool> {
+        let cnt = cdb.sqlite().await?.run(move |c|
+            diesel::update(refresh_tokens::table
+                    .filter(refresh_tokens::token.eq(&name))
+                    .filter(refresh_tokens::used.eq(false)))
+                .set(refresh_tokens::used.eq(true))
+                .execute(c)
+                ).await?;
+        Ok(cnt == 1)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_family(&self, cdb: &CachedDb<'_>, family: String) -> Result<Vec<String>> {
+        let access = cdb.sqlite().await?.run(move |c| c.transaction::<_, diesel::result::Error, _>(|| {
+            let access = refresh_tokens::table.filter(refresh_tokens::family.eq(&family)).select(refresh_tokens::access).load(c)?;
+            diesel::delete(refresh_tokens::table.filter(refresh_tokens::family.eq(&family))).execute(c)?;
+            Ok(access)
+        })).await?;
+        Ok(access)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_user_refresh(&self, cdb: &CachedDb<'_>, user: String) -> Result<usize> {
+        let cnt = cdb.sqlite().await?.run(move |c|
+            diesel::delete(refresh_tokens::table.filter(refresh_tokens::username.eq(&user))).execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_client_refresh(&self, cdb: &CachedDb<'_>, client_id: String) -> Result<usize> {
+        let cnt = cdb.sqlite().await?.run(move |c|
+            diesel::delete(refresh_tokens::table.filter(refresh_tokens::client_id.eq(&client_id))).execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn clean_refresh(&self, cdb: &CachedDb<'_>) -> Result<usize> {
+        let cnt = cdb.sqlite().await?.run(|c|
+            diesel::delete(refresh_tokens::table.filter(refresh_tokens::expiration.lt(now_secs()))).execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn is_revoked(&self, cdb: &CachedDb<'_>, jti: String) -> Result<bool> {
+        let cnt: i64 = cdb.sqlite().await?.run(move |c|
+            revoked_tokens::table.filter(revoked_tokens::jti.eq(&jti)).count().get_result(c)
+                ).await?;
+        Ok(cnt > 0)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn revoke(&self, cdb: &CachedDb<'_>, jti: String, expiration: SystemTime) -> Result<()> {
+        cdb.sqlite().await?.run(move |c|
+            diesel::insert_or_ignore_into(revoked_tokens::table)
+                .values((revoked_tokens::jti.eq(&jti), revoked_tokens::expiration.eq(secs(expiration))))
+                .execute(c)
+                ).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn clean_revoked(&self, cdb: &CachedDb<'_>) -> Result<usize> {
+        let cnt = cdb.sqlite().await?.run(|c|
+            diesel::delete(revoked_tokens::table.filter(revoked_tokens::expiration.lt(now_secs()))).execute(c)
+                ).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn get_totp(&self, cdb: &CachedDb<'_>, name: String) -> Result<Totp> {
+        let x = cdb.sqlite().await?.run(move |c| totp::table.filter(totp::username.eq(&name)).first(c)).await?;
+        Ok(x)
+    }
+
+    // Replacing the row starts the secret over, unconfirmed.
+    #[instrument(level = "debug", skip_all)]
+    async fn put_totp(&self, cdb: &CachedDb<'_>, t: Totp) -> Result<()> {
+        cdb.sqlite().await?.run(move |c|
+            diesel::replace_into(totp::table)
+                .values((totp::username.eq(&t.username),
+                         totp::secret.eq(&t.secret),
+                         totp::confirmed.eq(false),
+                         totp::last_step.eq(0)))
+                .execute(c)
+                ).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn confirm_totp(&self, cdb: &CachedDb<'_>, name: String, step: i64) -> Result<()> {
+        cdb.sqlite().await?.run(move |c|
+            diesel::update(totp::table.filter(totp::username.eq(&name)))
+                .set((totp::confirmed.eq(true), totp::last_step.eq(step)))
+                .execute(c)
+                ).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn use_step(&self, cdb: &CachedDb<'_>, name: String, step: i64) -> Result<bool> {
+        let cnt = cdb.sqlite().await?.run(move |c|
+            diesel::update(totp::table
+                    .filter(totp::username.eq(&name))
+                    .filter(totp::last_step.lt(step)))
+                .set(totp::last_step.eq(step))
+                .execute(c)
+                ).await?;
+        Ok(cnt == 1)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn del_totp(&self, cdb: &CachedDb<'_>, name: String) -> Result<usize> {
+        let cnt = cdb.sqlite().await?.run(move |c| c.transaction::<_, diesel::result::Error, _>(|| {
+            diesel::delete(recovery_codes::table.filter(recovery_codes::username.eq(&name))).execute(c)?;
+            diesel::delete(totp::table.filter(totp::username.eq(&name))).execute(c)
+        })).await?;
+        Ok(cnt)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn put_recovery_codes(&self, cdb: &CachedDb<'_>, name: String, hashes: Vec<String>) -> Result<()> {
+        cdb.sqlite().await?.run(move |c| c.transaction::<_, diesel::result::Error, _>(|| {
+            diesel::delete(recovery_codes::table.filter(recovery_codes::username.eq(&name))).execute(c)?;
+            for h in hashes.iter() {
+                diesel::insert_into(recovery_codes::table)
+                    .values((recovery_codes::username.eq(&name), recovery_codes::hash.eq(h)))
+                    .execute(c)?;
+            }
+            Ok(())
+        })).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn use_recovery_code(&self, cdb: &CachedDb<'_>, name: String, hash: String) -> Result<bool> {
+        let cnt = cdb.sqlite().await?.run(move |c|
+            diesel::delete(recovery_codes::table
+                    .filter(recovery_codes::username.eq(&name))
+                    .filter(recovery_codes::hash.eq(&hash)))
+                .execute(c)
+                ).await?;
+        Ok(cnt == 1)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn insert_event(&self, cdb: &CachedDb<'_>, ev: NewEvent) -> Result<()> {
+        cdb.sqlite().await?.run(move |c|
+            diesel::insert_into(audit_events::table)
+                .values((audit_events::time.eq(now_secs()),
+                         audit_events::event.eq(ev.event),
+                         audit_events::actor.eq(ev.actor),
+                         audit_events::actor_token.eq(ev.actor_token),
+                         audit_events::subject.eq(ev.subject),
+                         audit_events::client_ip.eq(ev.client_ip),
+                         audit_events::detail.eq(ev.detail)))
+                .execute(c)
+                ).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn list_events(&self, cdb: &CachedDb<'_>, f: Filter, offset: i64, limit: i64) -> Result<Vec<Event>> {
+        let rs: Vec<EventRow> = cdb.sqlite().await?.run(move |c| {
+            let mut q = audit_events::table.into_boxed();
+            if let Some(user) = f.user {
+                q = q.filter(audit_events::actor.eq(user.clone()).or(audit_events::subject.eq(user)));
+            }
+            if let Some(event) = f.event {
+                q = q.filter(audit_events::event.eq(event));
+            }
+            if let Some(since) = f.since {
+                q = q.filter(audit_events::time.ge(secs(since)));
+            }
+            if let Some(until) = f.until {
+                q = q.filter(audit_events::time.lt(secs(until)));
+            }
+            q.order(audit_events::id.desc()).offset(offset).limit(limit).load(c)
+        }).await?;
+        Ok(rs.into_iter().map(Event::from).collect())
+    }
+}
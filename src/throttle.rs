@@ -0,0 +1,188 @@
+
+/*
+ * Login throttling. Failed logins are counted per user name and per
+ * client address. Once a count passes its threshold, every further
+ * failure locks the name or address out, for twice as long as the
+ * time before. The address is the socket's, unless the request came
+ * through one of the trusted_proxies, see rocktypes::client_addr.
+ *
+ * With the redis cache backend the counts are kept in redis and shared
+ * by every node. If redis can't be reached logins aren't throttled,
+ * rather than failing outright. With any other backend, even "none",
+ * they are kept in this process, so each node throttles on its own.
+ */
+use std::net::IpAddr;
+use rocket::serde::{Serialize, Deserialize};
+
+use crate::Result;
+use crate::cache;
+use crate::cache::backend::{self, Backend};
+use crate::rocktypes::CachedDb;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct Config {
+    pub user_failures: u64, // failures allowed for a user name before it is locked out
+    pub ip_failures: u64,   // failures allowed from an address before it is locked out
+    pub lockout: u64,       // seconds the first lockout lasts
+    pub max_lockout: u64,   // longest a lockout can last
+    pub window: u64,        // seconds a failure is remembered for
+    pub size: usize,        // subjects counted at once, when not kept in redis
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            user_failures: 5,
+            ip_failures: 20,
+            lockout: 30,
+            max_lockout: 3600,
+            window: 900,
+            size: 100000,
+        }
+    }
+}
+
+// Where the counts are kept for the given cache backend.
+pub fn store(cache_backend: &str, cfg: &Config) -> std::result::Result<Box<dyn Backend>, String> {
+    match cache_backend {
+        "redis" => Ok(Box::new(backend::Redis)),
+        _ if cfg.size > 0 => Ok(Box::new(backend::Memory::new(cfg.size))),
+        _ => Err("throttle needs a size without redis".to_owned()),
+    }
+}
+
+// Something failures are counted against.
+#[derive(Debug, Clone)]
+pub enum Subject {
+    User(String),
+    Client(String),
+    Ip(IpAddr),
+}
+
+impl Subject {
+    pub fn parse(kind: &str, name: &str) -> Option<Subject> {
+        match kind {
+            "user" => Some(Subject::User(name.to_owned())),
+            "client" => Some(Subject::Client(name.to_owned())),
+            "ip" => name.parse().ok().map(Subject::Ip),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Subject::User(name) => format!("user_{}", name),
+            Subject::Client(id) => format!("client_{}", id),
+            Subject::Ip(addr) => format!("ip_{}", addr),
+        }
+    }
+
+    fn threshold(&self, cfg: &Config) -> u64 {
+        match self {
+            Subject::User(_) | Subject::Client(_) => cfg.user_failures,
+            Subject::Ip(_) => cfg.ip_failures,
+        }
+    }
+}
+
+// The subjects a login attempt counts against, the user's first.
+pub fn subjects(name: &str, ip: Option<IpAddr>) -> Vec<Subject> {
+    let mut v = vec![Subject::User(name.to_owned())];
+    v.extend(ip.map(Subject::Ip));
+    v
+}
+
+// The subjects a client authenticating counts against, the client's first.
+pub fn client_subjects(id: &str, ip: Option<IpAddr>) -> Vec<Subject> {
+    let mut v = vec![Subject::Client(id.to_owned())];
+    v.extend(ip.map(Subject::Ip));
+    v
+}
+
+fn failures_key(cdb: &CachedDb<'_>, name: &str) -> String {
+    cache::key(cdb, &format!("failures_{}", name))
+}
+
+fn lockout_key(cdb: &CachedDb<'_>, name: &str) -> String {
+    cache::key(cdb, &format!("lockout_{}", name))
+}
+
+// How long to lock out for after `over` failures past the threshold.
+fn lockout_secs(cfg: &Config, over: u64) -> u64 {
+    let mult = 1u64.checked_shl(over.min(63) as u32).unwrap_or(u64::MAX);
+    cfg.lockout.saturating_mul(mult).min(cfg.max_lockout).max(1)
+}
+
+// Seconds until the last lockout on any of the subjects ends, or None if none are locked out.
+pub async fn locked(cdb: &CachedDb<'_>, subjects: &[Subject]) -> Option<u64> {
+    let keys: Vec<String> = subjects.iter().map(|s| lockout_key(cdb, &s.name())).collect();
+    let ttls = cdb.serv.throttle_store.ttl(cdb, keys).await.ok()?;
+    ttls.into_iter().filter(|t| *t > 0).max()
+}
+
+// Count a failed login against each subject, locking out any that are past their threshold.
+pub async fn failed(cdb: &CachedDb<'_>, subjects: &[Subject]) -> Result<()> {
+    let cfg = &cdb.serv.throttle;
+    let store = &cdb.serv.throttle_store;
+    for s in subjects.iter() {
+        let key = failures_key(cdb, &s.name());
+        let cnt = store.incr(cdb, key.clone(), cfg.window as usize).await?;
+        let threshold = s.threshold(cfg);
+        if cnt >= threshold {
+            let secs = lockout_secs(cfg, cnt - threshold);
+            store.set(cdb, lockout_key(cdb, &s.name()), cnt.to_string().into_bytes(), secs as usize).await?;
+            // remember the failures past the lockout so the next one doubles it
+            store.expire(cdb, key, (secs + cfg.window) as usize).await?;
+        }
+    }
+    Ok(())
+}
+
+// A successful login forgets the user's, or client's, failures. The address's are kept.
+pub async fn succeeded(cdb: &CachedDb<'_>, s: &Subject) -> Result<()> {
+    cdb.serv.throttle_store.del(cdb, vec![failures_key(cdb, &s.name())]).await?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Lockout {
+    pub subject: String,
+    pub failures: u64,
+    pub retry_after: u64, // 0 if not locked out
+}
+
+async fn get_one(cdb: &CachedDb<'_>, name: String) -> Result<Lockout> {
+    let store = &cdb.serv.throttle_store;
+    let failures = store.get(cdb, failures_key(cdb, &name)).await?
+        .and_then(|v| String::from_utf8(v).ok())
+        .and_then(|v| v.parse().ok());
+    let ttls = store.ttl(cdb, vec![lockout_key(cdb, &name)]).await?;
+    Ok(Lockout {
+        subject: name,
+        failures: failures.unwrap_or(0),
+        retry_after: ttls.into_iter().next().unwrap_or(0),
+    })
+}
+
+pub async fn get_lockout(cdb: &CachedDb<'_>, s: &Subject) -> Result<Lockout> {
+    get_one(cdb, s.name()).await
+}
+
+// Get every subject that is currently locked out.
+pub async fn list_lockouts(cdb: &CachedDb<'_>) -> Result<Vec<Lockout>> {
+    let prefix = lockout_key(cdb, "");
+    let keys = cdb.serv.throttle_store.keys(cdb, prefix.clone()).await?;
+    let mut x = Vec::new();
+    for name in keys.iter().filter_map(|k| k.strip_prefix(&prefix)) {
+        x.push(get_one(cdb, name.to_owned()).await?);
+    }
+    Ok(x)
+}
+
+// Forget a subject's failures and lift any lockout. Returns the number of keys removed.
+pub async fn clear(cdb: &CachedDb<'_>, s: &Subject) -> Result<usize> {
+    let keys = vec![failures_key(cdb, &s.name()), lockout_key(cdb, &s.name())];
+    cdb.serv.throttle_store.del(cdb, keys).await
+}
@@ -1,6 +1,8 @@
 #!/usr/bin/env python
 
-import requests, time
+import requests, time, hmac, hashlib, struct, base64
+
+# Against a new db served with ROCKET_BOOTSTRAP='{password="adminadmin"}'
 
 serv = 'http://localhost:8000'
 
@@ -9,11 +11,12 @@ def new_session() :
     s.headers.update({'Content-Type': 'application/json'})
     return s
 
-def login(s, user, pw, scopes) :
+def login(s, user, pw, scopes, otp=None) :
     req = {
         'name': user,
         'secret': pw,
         'scopes': scopes,
+        'otp': otp,
     }
     v = s.post(serv + '/auth', json=req).json()
     if v['status'] == 'ok' :
@@ -21,6 +24,20 @@ def login(s, user, pw, scopes) :
         s.headers.update({'Authorization': 'bearer ' + tok})
     return v
 
+def totp(secret) :
+    key = base64.b32decode(secret + '=' * (-len(secret) % 8))
+    h = hmac.new(key, struct.pack('>Q', int(time.time()) // 30), hashlib.sha1).digest()
+    off = ord(h[-1]) & 0xf
+    return '%06d' % ((struct.unpack('>I', h[off:off+4])[0] & 0x7fffffff) % 1000000)
+
+# authadmin needs a second factor, which a new admin enrolls with a token for no scopes
+def enroll(s) :
+    v = s.post(serv + '/auth/totp/enroll').json()
+    if v['status'] != 'ok' :
+        return v, None
+    secret = v['result']['secret']
+    return s.post(serv + '/auth/totp/confirm', json={'otp': totp(secret)}).json(), secret
+
 def check(s) :
     return s.get(serv + '/auth').json()
 
@@ -33,6 +50,14 @@ def create_user(s, user, pw, life, scopes) :
     }
     return s.post(serv + '/admin/user', json=req).json()
 
+def create_client(s, client_id, scopes, grant_types) :
+    req = {
+        "client_id": client_id,
+        "scopes": scopes,
+        "grant_types": grant_types,
+    }
+    return s.post(serv + '/admin/client', json=req).json()
+
 def create_scope(s, scope) :
     return s.post(serv + '/admin/scope', json=scope).json()
 
@@ -42,7 +67,11 @@ def clean(s) :
 s = new_session()
 
 if 1 :
-    print login(s, 'admin', 'adminadmin', ['authadmin'])
+    print login(s, 'admin', 'adminadmin', [])
+    res, secret = enroll(s)
+    print res
+    time.sleep(30) # each code is only good once
+    print login(s, 'admin', 'adminadmin', ['authadmin'], totp(secret))
     if 1 :
         print check(s)
 
@@ -52,6 +81,10 @@ if 1 :
     if 0 :
         print create_user(s, "test", "testpw", 60*60*365*5, ["user"])
 
+    if 0 :
+        # services are clients now, rather than users that never expire
+        print create_client(s, "testsvc", ["user"], ["client_credentials"])
+
     if 1 :
         print clean(s)
 
@@ -1,4 +1,7 @@
 #!/bin/sh
+# Against a new db served with ROCKET_BOOTSTRAP='{password="adminadmin"}'
+# authadmin needs a second factor, so enroll one first as test.py does, and
+# add its code as "otp" to the logins asking for authadmin.
 
 curl http://localhost:8000/test
 echo
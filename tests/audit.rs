@@ -0,0 +1,138 @@
+// What the audit log records on the security paths, and finding it again.
+mod common;
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::serde::json::{json, Value};
+use diesel::RunQueryDsl;
+
+use authsrv::rocktypes::CachedDb;
+use authsrv::model::audit;
+
+// The events matching query, newest first.
+async fn events(s: &common::Server, admin: &str, query: &str) -> Vec<Value> {
+    let (status, body) = s.call("GET", &format!("/admin/audit?{}", query), Some(admin), None).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    body["result"].as_array().expect("events").clone()
+}
+
+fn details(evs: &[Value]) -> Vec<&str> {
+    evs.iter().map(|ev| ev["detail"].as_str().expect("detail")).collect()
+}
+
+#[rocket::async_test]
+async fn records_revocations() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    let tok = s.token("alice", "alicepassword", &[]).await;
+    s.call("DELETE", "/auth", Some(&tok), None).await;
+    let tok = s.token("alice", "alicepassword", &[]).await;
+    s.call("POST", "/admin/token/revoke", Some(&admin), Some(json!(tok))).await;
+
+    let evs = events(&s, &admin, "user=alice&event=token_revoked").await;
+    assert_eq!(details(&evs), vec!["admin", "logout"]);
+    assert_eq!(evs[0]["actor"], common::ADMIN);
+    assert_eq!(evs[1]["actor"], "alice");
+    assert!(evs.iter().all(|ev| ev["subject"] == "alice"));
+}
+
+#[rocket::async_test]
+async fn records_refresh_reuse() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    let (_, body) = s.login("alice", "alicepassword", &[], None).await;
+    let refresh = body["result"]["refresh"].as_str().expect("refresh").to_owned();
+
+    let (status, _) = s.call("POST", "/auth/refresh", None, Some(json!({ "refresh": refresh }))).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = s.call("POST", "/auth/refresh", None, Some(json!({ "refresh": refresh }))).await;
+    assert_eq!(status, Status::Unauthorized);
+
+    let evs = events(&s, &admin, "event=refresh_reused").await;
+    assert_eq!(evs.len(), 1);
+    assert_eq!(evs[0]["subject"], "alice");
+}
+
+#[rocket::async_test]
+async fn records_a_missing_second_factor() {
+    let s = common::server().await;
+    let (status, _) = s.login(common::ADMIN, common::ADMIN_PASSWORD, &["authadmin"], None).await;
+    assert_eq!(status, Status::Unauthorized);
+
+    let admin = s.admin_token().await;
+    let evs = events(&s, &admin, "user=admin&event=login_failed").await;
+    assert_eq!(details(&evs), vec!["mfarequired"]);
+}
+
+#[rocket::async_test]
+async fn records_lockouts() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    for _ in 0..5 {
+        s.login("alice", "wrong", &[], None).await;
+    }
+    let (status, _) = s.login("alice", "alicepassword", &[], None).await;
+    assert_eq!(status, Status::TooManyRequests);
+    s.call("DELETE", "/admin/lockout/user/alice", Some(&admin), None).await;
+
+    let evs = events(&s, &admin, "user=alice&event=login_failed").await;
+    assert_eq!(details(&evs), vec!["locked", "badauth", "badauth", "badauth", "badauth", "badauth"]);
+    let evs = events(&s, &admin, "user=alice&event=lockout_cleared").await;
+    assert_eq!(details(&evs), vec!["user"]);
+}
+
+#[rocket::async_test]
+async fn filters_the_log() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    s.create_user(&admin, "bob", "bobpassword", &[]).await;
+    s.login("bob", "wrong", &[], None).await;
+
+    // a user matches as the actor or the subject
+    let evs = events(&s, &admin, "user=bob").await;
+    let names: Vec<&str> = evs.iter().map(|ev| ev["event"].as_str().expect("event")).collect();
+    assert_eq!(names, vec!["login_failed", "user_created"]);
+    let evs = events(&s, &admin, "user=admin&event=user_created").await;
+    assert_eq!(evs.len(), 2);
+    assert_eq!(events(&s, &admin, "user=admin&event=user_created&limit=1&offset=1").await[0]["subject"], "alice");
+
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    assert!(events(&s, &admin, &format!("user=bob&since={}", now + 60)).await.is_empty());
+    assert!(events(&s, &admin, &format!("user=bob&until={}", now - 60)).await.is_empty());
+    assert_eq!(events(&s, &admin, &format!("user=bob&since={}&until={}", now - 60, now + 60)).await.len(), 2);
+}
+
+#[rocket::async_test]
+async fn records_the_real_address() {
+    let s = common::server_with(|f| f.merge(("trusted_proxies", ["10.0.0.1"]))).await;
+    let admin = s.admin_token().await;
+    for (remote, header) in [("198.51.100.7:4000", "X-Real-IP"), ("10.0.0.1:4000", "X-Forwarded-For")].iter() {
+        let body = json!({ "name": "nobody", "secret": "wrong", "scopes": [] });
+        s.client.post("/auth").header(ContentType::JSON)
+            .remote(remote.parse().expect("remote"))
+            .header(Header::new(*header, "192.0.2.1"))
+            .body(body.to_string()).dispatch().await;
+    }
+
+    // a client's own header is ignored, a trusted proxy's is believed
+    let evs = events(&s, &admin, "user=nobody&event=login_failed").await;
+    let ips: Vec<&str> = evs.iter().map(|ev| ev["client_ip"].as_str().expect("client_ip")).collect();
+    assert_eq!(ips, vec!["192.0.2.1", "198.51.100.7"]);
+}
+
+#[rocket::async_test]
+async fn counts_events_it_cant_write() {
+    let db = common::db_path();
+    let rocket = authsrv::custom(common::figment(&db)).ignite().await.expect("ignite");
+    let cdb = CachedDb::offline(&rocket);
+    cdb.sqlite().await.expect("db").run(|c| diesel::sql_query("DROP TABLE audit_events").execute(c)).await.expect("drop");
+
+    audit::record(&cdb, "login", None, Some("alice"), String::new()).await;
+    let metrics = cdb.serv.metrics.render(cdb.serv);
+    assert!(metrics.contains("authsrv_audit_failures_total 1"), "{}", metrics);
+    drop(cdb);
+    let _ = std::fs::remove_file(&db);
+}
@@ -0,0 +1,50 @@
+// Making the first admin, and refusing the old seeded one.
+mod common;
+
+use rocket::error::ErrorKind;
+
+use authsrv::rocktypes::CachedDb;
+
+#[rocket::async_test]
+async fn refuses_the_seeded_password_even_rehashed() {
+    let db = common::db_path();
+    let figment = common::figment(&db);
+    {
+        // as if the seeded admin had logged in since and had its hash upgraded
+        let rocket = authsrv::custom(figment.clone()).ignite().await.expect("ignite");
+        let cdb = CachedDb::offline(&rocket);
+        authsrv::bootstrap::admin(&cdb, "admin", "adminadmin").await.expect("admin");
+    }
+    let refused = authsrv::custom(figment.clone()).attach(authsrv::bootstrap::fairing()).ignite().await;
+    let err = refused.err().expect("refused");
+    assert!(matches!(err.kind(), ErrorKind::FailedFairings(_)));
+
+    let warned = authsrv::custom(figment.merge(("bootstrap.default_admin", "warn")))
+        .attach(authsrv::bootstrap::fairing()).ignite().await;
+    assert!(warned.is_ok());
+    let _ = std::fs::remove_file(&db);
+}
+
+#[rocket::async_test]
+async fn makes_the_first_admin() {
+    let db = common::db_path();
+    let figment = common::figment(&db).merge(("bootstrap.password", "firstpassword"));
+    let rocket = authsrv::custom(figment).attach(authsrv::bootstrap::fairing()).ignite().await.expect("ignite");
+    let cdb = CachedDb::offline(&rocket);
+    let u = authsrv::model::user::get_user(&cdb, "admin".to_owned()).await.expect("admin");
+    assert!(u.scopes.iter().any(|s| s == "authadmin"));
+    assert!(u.mfa_enroll);
+    drop(cdb);
+    let _ = std::fs::remove_file(&db);
+}
+
+#[rocket::async_test]
+async fn migrations_dont_seed_an_admin() {
+    let db = common::db_path();
+    let rocket = authsrv::custom(common::figment(&db)).ignite().await.expect("ignite");
+    let cdb = CachedDb::offline(&rocket);
+    let res = authsrv::model::user::get_user(&cdb, "admin".to_owned()).await;
+    assert!(matches!(res, Err(authsrv::Error::NotFound)));
+    drop(cdb);
+    let _ = std::fs::remove_file(&db);
+}
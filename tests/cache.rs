@@ -0,0 +1,86 @@
+// The cache, and what happens when redis can't be reached.
+mod common;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::{json, Value};
+
+use authsrv::Error;
+use authsrv::rocktypes::CachedDb;
+use authsrv::manage::{self, NewUser, UserChanges};
+
+#[rocket::async_test]
+async fn revoking_fails_without_redis() {
+    let db = common::db_path();
+    let rocket = authsrv::custom(common::without_redis(common::figment(&db))).ignite().await.expect("ignite");
+    {
+        let cdb = CachedDb::offline(&rocket);
+        authsrv::bootstrap::admin(&cdb, common::ADMIN, common::ADMIN_PASSWORD).await.expect("admin");
+
+        // disabling a user revokes its tokens, which have to be evicted everywhere
+        let alice = NewUser { name: "alice", secret: "alicepassword", life: 3600, scopes: vec![], require_mfa: false, mfa_enroll: false };
+        manage::create_user(&cdb, None, alice).await.expect("alice");
+        let disable = UserChanges { enabled: Some(false), ..UserChanges::default() };
+        let res = manage::update_user(&cdb, None, "alice", disable).await;
+        assert!(matches!(res, Err(Error::Unavailable(_))), "{:?}", res);
+    }
+    let client = Client::tracked(rocket).await.expect("client");
+    let s = common::Server::new(client, db);
+
+    // logging in carries on without the cache, and leaves redis alone for a while
+    let tok = s.token(common::ADMIN, common::ADMIN_PASSWORD, &[]).await;
+    let (status, _) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Ok);
+
+    // but a revocation other nodes may not hear of isn't reported as done
+    let (status, body) = s.call("DELETE", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::ServiceUnavailable, "{}", body);
+}
+
+async fn stats(s: &common::Server, admin: &str) -> Value {
+    let (status, body) = s.call("GET", "/admin/cache", Some(admin), None).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    body["result"].clone()
+}
+
+#[rocket::async_test]
+async fn flushing_a_user_sends_the_next_lookup_to_the_db() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    s.token("alice", "alicepassword", &[]).await;
+
+    // cached now, so logging in again doesn't miss
+    let before = stats(&s, &admin).await;
+    assert_eq!(before["backend"], "memory");
+    s.token("alice", "alicepassword", &[]).await;
+    let cached = stats(&s, &admin).await;
+    assert_eq!(cached["misses"], before["misses"], "{}", cached);
+
+    let (status, body) = s.call("POST", "/admin/cache/flush", Some(&admin), Some(json!({ "user": "alice" }))).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    let flushed = stats(&s, &admin).await;
+    assert_eq!(flushed["keys"]["user"].as_u64().unwrap() + 1, cached["keys"]["user"].as_u64().unwrap(), "{}", flushed);
+
+    s.token("alice", "alicepassword", &[]).await;
+    let after = stats(&s, &admin).await;
+    assert_eq!(after["misses"].as_u64().unwrap(), flushed["misses"].as_u64().unwrap() + 1, "{}", after);
+    assert_eq!(after["keys"]["user"], cached["keys"]["user"], "{}", after);
+
+    let both = json!({ "user": "alice", "scopes": true });
+    let (status, _) = s.call("POST", "/admin/cache/flush", Some(&admin), Some(both)).await;
+    assert_eq!(status, Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn works_without_any_cache() {
+    let s = common::server_with(|f| f.merge(("cache_backend", "none"))).await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    let tok = s.token("alice", "alicepassword", &[]).await;
+    let (status, _) = s.call("DELETE", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(stats(&s, &admin).await["backend"], "none");
+}
@@ -0,0 +1,128 @@
+// Registered clients, and keeping them apart from users.
+mod common;
+
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+#[rocket::async_test]
+async fn clients_and_users_cant_share_names() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    s.create_user(&admin, "alice", "alicepassword", &["reports"]).await;
+    s.create_client(&admin, "app", &["reports"], &["client_credentials"]).await;
+
+    let body = json!({ "client_id": "alice", "scopes": ["reports"], "grant_types": ["client_credentials"] });
+    let (status, _) = s.call("POST", "/admin/client", Some(&admin), Some(body)).await;
+    assert_eq!(status, Status::Conflict);
+
+    let body = json!({ "name": "app", "secret": "apppassword", "life": 3600, "scopes": ["reports"] });
+    let (status, _) = s.call("POST", "/admin/user", Some(&admin), Some(body)).await;
+    assert_eq!(status, Status::Conflict);
+}
+
+#[rocket::async_test]
+async fn client_tokens_cant_act_as_users() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    let secret = s.create_client(&admin, "app", &["reports"], &["client_credentials"]).await;
+
+    let (status, body) = s.form("/oauth/token", Some(("app", &secret)), "grant_type=client_credentials&scope=reports").await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    let tok = body["access_token"].as_str().expect("token").to_owned();
+
+    let (status, _) = s.call("POST", "/auth/totp/enroll", Some(&tok), None).await;
+    assert_eq!(status, Status::Forbidden);
+    let (status, _) = s.call("GET", "/admin/user/app", Some(&tok), None).await;
+    assert_eq!(status, Status::Forbidden);
+}
+
+#[rocket::async_test]
+async fn basic_credentials_are_form_decoded() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    let secret = s.create_client(&admin, "app", &["reports"], &["client_credentials"]).await;
+
+    // as a client that encodes every character would send them
+    let encode = |v: &str| v.bytes().map(|b| format!("%{:02X}", b)).collect::<String>();
+    let (status, body) = s.form("/oauth/token", Some((&encode("app"), &encode(&secret))), "grant_type=client_credentials").await;
+    assert_eq!(status, Status::Ok, "{}", body);
+}
+
+// A user logged in through app, and the refresh token it was given.
+async fn client_login(s: &common::Server, admin: &str, secret: &str) -> String {
+    s.create_user(admin, "alice", "alicepassword", &["reports"]).await;
+    let (status, body) = s.form("/oauth/token", Some(("app", secret)),
+        "grant_type=password&username=alice&password=alicepassword&scope=reports").await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    body["refresh_token"].as_str().expect("refresh_token").to_owned()
+}
+
+#[rocket::async_test]
+async fn refreshing_needs_the_clients_secret() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    let secret = s.create_client(&admin, "app", &["reports"], &["password", "refresh_token"]).await;
+    let refresh = client_login(&s, &admin, &secret).await;
+    let grant = format!("grant_type=refresh_token&refresh_token={}", refresh);
+
+    let (status, body) = s.form("/oauth/token", None, &grant).await;
+    assert_eq!(status, Status::BadRequest, "{}", body);
+    assert_eq!(body["error"], "invalid_grant");
+    let (status, body) = s.form("/oauth/token", Some(("app", "wrong")), &grant).await;
+    assert_eq!(status, Status::Unauthorized, "{}", body);
+    assert_eq!(body["error"], "invalid_client");
+    let (status, _) = s.call("POST", "/auth/refresh", None, Some(json!({ "refresh": refresh }))).await;
+    assert_eq!(status, Status::Unauthorized);
+
+    // none of that used it up
+    let (status, body) = s.form("/oauth/token", None, &format!("{}&client_id=app&client_secret={}", grant, secret)).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    assert_eq!(body["scope"], "reports");
+}
+
+#[rocket::async_test]
+async fn refreshing_needs_the_same_client() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    let secret = s.create_client(&admin, "app", &["reports"], &["password", "refresh_token"]).await;
+    let other = s.create_client(&admin, "other", &["reports"], &["password", "refresh_token"]).await;
+    let refresh = client_login(&s, &admin, &secret).await;
+    let grant = format!("grant_type=refresh_token&refresh_token={}", refresh);
+
+    let (status, body) = s.form("/oauth/token", Some(("other", &other)), &grant).await;
+    assert_eq!(status, Status::BadRequest, "{}", body);
+    assert_eq!(body["error"], "invalid_grant");
+    let (status, body) = s.form("/oauth/token", Some(("app", &secret)), &grant).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+
+    // nor can a client swap a refresh token that a user got logging in directly
+    let (_, body) = s.login("alice", "alicepassword", &[], None).await;
+    let own = body["result"]["refresh"].as_str().expect("refresh").to_owned();
+    let (status, _) = s.form("/oauth/token", Some(("app", &secret)), &format!("grant_type=refresh_token&refresh_token={}", own)).await;
+    assert_eq!(status, Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn refresh_tokens_only_for_clients_allowed_them() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    s.create_user(&admin, "alice", "alicepassword", &["reports"]).await;
+    let secret = s.create_client(&admin, "app", &["reports"], &["password"]).await;
+    let login = "grant_type=password&username=alice&password=alicepassword&scope=reports";
+
+    let (status, body) = s.form("/oauth/token", Some(("app", &secret)), login).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    assert!(body["access_token"].is_string());
+    assert!(body["refresh_token"].is_null(), "{}", body);
+
+    // a user logging in without a client still gets one
+    let (status, body) = s.form("/oauth/token", None, login).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    assert!(body["refresh_token"].is_string(), "{}", body);
+}
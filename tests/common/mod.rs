@@ -0,0 +1,191 @@
+// A server on a fresh sqlite db, with no redis, for the tests to talk to.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use ring::hmac;
+use rocket::figment::Figment;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::{self, json, Value};
+
+use authsrv::rocktypes::CachedDb;
+
+pub const ADMIN: &str = "admin";
+pub const ADMIN_PASSWORD: &str = "adminpassword";
+
+static DBS: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Server {
+    pub client: Client,
+    db: PathBuf,
+    recovery: Mutex<Vec<String>>, // the admin's, one is used for each admin login
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db);
+    }
+}
+
+// The config every test starts from. Hashing is made cheap, the tests don't need it slow.
+pub fn figment(db: &Path) -> Figment {
+    rocket::Config::figment()
+        .merge(("log_level", "off"))
+        .merge(("storage", "sqlite"))
+        .merge(("cache_backend", "memory"))
+        .merge(("cache_size", 1000))
+        .merge(("databases.sqlite.url", db.to_str().expect("db path")))
+        .merge(("password.mem_cost", 64))
+        .merge(("password.time_cost", 1))
+}
+
+// Point the cache at a redis nobody answers at.
+pub fn without_redis(f: Figment) -> Figment {
+    f.merge(("cache_backend", "redis"))
+        .merge(("local_cache.size", 0))
+        .merge(("databases.redis.url", "redis://127.0.0.1:1/"))
+        .merge(("databases.redis.timeout", 1))
+}
+
+pub fn db_path() -> PathBuf {
+    let n = DBS.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("authsrv-test-{}-{}.db", std::process::id(), n))
+}
+
+pub async fn server() -> Server {
+    server_with(|f| f).await
+}
+
+// A server with its config changed from the usual, and nobody in its db.
+pub async fn bare_server(change: impl FnOnce(Figment) -> Figment) -> Server {
+    let db = db_path();
+    let _ = std::fs::remove_file(&db);
+    let rocket = authsrv::custom(change(figment(&db))).ignite().await.expect("ignite");
+    Server::new(Client::tracked(rocket).await.expect("client"), db)
+}
+
+// A server with its config changed from the usual, and an admin already made.
+pub async fn server_with(change: impl FnOnce(Figment) -> Figment) -> Server {
+    let db = db_path();
+    let _ = std::fs::remove_file(&db);
+    let rocket = authsrv::custom(change(figment(&db))).ignite().await.expect("ignite");
+    {
+        let cdb = CachedDb::offline(&rocket);
+        authsrv::bootstrap::admin(&cdb, ADMIN, ADMIN_PASSWORD).await.expect("admin");
+    }
+    let client = Client::tracked(rocket).await.expect("client");
+    let s = Server::new(client, db);
+
+    // authadmin needs a second factor
+    let tok = s.token(ADMIN, ADMIN_PASSWORD, &[]).await;
+    let (_, codes) = s.enroll(&tok).await;
+    *s.recovery.lock().unwrap() = codes;
+    s
+}
+
+// The TOTP code for the current time.
+pub fn totp(secret: &str) -> String {
+    let key = base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret).expect("secret");
+    let step = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() / 30;
+    let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &key), &step.to_be_bytes());
+    let h = tag.as_ref();
+    let off = (h[h.len() - 1] & 0xf) as usize;
+    let bin = u32::from_be_bytes([h[off] & 0x7f, h[off + 1], h[off + 2], h[off + 3]]);
+    format!("{:06}", bin % 1_000_000)
+}
+
+impl Server {
+    // For a test that set up its own rocket. The db is removed when the server is dropped.
+    pub fn new(client: Client, db: PathBuf) -> Server {
+        Server { client: client, db: db, recovery: Mutex::new(Vec::new()) }
+    }
+
+    pub async fn call(&self, method: &str, uri: &str, token: Option<&str>, body: Option<Value>) -> (Status, Value) {
+        let mut req = match method {
+            "GET" => self.client.get(uri.to_owned()),
+            "POST" => self.client.post(uri.to_owned()),
+            "PATCH" => self.client.patch(uri.to_owned()),
+            "DELETE" => self.client.delete(uri.to_owned()),
+            m => panic!("unknown method {}", m),
+        };
+        req = req.header(ContentType::JSON);
+        if let Some(tok) = token {
+            req = req.header(Header::new("Authorization", format!("Bearer {}", tok)));
+        }
+        if let Some(body) = body {
+            req = req.body(body.to_string());
+        }
+        let resp = req.dispatch().await;
+        let status = resp.status();
+        // into_json can hang in this rocket, the body is small enough to take whole
+        let body = resp.into_string().await.and_then(|s| json::from_str(&s).ok()).unwrap_or(Value::Null);
+        (status, body)
+    }
+
+    // Post a form, as OAuth clients do.
+    pub async fn form(&self, uri: &str, basic: Option<(&str, &str)>, body: &str) -> (Status, Value) {
+        let mut req = self.client.post(uri.to_owned()).header(ContentType::Form).body(body);
+        if let Some((id, secret)) = basic {
+            let creds = base64::encode(format!("{}:{}", id, secret));
+            req = req.header(Header::new("Authorization", format!("Basic {}", creds)));
+        }
+        let resp = req.dispatch().await;
+        let status = resp.status();
+        let body = resp.into_string().await.and_then(|s| json::from_str(&s).ok()).unwrap_or(Value::Null);
+        (status, body)
+    }
+
+    // Log in and return the whole response.
+    pub async fn login(&self, name: &str, secret: &str, scopes: &[&str], otp: Option<&str>) -> (Status, Value) {
+        let body = json!({ "name": name, "secret": secret, "scopes": scopes, "otp": otp });
+        self.call("POST", "/auth", None, Some(body)).await
+    }
+
+    // Log in and return just the token, failing the test if the login does.
+    pub async fn token(&self, name: &str, secret: &str, scopes: &[&str]) -> String {
+        let (status, body) = self.login(name, secret, scopes, None).await;
+        assert_eq!(status, Status::Ok, "login {}: {}", name, body);
+        body["result"]["token"].as_str().expect("token").to_owned()
+    }
+
+    pub async fn admin_token(&self) -> String {
+        let code = self.recovery.lock().unwrap().pop().expect("recovery code");
+        let (status, body) = self.login(ADMIN, ADMIN_PASSWORD, &["authadmin"], Some(&code)).await;
+        assert_eq!(status, Status::Ok, "admin login: {}", body);
+        body["result"]["token"].as_str().expect("token").to_owned()
+    }
+
+    // Enroll and confirm a second factor, returning its secret and recovery codes.
+    pub async fn enroll(&self, tok: &str) -> (String, Vec<String>) {
+        let (status, body) = self.call("POST", "/auth/totp/enroll", Some(tok), None).await;
+        assert_eq!(status, Status::Ok, "enroll: {}", body);
+        let secret = body["result"]["secret"].as_str().expect("secret").to_owned();
+        let (status, body) = self.call("POST", "/auth/totp/confirm", Some(tok), Some(json!({ "otp": totp(&secret) }))).await;
+        assert_eq!(status, Status::Ok, "confirm: {}", body);
+        let codes = body["result"]["recovery_codes"].as_array().expect("codes")
+            .iter().map(|c| c.as_str().expect("code").to_owned()).collect();
+        (secret, codes)
+    }
+
+    pub async fn create_user(&self, admin: &str, name: &str, secret: &str, scopes: &[&str]) {
+        let body = json!({ "name": name, "secret": secret, "life": 3600, "scopes": scopes });
+        let (status, body) = self.call("POST", "/admin/user", Some(admin), Some(body)).await;
+        assert_eq!(status, Status::Ok, "create user {}: {}", name, body);
+    }
+
+    // Register a client and return its secret.
+    pub async fn create_client(&self, admin: &str, id: &str, scopes: &[&str], grants: &[&str]) -> String {
+        let body = json!({ "client_id": id, "scopes": scopes, "grant_types": grants });
+        let (status, body) = self.call("POST", "/admin/client", Some(admin), Some(body)).await;
+        assert_eq!(status, Status::Ok, "create client {}: {}", id, body);
+        body["result"]["client_secret"].as_str().expect("secret").to_owned()
+    }
+
+    pub async fn create_scope(&self, admin: &str, name: &str) {
+        let (status, body) = self.call("POST", "/admin/scope", Some(admin), Some(json!(name))).await;
+        assert_eq!(status, Status::Ok, "create scope {}: {}", name, body);
+    }
+}
@@ -0,0 +1,35 @@
+// Liveness and readiness.
+mod common;
+
+use rocket::http::Status;
+
+#[rocket::async_test]
+async fn ready_on_sqlite_with_the_memory_cache() {
+    let s = common::server().await;
+    let (status, body) = s.call("GET", "/healthz", None, None).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["result"], "alive");
+
+    let (status, body) = s.call("GET", "/readyz", None, None).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    assert_eq!(body["status"], "ok");
+    let checks = body["checks"].as_object().expect("checks");
+    let names: Vec<&str> = checks.keys().map(|k| k.as_str()).collect();
+    assert_eq!(names, vec!["cache", "database", "migrations", "pool"]);
+    assert!(checks.values().all(|c| c["ok"] == true), "{}", body);
+    assert_eq!(checks["database"]["detail"], "sqlite");
+    assert_eq!(checks["cache"]["detail"], "memory");
+    assert_eq!(checks["cache"]["critical"], false);
+    assert_eq!(checks["database"]["critical"], true);
+}
+
+#[rocket::async_test]
+async fn ready_without_redis() {
+    let s = common::bare_server(common::without_redis).await;
+
+    let (status, body) = s.call("GET", "/readyz", None, None).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["checks"]["cache"]["ok"], false, "{}", body);
+    assert_eq!(body["checks"]["database"]["ok"], true, "{}", body);
+}
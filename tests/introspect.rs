@@ -0,0 +1,88 @@
+// Token introspection, as a resource server would use it.
+mod common;
+
+use std::time::Duration;
+use rocket::http::{ContentType, Header, Status};
+use rocket::serde::json::{self, Value};
+
+// Ask about tok, with bearer as the resource server's own token.
+async fn introspect(s: &common::Server, bearer: Option<&str>, tok: &str) -> (Status, Value) {
+    let mut req = s.client.post("/oauth/introspect").header(ContentType::Form).body(format!("token={}", tok));
+    if let Some(bearer) = bearer {
+        req = req.header(Header::new("Authorization", format!("Bearer {}", bearer)));
+    }
+    let resp = req.dispatch().await;
+    let status = resp.status();
+    let body = resp.into_string().await.and_then(|s| json::from_str(&s).ok()).unwrap_or(Value::Null);
+    (status, body)
+}
+
+// A resource server allowed to introspect, alice, who isn't, and a client, app. Returns app's secret.
+async fn setup(s: &common::Server) -> String {
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    s.create_user(&admin, "rs", "rspassword", &["authintrospect"]).await;
+    s.create_user(&admin, "alice", "alicepassword", &["reports"]).await;
+    s.create_client(&admin, "app", &["reports"], &["password"]).await
+}
+
+#[rocket::async_test]
+async fn describes_an_active_token() {
+    let s = common::server().await;
+    let secret = setup(&s).await;
+    let rs = s.token("rs", "rspassword", &["authintrospect"]).await;
+
+    let (status, body) = s.form("/oauth/token", Some(("app", &secret)),
+        "grant_type=password&username=alice&password=alicepassword&scope=reports").await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    let tok = body["access_token"].as_str().expect("token");
+
+    let (status, body) = introspect(&s, Some(&rs), tok).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    assert_eq!(body["active"], true);
+    assert_eq!(body["scope"], "reports");
+    assert_eq!(body["username"], "alice");
+    assert_eq!(body["client_id"], "app");
+    assert_eq!(body["token_type"], "Bearer");
+    let iat = body["iat"].as_u64().expect("iat");
+    assert_eq!(body["exp"].as_u64().expect("exp") - iat, 3600);
+
+    // a user's own login has no client
+    let own = s.token("alice", "alicepassword", &[]).await;
+    let (_, body) = introspect(&s, Some(&rs), &own).await;
+    assert_eq!(body["active"], true);
+    assert_eq!(body["scope"], "");
+    assert!(body.get("client_id").is_none(), "{}", body);
+}
+
+#[rocket::async_test]
+async fn unknown_and_expired_tokens_are_inactive() {
+    let s = common::server_with(|f| f.merge(("token_lifetime", 3))).await;
+    setup(&s).await;
+    let rs = s.token("rs", "rspassword", &["authintrospect"]).await;
+
+    let (status, body) = introspect(&s, Some(&rs), "0123456789abcdef").await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    assert_eq!(body, rocket::serde::json::json!({ "active": false }));
+
+    let tok = s.token("alice", "alicepassword", &[]).await;
+    rocket::tokio::time::sleep(Duration::from_millis(3500)).await;
+    let rs = s.token("rs", "rspassword", &["authintrospect"]).await;
+    let (status, body) = introspect(&s, Some(&rs), &tok).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    assert_eq!(body, rocket::serde::json::json!({ "active": false }));
+}
+
+#[rocket::async_test]
+async fn needs_the_introspect_scope() {
+    let s = common::server().await;
+    setup(&s).await;
+    let alice = s.token("alice", "alicepassword", &["reports"]).await;
+
+    let (status, body) = introspect(&s, Some(&alice), &alice).await;
+    assert_eq!(status, Status::Forbidden, "{}", body);
+    assert_eq!(body["error"], "insufficient_scope");
+    let (status, body) = introspect(&s, None, &alice).await;
+    assert_eq!(status, Status::Unauthorized, "{}", body);
+    assert_eq!(body["error"], "invalid_token");
+}
@@ -0,0 +1,147 @@
+// Issuing JWTs instead of opaque tokens.
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use ring::rand::SystemRandom;
+use ring::signature::{self, Ed25519KeyPair};
+use rocket::figment::Figment;
+use rocket::http::Status;
+use rocket::serde::json::{self, json, Value};
+
+use authsrv::rocktypes::CachedDb;
+use authsrv::model::{hash_secret, token};
+
+// A fresh signing key, written where jwt_keys can find it.
+fn key_file(db: &Path) -> PathBuf {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).expect("key");
+    let path = db.with_extension("key");
+    std::fs::write(&path, pkcs8.as_ref()).expect("key file");
+    path
+}
+
+fn with_jwt(f: Figment, key: &Path) -> Figment {
+    f.merge(("token_format", "jwt"))
+        .merge(("jwt_keys", json!([{ "kid": "test", "alg": "EdDSA", "key_file": key.to_str().expect("key path") }])))
+}
+
+#[rocket::async_test]
+async fn only_jwts_are_accepted() {
+    let key = key_file(&common::db_path());
+    let s = common::server_with(|f| with_jwt(f, &key)).await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    let tok = s.token("alice", "alicepassword", &[]).await;
+    let (status, _) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Ok);
+
+    // the jti is the stored token's secret, it mustn't work as an opaque token
+    let claims = tok.split('.').nth(1).expect("claims");
+    let claims: Value = json::from_slice(&base64::decode_config(claims, base64::URL_SAFE_NO_PAD).expect("base64")).expect("json");
+    let jti = claims["jti"].as_str().expect("jti");
+    let (status, _) = s.call("GET", "/auth", Some(jti), None).await;
+    assert_eq!(status, Status::Unauthorized);
+    let _ = std::fs::remove_file(&key);
+}
+
+#[rocket::async_test]
+async fn opaque_tokens_stop_working() {
+    let db = common::db_path();
+    let key = key_file(&db);
+    let figment = common::figment(&db);
+    let now = SystemTime::now();
+    let tok = token::Token {
+        token: hash_secret("opaque"),
+        username: "alice".to_owned(),
+        expiration: now + Duration::from_secs(3600),
+        scopes: Vec::new(),
+        issued: now,
+        client_id: None,
+    };
+    {
+        let rocket = authsrv::custom(figment.clone()).ignite().await.expect("ignite");
+        let cdb = CachedDb::offline(&rocket);
+        token::put_token(&cdb, &tok).await.expect("put");
+        assert!(token::lookup_token(&cdb, "opaque".to_owned()).await.is_ok());
+    }
+
+    let rocket = authsrv::custom(with_jwt(figment, &key)).ignite().await.expect("ignite");
+    let cdb = CachedDb::offline(&rocket);
+    assert!(matches!(token::lookup_token(&cdb, "opaque".to_owned()).await, Err(authsrv::Error::NotFound)));
+    drop(cdb);
+    let _ = std::fs::remove_file(&db);
+    let _ = std::fs::remove_file(&key);
+}
+
+#[rocket::async_test]
+async fn deleting_a_scope_revokes_its_jwts() {
+    let key = key_file(&common::db_path());
+    let s = common::server_with(|f| with_jwt(f, &key)).await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    s.create_user(&admin, "alice", "alicepassword", &["reports"]).await;
+    let tok = s.token("alice", "alicepassword", &["reports"]).await;
+    let other = s.token("alice", "alicepassword", &[]).await;
+    let (status, _) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Ok);
+
+    let (status, _) = s.call("DELETE", "/admin/scope/reports", Some(&admin), None).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Unauthorized);
+    let (status, _) = s.call("GET", "/auth", Some(&other), None).await;
+    assert_eq!(status, Status::Ok);
+    let _ = std::fs::remove_file(&key);
+}
+
+#[rocket::async_test]
+async fn revoked_jwts_stop_working() {
+    let key = key_file(&common::db_path());
+    let s = common::server_with(|f| with_jwt(f, &key)).await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+
+    let tok = s.token("alice", "alicepassword", &[]).await;
+    let (status, _) = s.call("DELETE", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Unauthorized);
+
+    let tok = s.token("alice", "alicepassword", &[]).await;
+    let (status, body) = s.call("POST", "/admin/token/revoke", Some(&admin), Some(json!(tok))).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    let (status, _) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Unauthorized);
+    let _ = std::fs::remove_file(&key);
+}
+
+fn decode(part: &str) -> Vec<u8> {
+    base64::decode_config(part, base64::URL_SAFE_NO_PAD).expect("base64")
+}
+
+#[rocket::async_test]
+async fn jwks_verifies_what_is_issued() {
+    let key = key_file(&common::db_path());
+    let s = common::server_with(|f| with_jwt(f, &key)).await;
+    let tok = s.admin_token().await;
+
+    let (status, jwks) = s.call("GET", "/.well-known/jwks.json", None, None).await;
+    assert_eq!(status, Status::Ok);
+    let jwk = &jwks["keys"][0];
+    assert_eq!((&jwk["kid"], &jwk["kty"], &jwk["crv"], &jwk["use"]), (&json!("test"), &json!("OKP"), &json!("Ed25519"), &json!("sig")));
+
+    let parts: Vec<&str> = tok.split('.').collect();
+    let header: Value = json::from_slice(&decode(parts[0])).expect("header");
+    assert_eq!((&header["alg"], &header["kid"]), (&json!("EdDSA"), &jwk["kid"]));
+    let public = signature::UnparsedPublicKey::new(&signature::ED25519, decode(jwk["x"].as_str().expect("x")));
+    let signed = format!("{}.{}", parts[0], parts[1]);
+    assert!(public.verify(signed.as_bytes(), &decode(parts[2])).is_ok());
+    let _ = std::fs::remove_file(&key);
+}
+
+#[rocket::async_test]
+async fn no_jwks_without_jwts() {
+    let s = common::server().await;
+    let (status, _) = s.call("GET", "/.well-known/jwks.json", None, None).await;
+    assert_eq!(status, Status::NotFound);
+}
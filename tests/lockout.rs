@@ -0,0 +1,119 @@
+// Login throttling, counted without redis.
+mod common;
+
+use std::net::SocketAddr;
+use rocket::http::{ContentType, Header, Status};
+use rocket::serde::json::json;
+
+// Log in over a connection from remote, with the given headers.
+async fn login_from(s: &common::Server, remote: &str, headers: &[(&str, &str)], name: &str) -> Status {
+    let addr: SocketAddr = remote.parse().expect("remote");
+    let body = json!({ "name": name, "secret": "wrong", "scopes": [] });
+    let mut req = s.client.post("/auth").header(ContentType::JSON).remote(addr).body(body.to_string());
+    for (k, v) in headers.iter() {
+        req = req.header(Header::new(k.to_string(), v.to_string()));
+    }
+    req.dispatch().await.status()
+}
+
+#[rocket::async_test]
+async fn bad_passwords_lock_out_the_user() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+
+    for _ in 0..5 {
+        let (status, _) = s.login("alice", "wrong", &[], None).await;
+        assert_eq!(status, Status::Unauthorized);
+    }
+    // even the right password is turned away now
+    let (status, body) = s.login("alice", "alicepassword", &[], None).await;
+    assert_eq!(status, Status::TooManyRequests, "{}", body);
+
+    let (status, body) = s.call("GET", "/admin/lockout/user/alice", Some(&admin), None).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    assert_eq!(body["result"]["failures"], 5);
+    assert!(body["result"]["retry_after"].as_u64().expect("retry_after") > 0);
+    let (_, body) = s.call("GET", "/admin/lockouts", Some(&admin), None).await;
+    assert_eq!(body["result"].as_array().expect("lockouts").len(), 1, "{}", body);
+
+    let (status, _) = s.call("DELETE", "/admin/lockout/user/alice", Some(&admin), None).await;
+    assert_eq!(status, Status::Ok);
+    s.token("alice", "alicepassword", &[]).await;
+}
+
+#[rocket::async_test]
+async fn counts_without_a_cache() {
+    let s = common::server_with(|f| f.merge(("cache_backend", "none"))).await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+
+    for _ in 0..5 {
+        s.login("alice", "wrong", &[], None).await;
+    }
+    let (status, _) = s.login("alice", "alicepassword", &[], None).await;
+    assert_eq!(status, Status::TooManyRequests);
+}
+
+#[rocket::async_test]
+async fn bad_client_secrets_lock_out_only_the_client() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    let secret = s.create_client(&admin, "app", &["reports"], &["client_credentials"]).await;
+
+    for _ in 0..5 {
+        let (status, _) = s.form("/oauth/token", Some(("app", "wrong")), "grant_type=client_credentials").await;
+        assert_eq!(status, Status::Unauthorized);
+    }
+    let (status, _) = s.form("/oauth/token", Some(("app", &secret)), "grant_type=client_credentials").await;
+    assert_eq!(status, Status::TooManyRequests);
+
+    let (_, body) = s.call("GET", "/admin/lockout/client/app", Some(&admin), None).await;
+    assert_eq!(body["result"]["failures"], 5, "{}", body);
+    let (_, body) = s.call("GET", "/admin/lockout/user/app", Some(&admin), None).await;
+    assert_eq!(body["result"]["failures"], 0, "{}", body);
+}
+
+#[rocket::async_test]
+async fn forged_addresses_dont_dodge_the_lockout() {
+    let s = common::server_with(|f| f.merge(("throttle.ip_failures", 3))).await;
+
+    // a new name and a new X-Real-IP each time, but always the same socket
+    for i in 0..3 {
+        let fake = format!("192.0.2.{}", i);
+        let status = login_from(&s, "198.51.100.7:4000", &[("X-Real-IP", &fake)], &format!("nobody{}", i)).await;
+        assert_eq!(status, Status::Unauthorized);
+    }
+    let status = login_from(&s, "198.51.100.7:4000", &[("X-Real-IP", "192.0.2.99")], "nobody99").await;
+    assert_eq!(status, Status::TooManyRequests);
+
+    // nor can a client get someone else's address locked out
+    let admin = s.admin_token().await;
+    let (_, body) = s.call("GET", "/admin/lockout/ip/192.0.2.0", Some(&admin), None).await;
+    assert_eq!(body["result"]["failures"], 0, "{}", body);
+    let (_, body) = s.call("GET", "/admin/lockout/ip/198.51.100.7", Some(&admin), None).await;
+    assert_eq!(body["result"]["failures"], 3, "{}", body);
+}
+
+#[rocket::async_test]
+async fn trusted_proxies_name_the_client() {
+    let s = common::server_with(|f| f
+        .merge(("throttle.ip_failures", 3))
+        .merge(("trusted_proxies", ["10.0.0.1", "10.0.0.2"]))).await;
+
+    // the client is the last hop that isn't a trusted proxy, whatever it claims before that
+    for i in 0..3 {
+        let hops = format!("192.0.2.{}, 198.51.100.7, 10.0.0.2", i);
+        let status = login_from(&s, "10.0.0.1:4000", &[("X-Forwarded-For", &hops)], &format!("nobody{}", i)).await;
+        assert_eq!(status, Status::Unauthorized);
+    }
+    let status = login_from(&s, "10.0.0.1:4000", &[("X-Forwarded-For", "198.51.100.7, 10.0.0.2")], "nobody99").await;
+    assert_eq!(status, Status::TooManyRequests);
+
+    // other clients of the proxy are still let in, and X-Real-IP is believed from it too
+    let status = login_from(&s, "10.0.0.1:4000", &[("X-Forwarded-For", "198.51.100.8")], "nobody99").await;
+    assert_eq!(status, Status::Unauthorized);
+    let status = login_from(&s, "10.0.0.1:4000", &[("X-Real-IP", "198.51.100.7")], "nobody99").await;
+    assert_eq!(status, Status::TooManyRequests);
+}
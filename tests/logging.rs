@@ -0,0 +1,34 @@
+// Request ids.
+mod common;
+
+use rocket::http::Header;
+
+async fn request_id(s: &common::Server, id: Option<&str>) -> String {
+    let mut req = s.client.get("/healthz");
+    if let Some(id) = id {
+        req = req.header(Header::new("X-Request-Id", id.to_owned()));
+    }
+    let resp = req.dispatch().await;
+    resp.headers().get_one("X-Request-Id").expect("X-Request-Id").to_owned()
+}
+
+#[rocket::async_test]
+async fn echoes_the_request_id() {
+    let s = common::bare_server(|f| f).await;
+    assert_eq!(request_id(&s, Some("trace-1234.abc_d")).await, "trace-1234.abc_d");
+}
+
+#[rocket::async_test]
+async fn makes_up_an_id_when_there_is_no_good_one() {
+    let s = common::bare_server(|f| f).await;
+    let made = request_id(&s, None).await;
+    assert_eq!(made.len(), 16, "{}", made);
+    assert!(made.bytes().all(|b| b.is_ascii_hexdigit()), "{}", made);
+    assert_ne!(request_id(&s, None).await, made);
+
+    for bad in &["has spaces", "<script>", &"a".repeat(65)] {
+        let id = request_id(&s, Some(bad)).await;
+        assert_ne!(&id, bad);
+        assert_eq!(id.len(), 16, "{}", id);
+    }
+}
@@ -0,0 +1,29 @@
+// Logging in, and what a failed login gives away.
+mod common;
+
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+async fn hashes(s: &common::Server, admin: &str) -> u64 {
+    let (status, body) = s.call("GET", "/admin/hashstats", Some(admin), None).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    body["result"]["hashes"].as_u64().expect("hashes")
+}
+
+#[rocket::async_test]
+async fn every_login_checks_a_password() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    let (status, _) = s.call("PATCH", "/admin/user/alice", Some(&admin), Some(json!({ "enabled": false }))).await;
+    assert_eq!(status, Status::Ok);
+
+    // an unknown name and a disabled account cost as much as a wrong password, and look the same
+    for name in ["nobody", "alice"].iter() {
+        let before = hashes(&s, &admin).await;
+        let (status, body) = s.login(name, "alicepassword", &[], None).await;
+        assert_eq!(status, Status::Unauthorized);
+        assert_eq!(body["code"], "badauth");
+        assert_eq!(hashes(&s, &admin).await, before + 1, "login as {}", name);
+    }
+}
@@ -0,0 +1,95 @@
+// The admin operations, as authsrv-admin calls them.
+mod common;
+
+use std::time::{Duration, SystemTime};
+use rocket::http::Status;
+
+use authsrv::Error;
+use authsrv::manage;
+use authsrv::model::{scopes, token, user, hash_secret};
+use authsrv::rocktypes::CachedDb;
+
+#[rocket::async_test]
+async fn keeps_the_same_rules_as_the_api() {
+    let db = common::db_path();
+    let rocket = authsrv::custom(common::figment(&db)).ignite().await.expect("ignite");
+    let cdb = CachedDb::offline(&rocket);
+    authsrv::bootstrap::admin(&cdb, common::ADMIN, common::ADMIN_PASSWORD).await.expect("admin");
+
+    manage::create_scope(&cdb, None, "reports").await.expect("scope");
+    let nu = |scope: &str| manage::NewUser {
+        name: "alice",
+        secret: "alicepassword",
+        life: 3600,
+        scopes: vec![scope.to_owned()],
+        require_mfa: false,
+        mfa_enroll: false,
+    };
+    assert!(matches!(manage::create_user(&cdb, None, nu("billing")).await, Err(Error::BadScopes(_))));
+    manage::create_user(&cdb, None, nu("reports")).await.expect("user");
+
+    assert!(matches!(manage::delete_scope(&cdb, None, "authadmin").await, Err(Error::BadScopes(_))));
+    let retire = scopes::ScopeChanges { description: None, retired: Some(true), require_mfa: None };
+    assert!(matches!(manage::update_scope(&cdb, None, "authadmin", retire).await, Err(Error::BadScopes(_))));
+    manage::delete_scope(&cdb, None, "reports").await.expect("delete");
+    assert!(matches!(manage::delete_scope(&cdb, None, "reports").await, Err(Error::NotFound)));
+    assert!(user::get_user(&cdb, "alice".to_owned()).await.expect("alice").scopes.is_empty());
+
+    let ch = manage::UserChanges { enabled: Some(false), ..Default::default() };
+    manage::update_user(&cdb, None, "alice", ch).await.expect("disable");
+    assert!(!user::get_user(&cdb, "alice".to_owned()).await.expect("alice").enabled);
+    assert!(matches!(manage::revoke_token(&cdb, None, "nonsense".to_owned()).await, Err(Error::NotFound)));
+    drop(cdb);
+    let _ = std::fs::remove_file(&db);
+}
+
+// A token for name, as if it had logged in, and the bearer string for it.
+async fn issue(cdb: &CachedDb<'_>, name: &str, bearer: &str) -> String {
+    let tok = token::Token {
+        token: hash_secret(bearer),
+        username: name.to_owned(),
+        expiration: SystemTime::now() + Duration::from_secs(3600),
+        scopes: vec![],
+        issued: SystemTime::now(),
+        client_id: None,
+    };
+    token::put_token(cdb, &tok).await.expect("token");
+    bearer.to_owned()
+}
+
+#[rocket::async_test]
+async fn revokes_and_deletes_users() {
+    let db = common::db_path();
+    let rocket = authsrv::custom(common::figment(&db)).ignite().await.expect("ignite");
+    let cdb = CachedDb::offline(&rocket);
+    let alice = manage::NewUser { name: "alice", secret: "alicepassword", life: 3600, scopes: vec![], require_mfa: false, mfa_enroll: false };
+    manage::create_user(&cdb, None, alice).await.expect("alice");
+
+    // as authsrv-admin token revoke-user does, leaving alice able to log in again
+    let bearer = issue(&cdb, "alice", "0123456789abcdef").await;
+    assert_eq!(manage::revoke_user_tokens(&cdb, None, "alice").await.expect("revoke"), 1);
+    assert!(matches!(token::lookup_token(&cdb, bearer).await, Err(Error::NotFound)));
+    assert!(user::get_user(&cdb, "alice".to_owned()).await.expect("alice").enabled);
+
+    let bearer = issue(&cdb, "alice", "fedcba9876543210").await;
+    manage::delete_user(&cdb, None, "alice").await.expect("delete");
+    assert!(matches!(token::lookup_token(&cdb, bearer).await, Err(Error::NotFound)));
+    assert!(matches!(manage::delete_user(&cdb, None, "alice").await, Err(Error::NotFound)));
+    drop(cdb);
+    let _ = std::fs::remove_file(&db);
+}
+
+#[rocket::async_test]
+async fn the_api_deletes_users_the_same_way() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    let tok = s.token("alice", "alicepassword", &[]).await;
+
+    let (status, _) = s.call("DELETE", "/admin/user/alice", Some(&admin), None).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Unauthorized);
+    let (status, _) = s.call("DELETE", "/admin/user/alice", Some(&admin), None).await;
+    assert_eq!(status, Status::NotFound);
+}
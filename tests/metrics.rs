@@ -0,0 +1,39 @@
+// What /metrics reports.
+mod common;
+
+use rocket::http::{ContentType, Status};
+
+async fn scrape(s: &common::Server) -> String {
+    let resp = s.client.get("/metrics").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(resp.content_type(), Some(ContentType::Plain));
+    resp.into_string().await.expect("metrics")
+}
+
+// The value of a series, name including any labels, or 0 if it isn't there yet.
+fn value(metrics: &str, name: &str) -> f64 {
+    metrics.lines()
+        .find_map(|l| l.strip_prefix(name).and_then(|v| v.strip_prefix(' ')))
+        .map(|v| v.parse().expect("value"))
+        .unwrap_or(0.0)
+}
+
+#[rocket::async_test]
+async fn counts_logins_and_tokens() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+
+    let before = scrape(&s).await;
+    s.token("alice", "alicepassword", &[]).await;
+    s.login("alice", "wrong", &[], None).await;
+    let after = scrape(&s).await;
+
+    let ok = r#"authsrv_logins_total{outcome="ok"}"#;
+    let bad = r#"authsrv_logins_total{outcome="badauth"}"#;
+    assert_eq!(value(&after, ok) - value(&before, ok), 1.0, "{}", after);
+    assert_eq!(value(&after, bad) - value(&before, bad), 1.0, "{}", after);
+    let issued = "authsrv_tokens_issued_total";
+    assert_eq!(value(&after, issued) - value(&before, issued), 1.0, "{}", after);
+    assert!(after.contains("# TYPE authsrv_password_hash_duration_seconds histogram"), "{}", after);
+}
@@ -0,0 +1,74 @@
+// Second factors, and who may enroll one.
+mod common;
+
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+#[rocket::async_test]
+async fn authadmin_needs_a_second_factor() {
+    let s = common::server().await;
+    let (status, body) = s.login(common::ADMIN, common::ADMIN_PASSWORD, &["authadmin"], None).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(body["code"], "mfarequired");
+    s.admin_token().await;
+}
+
+#[rocket::async_test]
+async fn enrolling_needs_an_admin() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    s.create_user(&admin, "alice", "alicepassword", &["reports"]).await;
+
+    // a password alone can't add a second factor
+    let tok = s.token("alice", "alicepassword", &[]).await;
+    let (status, _) = s.call("POST", "/auth/totp/enroll", Some(&tok), None).await;
+    assert_eq!(status, Status::Forbidden);
+
+    let body = json!({ "mfa_enroll": true });
+    let (status, _) = s.call("PATCH", "/admin/user/alice", Some(&admin), Some(body)).await;
+    assert_eq!(status, Status::Ok);
+    let (_, codes) = s.enroll(&tok).await;
+
+    let (_, body) = s.call("GET", "/admin/user/alice", Some(&admin), None).await;
+    assert_eq!(body["result"]["require_mfa"], true);
+    assert_eq!(body["result"]["mfa_enroll"], false);
+
+    // and now every login needs it, even one for no scopes
+    let (status, _) = s.login("alice", "alicepassword", &[], None).await;
+    assert_eq!(status, Status::Unauthorized);
+    let (status, _) = s.login("alice", "alicepassword", &["reports"], None).await;
+    assert_eq!(status, Status::Unauthorized);
+    let (status, _) = s.login("alice", "alicepassword", &["reports"], Some(&codes[0])).await;
+    assert_eq!(status, Status::Ok);
+    // recovery codes only work once
+    let (status, _) = s.login("alice", "alicepassword", &["reports"], Some(&codes[0])).await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn codes_work_once_and_can_be_reset() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    let (status, _) = s.call("PATCH", "/admin/user/alice", Some(&admin), Some(json!({ "mfa_enroll": true }))).await;
+    assert_eq!(status, Status::Ok);
+    let tok = s.token("alice", "alicepassword", &[]).await;
+    let (secret, _) = s.enroll(&tok).await;
+
+    // a code is used up once it logged in, or confirmed the enrollment if the step hasn't changed
+    let code = common::totp(&secret);
+    s.login("alice", "alicepassword", &[], Some(&code)).await;
+    let (status, body) = s.login("alice", "alicepassword", &[], Some(&code)).await;
+    assert_eq!(status, Status::Unauthorized, "{}", body);
+    let (status, _) = s.login("alice", "alicepassword", &[], Some("000000x")).await;
+    assert_eq!(status, Status::Unauthorized);
+
+    // without it alice can enroll again on a password alone
+    let (status, _) = s.call("DELETE", "/admin/user/alice/totp", Some(&admin), None).await;
+    assert_eq!(status, Status::Ok);
+    let tok = s.token("alice", "alicepassword", &[]).await;
+    s.enroll(&tok).await;
+    let (status, _) = s.call("DELETE", "/admin/user/nobody/totp", Some(&admin), None).await;
+    assert_eq!(status, Status::NotFound);
+}
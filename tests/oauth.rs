@@ -0,0 +1,56 @@
+// The RFC 6749 token endpoint, and the errors it answers with.
+mod common;
+
+use rocket::http::{ContentType, Status};
+use rocket::serde::json::{self, Value};
+
+#[rocket::async_test]
+async fn password_grant_issues_a_bearer_token() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    s.create_user(&admin, "alice", "alicepassword", &["reports"]).await;
+
+    let resp = s.client.post("/oauth/token").header(ContentType::Form)
+        .body("grant_type=password&username=alice&password=alicepassword&scope=reports").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(resp.headers().get_one("Cache-Control"), Some("no-store"));
+    assert_eq!(resp.headers().get_one("Pragma"), Some("no-cache"));
+    let body: Value = json::from_str(&resp.into_string().await.expect("body")).expect("json");
+    assert_eq!(body["token_type"], "Bearer");
+    assert_eq!(body["scope"], "reports");
+    assert!(body["expires_in"].as_u64().expect("expires_in") > 0);
+
+    let tok = body["access_token"].as_str().expect("token");
+    let (status, body) = s.call("GET", "/auth", Some(tok), None).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["result"]["username"], "alice");
+}
+
+#[rocket::async_test]
+async fn errors_are_rfc6749_errors() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+
+    let cases = [
+        ("grant_type=password&username=alice&password=wrongpassword", "invalid_grant"),
+        ("grant_type=password&username=nobody&password=alicepassword", "invalid_grant"),
+        ("grant_type=password&username=alice&password=alicepassword&scope=authadmin", "invalid_scope"),
+        ("grant_type=password&username=alice", "invalid_request"),
+        ("username=alice&password=alicepassword", "invalid_request"),
+        ("grant_type=authorization_code&code=abc", "unsupported_grant_type"),
+    ];
+    for (body, error) in cases.iter() {
+        let (status, resp) = s.form("/oauth/token", None, body).await;
+        assert_eq!(status, Status::BadRequest, "{}: {}", body, resp);
+        assert_eq!(resp["error"], *error, "{}", body);
+        assert!(resp["error_description"].is_string());
+    }
+
+    // failed client authentication asks for basic auth
+    let resp = s.client.post("/oauth/token").header(ContentType::Form)
+        .body("grant_type=client_credentials&client_id=app&client_secret=wrong").dispatch().await;
+    assert_eq!(resp.status(), Status::Unauthorized);
+    assert_eq!(resp.headers().get_one("WWW-Authenticate"), Some("Basic realm=\"authsrv\""));
+}
@@ -0,0 +1,37 @@
+// Upgrading password hashes at login.
+mod common;
+
+use authsrv::rocktypes::CachedDb;
+use authsrv::model::user;
+
+#[rocket::async_test]
+async fn login_adds_the_pepper() {
+    let db = common::db_path();
+    let figment = common::figment(&db);
+    {
+        let rocket = authsrv::custom(figment.clone()).ignite().await.expect("ignite");
+        let cdb = CachedDb::offline(&rocket);
+        authsrv::bootstrap::admin(&cdb, "admin", "adminpassword").await.expect("admin");
+        let u = user::get_user(&cdb, "admin".to_owned()).await.expect("admin");
+        assert!(!u.hash.starts_with("$peppered"));
+    }
+
+    let figment = figment.merge(("password.pepper", "pepper"));
+    let rocket = authsrv::custom(figment.clone()).ignite().await.expect("ignite");
+    let client = rocket::local::asynchronous::Client::tracked(rocket).await.expect("client");
+    let body = rocket::serde::json::json!({ "name": "admin", "secret": "adminpassword", "scopes": [] });
+    let resp = client.post("/auth").header(rocket::http::ContentType::JSON).body(body.to_string()).dispatch().await;
+    assert_eq!(resp.status(), rocket::http::Status::Ok);
+    drop(resp);
+    drop(client);
+
+    let rocket = authsrv::custom(figment).ignite().await.expect("ignite");
+    let cdb = CachedDb::offline(&rocket);
+    let u = user::get_user(&cdb, "admin".to_owned()).await.expect("admin");
+    assert!(u.hash.starts_with("$peppered"));
+    // nothing else about the user changed
+    assert!(u.mfa_enroll);
+    assert!(u.scopes.iter().any(|s| s == "authadmin"));
+    drop(cdb);
+    let _ = std::fs::remove_file(&db);
+}
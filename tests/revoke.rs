@@ -0,0 +1,86 @@
+// Revoking tokens, and the refresh tokens that would replace them.
+mod common;
+
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+#[rocket::async_test]
+async fn logout_revokes_the_refresh_token() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    let (status, body) = s.login("alice", "alicepassword", &[], None).await;
+    assert_eq!(status, Status::Ok);
+    let tok = body["result"]["token"].as_str().unwrap().to_owned();
+    let refresh = body["result"]["refresh"].as_str().unwrap().to_owned();
+
+    let (status, _) = s.call("DELETE", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Unauthorized);
+    let (status, _) = s.call("POST", "/auth/refresh", None, Some(json!({ "refresh": refresh }))).await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn admin_revoke_takes_the_whole_family() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    s.create_user(&admin, "alice", "alicepassword", &["reports"]).await;
+    let (_, body) = s.login("alice", "alicepassword", &["reports"], None).await;
+    let first = body["result"]["token"].as_str().unwrap().to_owned();
+    let refresh = body["result"]["refresh"].as_str().unwrap().to_owned();
+
+    // a refreshed token is in the same family as the first
+    let (status, body) = s.call("POST", "/auth/refresh", None, Some(json!({ "refresh": refresh }))).await;
+    assert_eq!(status, Status::Ok);
+    let second = body["result"]["token"].as_str().unwrap().to_owned();
+    let refresh = body["result"]["refresh"].as_str().unwrap().to_owned();
+
+    let (status, _) = s.call("POST", "/admin/token/revoke", Some(&admin), Some(json!(second))).await;
+    assert_eq!(status, Status::Ok);
+    for tok in [&first, &second].iter() {
+        let (status, _) = s.call("GET", "/auth", Some(tok), None).await;
+        assert_eq!(status, Status::Unauthorized);
+    }
+    let (status, _) = s.call("POST", "/auth/refresh", None, Some(json!({ "refresh": refresh }))).await;
+    assert_eq!(status, Status::Unauthorized);
+
+    // there is nothing left to revoke
+    let (status, body) = s.call("POST", "/admin/token/revoke", Some(&admin), Some(json!(second))).await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(body["code"], "notfound");
+    let (status, _) = s.call("POST", "/admin/token/revoke", Some(&admin), Some(json!("nonsense"))).await;
+    assert_eq!(status, Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn refresh_tokens_rotate_and_reuse_takes_the_family() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    s.create_user(&admin, "alice", "alicepassword", &["reports"]).await;
+    let (_, body) = s.login("alice", "alicepassword", &["reports"], None).await;
+    let first = body["result"]["refresh"].as_str().unwrap().to_owned();
+
+    // a refresh may narrow the scopes, but not widen them
+    let narrow = json!({ "refresh": first, "scopes": [] });
+    let (status, body) = s.call("POST", "/auth/refresh", None, Some(narrow)).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    assert_eq!(body["result"]["scopes"], json!([]));
+    let tok = body["result"]["token"].as_str().unwrap().to_owned();
+    let second = body["result"]["refresh"].as_str().unwrap().to_owned();
+    assert_ne!(first, second);
+    let wide = json!({ "refresh": second, "scopes": ["authadmin"] });
+    let (_, body) = s.call("POST", "/auth/refresh", None, Some(wide)).await;
+    assert_eq!(body["code"], "badscopes");
+
+    // someone presenting the first one again means it leaked, so nothing from it is trusted
+    let (status, _) = s.call("POST", "/auth/refresh", None, Some(json!({ "refresh": first }))).await;
+    assert_eq!(status, Status::Unauthorized);
+    let (status, _) = s.call("POST", "/auth/refresh", None, Some(json!({ "refresh": second }))).await;
+    assert_eq!(status, Status::Unauthorized);
+    let (status, _) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Unauthorized);
+}
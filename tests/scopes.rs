@@ -0,0 +1,80 @@
+// The life of a scope: described, retired and deleted.
+mod common;
+
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+
+async fn scope(s: &common::Server, admin: &str, name: &str) -> Value {
+    let (status, body) = s.call("GET", "/admin/scopes", Some(admin), None).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    body["result"].as_array().expect("scopes").iter().find(|sc| sc["name"] == name).cloned().unwrap_or(Value::Null)
+}
+
+#[rocket::async_test]
+async fn describes_and_retires_scopes() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    s.create_user(&admin, "alice", "alicepassword", &["reports"]).await;
+
+    let sc = scope(&s, &admin, "reports").await;
+    assert_eq!(sc["retired"], false);
+    assert!(sc["created"].as_u64().expect("created") > 0);
+
+    let change = json!({ "description": "read the reports" });
+    let (status, body) = s.call("PATCH", "/admin/scope/reports", Some(&admin), Some(change)).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    assert_eq!(scope(&s, &admin, "reports").await["description"], "read the reports");
+
+    // a retired scope is still listed, but can't be asked for or handed out
+    let (status, _) = s.call("PATCH", "/admin/scope/reports", Some(&admin), Some(json!({ "retired": true }))).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(scope(&s, &admin, "reports").await["retired"], true);
+    let (_, body) = s.login("alice", "alicepassword", &["reports"], None).await;
+    assert_eq!(body["code"], "badscopes");
+    let bob = json!({ "name": "bob", "secret": "bobpassword", "life": 3600, "scopes": ["reports"] });
+    let (_, body) = s.call("POST", "/admin/user", Some(&admin), Some(bob)).await;
+    assert_eq!(body["code"], "badscopes");
+
+    let (status, _) = s.call("PATCH", "/admin/scope/reports", Some(&admin), Some(json!({ "retired": false }))).await;
+    assert_eq!(status, Status::Ok);
+    s.token("alice", "alicepassword", &["reports"]).await;
+
+    let (status, _) = s.call("PATCH", "/admin/scope/reports", Some(&admin), Some(json!({}))).await;
+    assert_eq!(status, Status::BadRequest);
+    let (status, _) = s.call("PATCH", "/admin/scope/nosuchscope", Some(&admin), Some(json!({ "retired": true }))).await;
+    assert_eq!(status, Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn deleting_takes_the_scope_away() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    s.create_user(&admin, "alice", "alicepassword", &["reports"]).await;
+    let tok = s.token("alice", "alicepassword", &["reports"]).await;
+
+    let (status, _) = s.call("DELETE", "/admin/scope/reports", Some(&admin), None).await;
+    assert_eq!(status, Status::Ok);
+    assert!(scope(&s, &admin, "reports").await.is_null());
+    let (_, body) = s.call("GET", "/admin/user/alice", Some(&admin), None).await;
+    assert_eq!(body["result"]["scopes"], json!([]));
+    // tokens keep working, just without it
+    let (status, body) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["result"]["scopes"], json!([]));
+
+    let (status, _) = s.call("DELETE", "/admin/scope/reports", Some(&admin), None).await;
+    assert_eq!(status, Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn authadmin_stays() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    let (_, body) = s.call("PATCH", "/admin/scope/authadmin", Some(&admin), Some(json!({ "retired": true }))).await;
+    assert_eq!(body["code"], "badscopes");
+    let (_, body) = s.call("DELETE", "/admin/scope/authadmin", Some(&admin), None).await;
+    assert_eq!(body["code"], "badscopes");
+    assert_eq!(scope(&s, &admin, "authadmin").await["retired"], false);
+}
@@ -0,0 +1,56 @@
+// The sqlite backend, end to end.
+mod common;
+
+use rocket::http::Status;
+
+#[rocket::async_test]
+async fn login_and_check() {
+    let s = common::server().await;
+    let tok = s.admin_token().await;
+    let (status, body) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["result"]["username"], "admin");
+    assert_eq!(body["result"]["scopes"][0], "authadmin");
+
+    let (status, _) = s.login(common::ADMIN, "wrong", &["authadmin"], None).await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn missing_scope_is_forbidden() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    let tok = s.token("alice", "alicepassword", &[]).await;
+
+    let (status, body) = s.call("GET", "/admin/lockouts", Some(&tok), None).await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(body["code"], "forbidden");
+    let (status, _) = s.call("GET", "/admin/user/admin", Some(&tok), None).await;
+    assert_eq!(status, Status::Forbidden);
+    // their own is fine
+    let (status, _) = s.call("GET", "/admin/user/alice", Some(&tok), None).await;
+    assert_eq!(status, Status::Ok);
+    // a bad token is still a bad token
+    let (status, body) = s.call("GET", "/admin/lockouts", Some("nonsense"), None).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(body["code"], "badauth");
+}
+
+#[rocket::async_test]
+async fn delete_scope_removes_it_everywhere() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    s.create_scope(&admin, "billing").await;
+    s.create_user(&admin, "alice", "alicepassword", &["reports", "billing"]).await;
+    let tok = s.token("alice", "alicepassword", &["reports", "billing"]).await;
+
+    let (status, _) = s.call("DELETE", "/admin/scope/reports", Some(&admin), None).await;
+    assert_eq!(status, Status::Ok);
+
+    let (_, body) = s.call("GET", "/admin/user/alice", Some(&admin), None).await;
+    assert_eq!(body["result"]["scopes"], rocket::serde::json::json!(["billing"]));
+    let (_, body) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(body["result"]["scopes"], rocket::serde::json::json!(["billing"]));
+}
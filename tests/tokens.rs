@@ -0,0 +1,37 @@
+// How tokens are kept at rest.
+mod common;
+
+use rocket::local::asynchronous::Client;
+
+use authsrv::Error;
+use authsrv::manage::{self, NewUser};
+use authsrv::model::{hash_secret, refresh, token};
+use authsrv::rocktypes::CachedDb;
+
+#[rocket::async_test]
+async fn only_digests_are_stored() {
+    let db = common::db_path();
+    let rocket = authsrv::custom(common::figment(&db)).ignite().await.expect("ignite");
+    {
+        let cdb = CachedDb::offline(&rocket);
+        let alice = NewUser { name: "alice", secret: "alicepassword", life: 3600, scopes: vec![], require_mfa: false, mfa_enroll: false };
+        manage::create_user(&cdb, None, alice).await.expect("alice");
+    }
+    let s = common::Server::new(Client::tracked(rocket).await.expect("client"), db.clone());
+    let (_, body) = s.login("alice", "alicepassword", &[], None).await;
+    let bearer = body["result"]["token"].as_str().expect("token").to_owned();
+    let rt = body["result"]["refresh"].as_str().expect("refresh").to_owned();
+
+    // another node on the same db, so nothing comes out of the first one's cache
+    let other = authsrv::custom(common::figment(&db)).ignite().await.expect("ignite");
+    let cdb = CachedDb::offline(&other);
+    let tok = token::get_token(&cdb, hash_secret(&bearer)).await.expect("stored");
+    assert_eq!(tok.username, "alice");
+    assert!(matches!(token::get_token(&cdb, bearer.clone()).await, Err(Error::NotFound)));
+    assert_eq!(refresh::get_refresh(&cdb, hash_secret(&rt)).await.expect("stored").access, tok.token);
+    assert!(matches!(refresh::get_refresh(&cdb, rt).await, Err(Error::NotFound)));
+
+    // and what is stored can't be used as a token
+    assert!(matches!(token::lookup_token(&cdb, tok.token.clone()).await, Err(Error::NotFound)));
+    assert!(token::lookup_token(&cdb, bearer).await.is_ok());
+}
@@ -0,0 +1,94 @@
+// Managing users through the admin api.
+mod common;
+
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+
+fn names(body: &Value) -> Vec<&str> {
+    body["result"].as_array().expect("users").iter().map(|u| u["name"].as_str().expect("name")).collect()
+}
+
+#[rocket::async_test]
+async fn users_can_read_only_themselves() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    s.create_user(&admin, "bob", "bobpassword", &[]).await;
+    let alice = s.token("alice", "alicepassword", &[]).await;
+
+    let (status, body) = s.call("GET", "/admin/user/alice", Some(&alice), None).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    assert_eq!(body["result"]["name"], "alice");
+    assert_eq!(body["result"]["enabled"], true);
+    assert!(body["result"].get("hash").is_none(), "{}", body);
+
+    let (status, _) = s.call("GET", "/admin/user/bob", Some(&alice), None).await;
+    assert_eq!(status, Status::Forbidden);
+    let (status, _) = s.call("GET", "/admin/users", Some(&alice), None).await;
+    assert_eq!(status, Status::Forbidden);
+    let (status, _) = s.call("GET", "/admin/user/nobody", Some(&admin), None).await;
+    assert_eq!(status, Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn lists_a_page_at_a_time() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    for name in ["alice", "bob", "carol"].iter() {
+        s.create_user(&admin, name, "somepassword", &[]).await;
+    }
+
+    let (status, body) = s.call("GET", "/admin/users", Some(&admin), None).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    assert_eq!(names(&body), vec!["admin", "alice", "bob", "carol"]);
+    let (_, body) = s.call("GET", "/admin/users?offset=1&limit=2", Some(&admin), None).await;
+    assert_eq!(names(&body), vec!["alice", "bob"]);
+    let (_, body) = s.call("GET", "/admin/users?offset=10", Some(&admin), None).await;
+    assert!(names(&body).is_empty());
+}
+
+#[rocket::async_test]
+async fn updates_only_what_is_given() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_scope(&admin, "reports").await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+
+    let (status, body) = s.call("PATCH", "/admin/user/alice", Some(&admin), Some(json!({ "scopes": ["reports"] }))).await;
+    assert_eq!(status, Status::Ok, "{}", body);
+    let (_, body) = s.call("GET", "/admin/user/alice", Some(&admin), None).await;
+    assert_eq!(body["result"]["scopes"], json!(["reports"]));
+    assert_eq!(body["result"]["enabled"], true);
+    s.token("alice", "alicepassword", &["reports"]).await;
+
+    let (_, body) = s.call("PATCH", "/admin/user/alice", Some(&admin), Some(json!({ "scopes": ["nosuchscope"] }))).await;
+    assert_eq!(body["code"], "badscopes");
+    let (status, _) = s.call("PATCH", "/admin/user/nobody", Some(&admin), Some(json!({ "enabled": false }))).await;
+    assert_eq!(status, Status::NotFound);
+
+    // a new password takes over from the old one
+    let (status, _) = s.call("PATCH", "/admin/user/alice", Some(&admin), Some(json!({ "secret": "newpassword" }))).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = s.login("alice", "alicepassword", &[], None).await;
+    assert_eq!(status, Status::Unauthorized);
+    s.token("alice", "newpassword", &[]).await;
+}
+
+#[rocket::async_test]
+async fn disabling_revokes_tokens() {
+    let s = common::server().await;
+    let admin = s.admin_token().await;
+    s.create_user(&admin, "alice", "alicepassword", &[]).await;
+    let tok = s.token("alice", "alicepassword", &[]).await;
+
+    let (status, _) = s.call("PATCH", "/admin/user/alice", Some(&admin), Some(json!({ "enabled": false }))).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = s.call("GET", "/auth", Some(&tok), None).await;
+    assert_eq!(status, Status::Unauthorized);
+    let (status, _) = s.login("alice", "alicepassword", &[], None).await;
+    assert_eq!(status, Status::Unauthorized);
+
+    let (status, _) = s.call("PATCH", "/admin/user/alice", Some(&admin), Some(json!({ "enabled": true }))).await;
+    assert_eq!(status, Status::Ok);
+    s.token("alice", "alicepassword", &[]).await;
+}